@@ -1,4 +1,7 @@
 pub mod driver;
+pub mod filter;
 pub mod menus;
+pub mod preview;
+pub mod tree;
 pub mod utils;
 pub mod config;
@@ -6,6 +6,9 @@ use anyhow::Result;
 #[derive(Serialize, Deserialize, Default)]
 pub struct Config {
     pub default_preset: Option<String>,
+    /// tmux server socket to use when not running inside tmux.
+    /// Treated as a path if it contains a `/`, and as a socket name otherwise.
+    pub socket: Option<String>,
 }
 
 fn get_config_path() -> Result<PathBuf> {
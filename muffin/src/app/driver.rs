@@ -1,4 +1,5 @@
 use std::collections::BTreeMap;
+use std::time::Duration;
 
 use futures::{FutureExt, StreamExt};
 use tokio::sync::mpsc;
@@ -7,21 +8,25 @@ use tokio::task::JoinHandle;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use ratatui::DefaultTerminal;
 
-use tmux::{self, Preset, Session};
+use tmux::{Preset, Session, TmuxBackend};
 
 use crate::app::menus::Menu;
 use crate::app::menus::create::CreateMenu;
 use crate::app::menus::delete::DeleteMenu;
+use crate::app::menus::params::ParamsMenu;
 use crate::app::menus::presets::PresetsMenu;
 use crate::app::menus::rename::RenameMenu;
 use crate::app::menus::sessions::SessionsMenu;
 use crate::app::menus::set_default_preset::SetDefaultPresetMenu;
+use crate::app::tree::TreeItem;
 
 #[derive(Debug, Clone, Default)]
 pub enum Mode {
     #[default]
     Sessions,
     Presets,
+    /// Asking for the parameters of the selected preset
+    Params,
     Create,
     Rename,
     Delete,
@@ -34,10 +39,17 @@ pub struct App {
 
 pub struct AppState {
     pub event_handler: EventHandler,
+    pub tmux: Box<dyn TmuxBackend>,
     pub sessions: Vec<Session>,
     pub presets: BTreeMap<String, Preset>,
     pub presets_path: String,
+    /// Why the presets file couldn't be loaded, if it couldn't
+    pub presets_error: Option<String>,
+    /// Why the last preset couldn't be spawned, which stays up until it's dismissed
+    pub spawn_error: Option<String>,
     pub selected_session: Option<usize>,
+    /// Window or pane picked in the sessions tree, which belongs to `selected_session`
+    pub selected_item: Option<TreeItem>,
     pub selected_preset: Option<usize>,
     pub exit: bool,
     pub mode: Mode,
@@ -48,10 +60,15 @@ pub enum AppEvent {
     Error,
     Key(KeyEvent),
     Redraw,
+    /// Sent every [`TICK_RATE`], so that what tmux shows can be picked up without a key press
+    Tick,
     ShowNotification(String),
     ClearNotification,
 }
 
+/// How often sessions and the preview are read from tmux again while nothing is pressed
+const TICK_RATE: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
 pub struct EventHandler {
     pub tx: mpsc::UnboundedSender<AppEvent>,
@@ -66,15 +83,15 @@ impl EventHandler {
 
         let task = tokio::spawn(async move {
             let mut reader = crossterm::event::EventStream::new();
+            let mut tick = tokio::time::interval(TICK_RATE);
             loop {
                 let crossterm_event = reader.next().fuse();
                 tokio::select! {
                     Some(Ok(evt)) = crossterm_event => {
                         match evt {
-                            crossterm::event::Event::Key(key) => {
-                                if key.kind == crossterm::event::KeyEventKind::Press {
-                                    tx.send(AppEvent::Key(key)).unwrap();
-                                }
+                            crossterm::event::Event::Key(key)
+                                if key.kind == crossterm::event::KeyEventKind::Press => {
+                                tx.send(AppEvent::Key(key)).unwrap();
                             },
                             crossterm::event::Event::Resize(_, _) | crossterm::event::Event::FocusGained => {
                                 tx.send(AppEvent::Redraw).unwrap();
@@ -82,6 +99,9 @@ impl EventHandler {
                             _ => {},
                         }
                     },
+                    _ = tick.tick() => {
+                        tx.send(AppEvent::Tick).unwrap();
+                    },
                 }
             }
         });
@@ -93,25 +113,88 @@ impl EventHandler {
         }
     }
 
+    /// Event handler that never reads from the terminal, for driving menus in tests
+    #[cfg(test)]
+    pub fn detached() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self { tx, rx, _task: None }
+    }
+
     pub async fn next(&mut self) -> Result<AppEvent, ()> {
         self.rx.recv().await.ok_or(())
     }
 }
 
+impl AppState {
+    /// Re-reads sessions from tmux and updates which presets are running
+    pub fn refresh(&mut self) -> Result<(), tmux::Error> {
+        self.sessions = match self.tmux.list_sessions() {
+            Ok(sessions) => sessions,
+            // The server exits along with its last session
+            Err(tmux::Error::NoServer) => vec![],
+            Err(e) => return Err(e),
+        };
+
+        // TODO: This hurts the time complexity part of my brain. Fix it?
+        for preset in self.presets.values_mut() {
+            preset.running = false;
+        }
+
+        // Required to update which presets are running and which are dead
+        // Fortunately, this uses a BTreeMap now so it's not as bad as a regular Vec<Preset>
+        for session in self.sessions.iter() {
+            if let Some(v) = self.presets.get_mut(session.preset_name()) {
+                v.running = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// State backed by a fake tmux server, for driving menus in tests
+    #[cfg(test)]
+    pub fn with_fake(tmux: tmux::fake::FakeBackend, presets: BTreeMap<String, Preset>) -> Self {
+        let mut state = Self {
+            event_handler: EventHandler::detached(),
+            tmux: Box::new(tmux),
+            sessions: vec![],
+            presets,
+            presets_path: String::new(),
+            presets_error: None,
+            spawn_error: None,
+            selected_session: None,
+            selected_item: None,
+            selected_preset: None,
+            exit: false,
+            mode: Mode::Sessions,
+        };
+        let _ = state.refresh();
+        state
+    }
+}
+
 impl App {
     pub fn new(
+        tmux: Box<dyn TmuxBackend>,
         sessions: Vec<Session>,
-        presets: BTreeMap<String, Preset>,
+        presets: Result<BTreeMap<String, Preset>, String>,
         presets_file: String,
     ) -> Self {
+        let (presets, presets_error) = match presets {
+            Ok(presets) => (presets, None),
+            Err(e) => (BTreeMap::new(), Some(e)),
+        };
         Self {
             state: AppState {
                 mode: Mode::Sessions,
                 exit: false,
+                tmux,
                 sessions,
                 selected_session: None,
+                selected_item: None,
                 presets,
                 presets_path: presets_file,
+                presets_error,
+                spawn_error: None,
                 selected_preset: None,
                 event_handler: EventHandler::new(),
             },
@@ -120,7 +203,7 @@ impl App {
 
     /// runs the application's main loop until the user quits
     pub async fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), String> {
-        let active_index = self.state.sessions.iter().position(|s| s.active);
+        let active_index = self.state.sessions.iter().position(|s| s.is_attached());
         self.state.selected_session = active_index;
         self.state.selected_preset = if self.state.presets.is_empty() {
             None
@@ -132,7 +215,10 @@ impl App {
         let mut rename_menu = RenameMenu::default();
         let mut delete_menu = DeleteMenu::default();
         let mut sessions_menu = SessionsMenu::new(active_index);
+        sessions_menu.refresh(&mut self.state);
         let mut presets_menu = PresetsMenu::new(active_index);
+        presets_menu.refresh(&mut self.state);
+        let mut params_menu = ParamsMenu::default();
         let mut set_default_preset_menu = SetDefaultPresetMenu::default();
 
         while !self.state.exit {
@@ -158,6 +244,10 @@ impl App {
                         Mode::Presets => {
                             frame.render_stateful_widget(&mut presets_menu, area, &mut self.state)
                         }
+                        Mode::Params => {
+                            frame.render_stateful_widget(&mut presets_menu, area, &mut self.state);
+                            frame.render_stateful_widget(&mut params_menu, area, &mut self.state)
+                        }
                         Mode::SetDefaultPreset => frame.render_stateful_widget(
                             &mut set_default_preset_menu,
                             area,
@@ -182,6 +272,14 @@ impl App {
                 self.state.exit = true;
             }
 
+            // Ticks leave sessions alone while asking about one of them, so the answer can't
+            // end up going to another
+            let refresh = match event {
+                AppEvent::Key(_) => true,
+                AppEvent::Tick => !matches!(self.state.mode, Mode::Delete | Mode::Rename),
+                _ => false,
+            };
+
             // Handle said event
             // TODO: This looks stupid
             match self.state.mode {
@@ -190,28 +288,51 @@ impl App {
                 Mode::Rename => rename_menu.handle_event(event, &mut self.state),
                 Mode::Delete => delete_menu.handle_event(event, &mut self.state),
                 Mode::Presets => presets_menu.handle_event(event, &mut self.state),
+                Mode::Params => params_menu.handle_event(event, &mut self.state),
                 Mode::SetDefaultPreset => {
                     set_default_preset_menu.handle_event(event, &mut self.state)
                 }
             }
 
-            // Refresh tmux sessions on each keystroke
-            self.state.sessions = tmux::list_sessions()?;
-
-            // TODO: This hurts the time complexity part of my brain. Fix it?
-            for preset in self.state.presets.values_mut() {
-                preset.running = false;
-            }
-
-            // Required to update which presets are running and which are dead
-            // Fortunately, this uses a BTreeMap now so it's not as bad as a regular Vec<Preset>
-            for session in self.state.sessions.iter() {
-                if let Some(v) = self.state.presets.get_mut(&session.name) {
-                    v.running = true;
-                }
+            // Refresh tmux sessions on each keystroke, and every so often in case they changed
+            // outside of muffin
+            if refresh {
+                self.state.refresh().map_err(|e| e.to_string())?;
+                sessions_menu.refresh(&mut self.state);
+                presets_menu.refresh(&mut self.state);
             }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tmux::fake::{self, FakeBackend, pane, window};
+
+    fn preset(name: &str) -> Preset {
+        fake::preset(name, vec![window("main", pane("/tmp", None, 1))])
+    }
+
+    #[test]
+    fn test_refresh_marks_running_presets() {
+        let tmux = FakeBackend::new();
+        tmux.create_session("work", None, &[]).unwrap();
+
+        let presets = BTreeMap::from([
+            ("home".to_string(), preset("home")),
+            ("work".to_string(), preset("work")),
+        ]);
+        let mut state = AppState::with_fake(tmux.clone(), presets);
+        assert!(state.presets["work"].running);
+        assert!(!state.presets["home"].running);
+
+        tmux.kill_session("work").unwrap();
+        tmux.create_session("home", None, &[]).unwrap();
+        state.refresh().unwrap();
+        assert!(!state.presets["work"].running);
+        assert!(state.presets["home"].running);
+    }
+}
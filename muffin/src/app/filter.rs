@@ -0,0 +1,266 @@
+use crossterm::event::KeyCode;
+use ratatui::{
+    style::{Style, Stylize},
+    text::{Line, Span},
+};
+
+/// How well a query matched some text
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Match {
+    pub score: i64,
+    /// Indices of the matched characters
+    pub positions: Vec<usize>,
+}
+
+/// Matches `query` against `text` as a subsequence, the way fzf does: the earliest place it
+/// can end, started as late as possible. Case only matters if the query has capitals in it.
+pub fn fuzzy_match(query: &str, text: &str) -> Option<Match> {
+    let smart_case = query.chars().any(char::is_uppercase);
+    let fold = |c: char| {
+        if smart_case {
+            c
+        } else {
+            c.to_lowercase().next().unwrap_or(c)
+        }
+    };
+    let query: Vec<char> = query.chars().map(fold).collect();
+    let chars: Vec<char> = text.chars().collect();
+    if query.is_empty() {
+        return Some(Match::default());
+    }
+
+    // Find where the first full match ends...
+    let mut matched = 0;
+    let end = chars.iter().position(|&c| {
+        if fold(c) == query[matched] {
+            matched += 1;
+        }
+        matched == query.len()
+    })?;
+
+    // ...and walk back from there, which tightens it as much as possible
+    let mut positions = Vec::with_capacity(query.len());
+    for i in (0..=end).rev() {
+        if fold(chars[i]) == query[query.len() - positions.len() - 1] {
+            positions.push(i);
+            if positions.len() == query.len() {
+                break;
+            }
+        }
+    }
+    positions.reverse();
+
+    Some(Match {
+        score: score(&chars, &positions),
+        positions,
+    })
+}
+
+/// Rewards runs of characters and ones starting words, and takes off a bit for every
+/// character skipped over
+fn score(chars: &[char], positions: &[usize]) -> i64 {
+    let mut score = 0;
+    for (n, &i) in positions.iter().enumerate() {
+        score += 16;
+        if n > 0 && positions[n - 1] + 1 == i {
+            score += 8;
+        }
+        let starts_word = i == 0
+            || matches!(chars[i - 1], ' ' | '-' | '_' | '/' | '.' | ':')
+            || (chars[i - 1].is_lowercase() && chars[i].is_uppercase());
+        if starts_word {
+            score += 8;
+        }
+    }
+
+    let (first, last) = (positions[0], positions[positions.len() - 1]);
+    let gaps = last - first + 1 - positions.len();
+    score - gaps as i64 - first.min(8) as i64
+}
+
+/// What a key did to the filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterEvent {
+    /// The key wasn't meant for the filter
+    Ignored,
+    Handled,
+    /// The query changed, so whatever was filtered with it should be again
+    Changed,
+}
+
+/// Query typed after pressing `/` in a list
+#[derive(Debug, Default)]
+pub struct Filter {
+    query: String,
+    typing: bool,
+}
+
+impl Filter {
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// `/` starts typing, after which every character goes to the query until enter or esc
+    /// is pressed. Esc clears the query. Any other character that isn't one of the menu's
+    /// `bound` keys starts typing too, as the first character of the query.
+    pub fn handle_key(&mut self, code: KeyCode, bound: &str) -> FilterEvent {
+        match (self.typing, code) {
+            (false, KeyCode::Char('/')) => {
+                self.typing = true;
+                FilterEvent::Handled
+            }
+            (false, KeyCode::Char(c)) if !bound.contains(c) && !c.is_whitespace() => {
+                self.typing = true;
+                self.query = c.to_string();
+                FilterEvent::Changed
+            }
+            (false, KeyCode::Esc) if !self.query.is_empty() => {
+                self.query.clear();
+                FilterEvent::Changed
+            }
+            (true, KeyCode::Char(c)) => {
+                self.query.push(c);
+                FilterEvent::Changed
+            }
+            (true, KeyCode::Backspace) => {
+                if self.query.pop().is_some() {
+                    FilterEvent::Changed
+                } else {
+                    self.typing = false;
+                    FilterEvent::Handled
+                }
+            }
+            (true, KeyCode::Enter) => {
+                self.typing = false;
+                FilterEvent::Handled
+            }
+            (true, KeyCode::Esc) => {
+                self.typing = false;
+                if self.query.is_empty() {
+                    FilterEvent::Handled
+                } else {
+                    self.query.clear();
+                    FilterEvent::Changed
+                }
+            }
+            _ => FilterEvent::Ignored,
+        }
+    }
+
+    /// Indices of the `texts` matching the query, best match first. Everything matches an
+    /// empty query, in its original order.
+    pub fn apply<'a>(&self, texts: impl IntoIterator<Item = &'a str>) -> Vec<(usize, Match)> {
+        let mut matches: Vec<(usize, Match)> = texts
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, text)| Some((i, fuzzy_match(&self.query, text)?)))
+            .collect();
+        matches.sort_by_key(|(_, m)| std::cmp::Reverse(m.score));
+        matches
+    }
+
+    /// The query as it's being typed, if there's any to show
+    pub fn prompt(&self) -> Option<Line<'static>> {
+        if !self.typing && self.query.is_empty() {
+            return None;
+        }
+        let cursor = if self.typing { "▏" } else { "" };
+        Some(Line::from(vec![
+            "/".dark_gray(),
+            self.query.clone().yellow(),
+            cursor.into(),
+        ]))
+    }
+}
+
+/// `prefix` followed by `text`, with the characters at `positions` highlighted and cut down
+/// to `width` characters
+pub fn highlight(prefix: String, text: &str, positions: &[usize], width: usize) -> Line<'static> {
+    let available = width.saturating_sub(prefix.chars().count());
+    let cut = text.chars().count() > available;
+    let keep = if cut {
+        available.saturating_sub(3)
+    } else {
+        available
+    };
+
+    let mut spans = vec![Span::raw(prefix)];
+    let mut run = String::new();
+    let mut run_matched = false;
+    for (i, c) in text.chars().take(keep).enumerate() {
+        let matched = positions.contains(&i);
+        if matched != run_matched && !run.is_empty() {
+            spans.push(styled_run(std::mem::take(&mut run), run_matched));
+        }
+        run_matched = matched;
+        run.push(c);
+    }
+    if !run.is_empty() {
+        spans.push(styled_run(run, run_matched));
+    }
+    if cut {
+        spans.push("...".into());
+    }
+    Line::from(spans)
+}
+
+fn styled_run(run: String, matched: bool) -> Span<'static> {
+    if matched {
+        Span::styled(run, Style::new().yellow().underlined())
+    } else {
+        Span::raw(run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert_eq!(fuzzy_match("wk", "work").unwrap().positions, [0, 3]);
+        assert_eq!(fuzzy_match("MUF", "muffin"), None);
+        assert_eq!(fuzzy_match("muf", "MUFFIN").unwrap().positions, [0, 1, 2]);
+        assert_eq!(fuzzy_match("xyz", "muffin"), None);
+        // The tightest match is picked over the first one
+        assert_eq!(fuzzy_match("ab", "a-xab").unwrap().positions, [3, 4]);
+
+        let names = ["dotfiles", "my-web", "webapp", "w_e_b"];
+        let mut filter = Filter::default();
+        assert_eq!(filter.apply(names).len(), 4);
+
+        filter.handle_key(KeyCode::Char('/'), "j");
+        for c in "web".chars() {
+            assert_eq!(filter.handle_key(KeyCode::Char(c), "j"), FilterEvent::Changed);
+        }
+        let ranked: Vec<&str> = filter.apply(names).iter().map(|(i, _)| names[*i]).collect();
+        assert_eq!(ranked, ["webapp", "w_e_b", "my-web"]);
+
+        // Done typing, so keys go back to the list, until esc clears the query
+        assert_eq!(filter.handle_key(KeyCode::Enter, "j"), FilterEvent::Handled);
+        assert_eq!(filter.handle_key(KeyCode::Char('j'), "j"), FilterEvent::Ignored);
+        assert_eq!(filter.handle_key(KeyCode::Esc, "j"), FilterEvent::Changed);
+        assert_eq!(filter.query(), "");
+
+        // Keys the list doesn't use start a new query on their own
+        assert_eq!(filter.handle_key(KeyCode::Char('d'), "j"), FilterEvent::Changed);
+        assert_eq!(filter.handle_key(KeyCode::Char('o'), "j"), FilterEvent::Changed);
+        assert_eq!(filter.query(), "do");
+    }
+
+    #[test]
+    fn test_highlight() {
+        let spans = |line: &Line| -> Vec<String> {
+            line.spans.iter().map(|s| s.content.to_string()).collect()
+        };
+
+        let line = highlight("1 - ".into(), "webapp", &[0, 1, 5], 10);
+        assert_eq!(spans(&line), ["1 - ", "we", "bap", "p"]);
+        assert_eq!(line.spans[1].style, Style::new().yellow().underlined());
+        assert_eq!(line.spans[2].style, Style::new());
+
+        // Matches that got cut off aren't shown
+        let line = highlight("1 - ".into(), "webapp", &[0, 1, 5], 9);
+        assert_eq!(spans(&line), ["1 - ", "we", "..."]);
+    }
+}
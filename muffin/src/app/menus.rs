@@ -1,5 +1,6 @@
 pub mod create;
 pub mod delete;
+pub mod params;
 pub mod presets;
 pub mod rename;
 pub mod sessions;
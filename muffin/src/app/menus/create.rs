@@ -87,7 +87,7 @@ impl<'a> Menu for CreateMenu<'a> {
                 }
                 KeyCode::Enter => {
                     let new_session_name = self.text_area.lines().join("\n");
-                    match tmux::create_session(&new_session_name) {
+                    match state.tmux.create_session(&new_session_name, None, &[]) {
                         Ok(_) => {
                             self.text_area = TextArea::default();
                             if std::env::var("TMUX").is_ok() {
@@ -95,7 +95,7 @@ impl<'a> Menu for CreateMenu<'a> {
                                 state.mode = Mode::Sessions;
                             } else {
                                 // Muffin is running outside tmux, attach to the new session and exit
-                                match tmux::attach_session(&new_session_name) {
+                                match state.tmux.attach_session(&new_session_name) {
                                     Ok(_) => state.exit = true, // Exit muffin to let tmux take over
                                     Err(e) => send_timed_notification(
                                         &state.event_handler,
@@ -104,7 +104,9 @@ impl<'a> Menu for CreateMenu<'a> {
                                 };
                             }
                         }
-                        Err(s) => send_timed_notification(&state.event_handler, s),
+                        Err(e) => {
+                            send_timed_notification(&state.event_handler, e.to_string())
+                        }
                     }
                 }
                 _ => _ = self.text_area.input(key_event),
@@ -1,6 +1,7 @@
 use super::Menu;
 use crate::app::{
     driver::{AppEvent, AppState, Mode},
+    tree::TreeItem,
     utils::{centered_fixed_rect, make_instructions, send_timed_notification},
 };
 use crossterm::event::KeyCode;
@@ -37,8 +38,14 @@ impl<'a> StatefulWidget for &mut DeleteMenu<'a> {
         // Render title
         {
             let index = state.selected_session.unwrap();
-            let content = match self.notification.clone() {
-                Some(msg) => msg,
+            let content = match (self.notification.clone(), &state.selected_item) {
+                (Some(msg), _) => msg,
+                (None, Some(TreeItem::Window(_, window))) => {
+                    format!("Kill window '{}'?", window.name)
+                }
+                (None, Some(TreeItem::Pane(_, window, pane))) => {
+                    format!("Kill pane {} of window '{}'?", pane.index, window.name)
+                }
                 _ => format!("Delete session '{}'?", state.sessions[index].name),
             };
 
@@ -65,12 +72,32 @@ impl<'a> Menu for DeleteMenu<'a> {
             AppEvent::Key(key_event) => match key_event.code {
                 KeyCode::Char('y') | KeyCode::Enter => {
                     if let Some(index) = state.selected_session {
-                        match tmux::delete_session(&state.sessions[index].name) {
+                        let session = &state.sessions[index];
+                        let result = match (&state.selected_item, session.preset(&state.presets)) {
+                            (Some(TreeItem::Window(_, window)), _) => {
+                                state.tmux.kill_window(&window.id)
+                            }
+                            (Some(TreeItem::Pane(_, _, pane)), _) => state.tmux.kill_pane(&pane.id),
+                            // Sessions spawned from a preset get to clean up after themselves
+                            (_, Some(preset)) => {
+                                tmux::kill_preset(state.tmux.as_ref(), &session.id, &preset)
+                            }
+                            (_, None) => state.tmux.kill_session(&session.id),
+                        };
+                        match result {
                             Ok(_) => {
                                 self.text_area = TextArea::default();
                                 state.mode = Mode::Sessions;
                             }
-                            Err(s) => send_timed_notification(&state.event_handler, s),
+                            // The session is gone by the time on_kill runs
+                            Err(e @ tmux::Error::Hook { .. }) => {
+                                self.text_area = TextArea::default();
+                                state.mode = Mode::Sessions;
+                                send_timed_notification(&state.event_handler, e.to_string())
+                            }
+                            Err(e) => {
+                                send_timed_notification(&state.event_handler, e.to_string())
+                            }
                         }
                     };
                 }
@@ -83,3 +110,59 @@ impl<'a> Menu for DeleteMenu<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyEvent;
+    use tmux::TmuxBackend;
+    use tmux::fake::FakeBackend;
+
+    #[test]
+    fn test_delete_selected_session() {
+        let tmux = FakeBackend::new();
+        tmux.create_session("keep", None, &[]).unwrap();
+        tmux.create_session("doomed", None, &[]).unwrap();
+
+        let mut state = AppState::with_fake(tmux.clone(), Default::default());
+        state.selected_session = Some(1);
+        state.mode = Mode::Delete;
+
+        let mut menu = DeleteMenu::default();
+        menu.handle_event(AppEvent::Key(KeyEvent::from(KeyCode::Char('y'))), &mut state);
+
+        assert!(matches!(state.mode, Mode::Sessions));
+        assert!(tmux.session("doomed").is_none());
+        assert!(tmux.session("keep").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_delete_preset_session_runs_on_kill() {
+        let tmux = FakeBackend::new();
+        let mut preset = parser::parse_config(
+            "session name=\"project\" cwd=\"/tmp\" { on_kill \"echo 'compose down failed' >&2; false\"; }",
+        )
+        .unwrap()
+        .remove("project")
+        .unwrap();
+        tmux::spawn_preset(&tmux, &preset).unwrap();
+        preset.running = true;
+
+        let mut state =
+            AppState::with_fake(tmux.clone(), [("project".to_string(), preset)].into());
+        state.selected_session = Some(0);
+        state.mode = Mode::Delete;
+
+        let mut menu = DeleteMenu::default();
+        menu.handle_event(AppEvent::Key(KeyEvent::from(KeyCode::Enter)), &mut state);
+
+        // The session is killed even though its hook failed, which the user gets told about
+        assert!(tmux.session("project").is_none());
+        assert!(matches!(state.mode, Mode::Sessions));
+        assert!(matches!(
+            state.event_handler.next().await,
+            Ok(AppEvent::ShowNotification(msg))
+                if msg == "`on_kill` hook failed: compose down failed"
+        ));
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+
+use super::Menu;
+use crate::app::{
+    driver::{AppEvent, AppState, Mode},
+    utils::{
+        centered_fixed_rect, make_instructions, send_timed_notification, switch_to_session,
+    },
+};
+use crossterm::event::KeyCode;
+use ratatui::{
+    prelude::{Buffer, Constraint, Layout, Rect},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, Clear, Paragraph, StatefulWidget, Widget, Wrap},
+};
+use tmux::Preset;
+use tui_textarea::TextArea;
+
+/// Asks for the parameters of the selected preset before launching it
+#[derive(Default)]
+pub struct ParamsMenu<'a> {
+    /// Preset the fields were made for
+    preset: Option<String>,
+    fields: Vec<TextArea<'a>>,
+    selected: usize,
+    notification: Option<String>,
+}
+
+impl<'a> ParamsMenu<'a> {
+    /// Starts over with empty fields whenever a different preset is selected
+    fn sync(&mut self, preset: &Preset) {
+        if self.preset.as_ref() == Some(&preset.name) {
+            return;
+        }
+        self.preset = Some(preset.name.clone());
+        self.selected = 0;
+        self.fields = preset
+            .params
+            .iter()
+            .map(|param| {
+                let mut text_area = TextArea::default();
+                text_area.set_placeholder_text(param.default.as_deref().unwrap_or("required"));
+                text_area.set_placeholder_style(Style::new().dark_gray());
+                text_area
+            })
+            .collect();
+    }
+
+    fn reset(&mut self) {
+        self.preset = None;
+        self.fields.clear();
+    }
+
+    /// What was typed for each parameter, leaving empty fields to their defaults
+    fn values(&self, preset: &Preset) -> BTreeMap<String, String> {
+        preset
+            .params
+            .iter()
+            .zip(&self.fields)
+            .map(|(param, field)| (param.name.clone(), field.lines().join("")))
+            .filter(|(_, value)| !value.is_empty())
+            .collect()
+    }
+}
+
+fn selected_preset(state: &AppState) -> Option<&Preset> {
+    state.presets.values().nth(state.selected_preset?)
+}
+
+impl<'a> StatefulWidget for &mut ParamsMenu<'a> {
+    type State = AppState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut AppState) {
+        let Some(preset) = selected_preset(state) else {
+            return;
+        };
+        self.sync(preset);
+
+        let height = self.fields.len() as u16 + 7;
+        let area = centered_fixed_rect(area, 50, height);
+        Clear.render(area, buf);
+
+        let block = Block::bordered().border_style(Style::new().cyan());
+        let inner_area = block.inner(area);
+
+        let [title_area, fields_area, instructions_area] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .vertical_margin(1)
+        .horizontal_margin(1)
+        .areas(inner_area);
+
+        // Render title
+        {
+            let content = match self.notification.clone() {
+                Some(msg) => msg,
+                _ => format!("Launch '{}' with...", preset.name),
+            };
+
+            Line::from(content.cyan()).centered().render(title_area, buf);
+        }
+
+        // Render one field per parameter
+        {
+            let label_width = preset
+                .params
+                .iter()
+                .map(|param| param.name.chars().count())
+                .max()
+                .unwrap_or_default() as u16
+                + 2;
+            let rows = Layout::vertical(vec![Constraint::Length(1); self.fields.len()])
+                .horizontal_margin(3)
+                .split(fields_area);
+
+            for (idx, ((param, field), row)) in
+                preset.params.iter().zip(&self.fields).zip(rows.iter()).enumerate()
+            {
+                let [label_area, input_area] =
+                    Layout::horizontal([Constraint::Length(label_width), Constraint::Fill(1)])
+                        .areas(*row);
+
+                let label = format!("{}:", param.name);
+                if idx == self.selected {
+                    label.cyan().bold().render(label_area, buf);
+                } else {
+                    label.dark_gray().render(label_area, buf);
+                }
+                field.render(input_area, buf);
+            }
+        }
+
+        // Render instructions
+        {
+            let instructions = vec![("esc", "cancel"), ("tab", "next"), ("enter", "launch")];
+
+            Paragraph::new(make_instructions(instructions))
+                .wrap(Wrap { trim: true })
+                .centered()
+                .render(instructions_area, buf);
+        }
+
+        block.render(area, buf);
+    }
+}
+
+impl<'a> Menu for ParamsMenu<'a> {
+    fn handle_event(&mut self, event: AppEvent, state: &mut AppState) {
+        match event {
+            AppEvent::Key(key_event) => {
+                let Some(preset) = selected_preset(state) else {
+                    state.mode = Mode::Presets;
+                    return;
+                };
+                self.sync(preset);
+
+                match key_event.code {
+                    KeyCode::Esc => {
+                        self.reset();
+                        state.mode = Mode::Presets;
+                    }
+                    KeyCode::Tab | KeyCode::Down => {
+                        self.selected = (self.selected + 1) % self.fields.len().max(1);
+                    }
+                    KeyCode::BackTab | KeyCode::Up => {
+                        self.selected = self
+                            .selected
+                            .checked_sub(1)
+                            .unwrap_or(self.fields.len().saturating_sub(1));
+                    }
+                    KeyCode::Enter => {
+                        // Values that don't fit are fixed right here, anything else is shown
+                        // back in the presets menu
+                        let preset = match preset.with_params(&self.values(preset)) {
+                            Ok(preset) => preset,
+                            Err(e) => {
+                                send_timed_notification(&state.event_handler, e.to_string());
+                                return;
+                            }
+                        };
+                        self.reset();
+                        match tmux::ensure_preset(state.tmux.as_ref(), &preset) {
+                            Ok(true) => state.mode = Mode::Sessions,
+                            Ok(false) => {
+                                // It was running already, so go there instead
+                                state.mode = Mode::Sessions;
+                                switch_to_session(state, &preset.name);
+                            }
+                            Err(e) => {
+                                state.spawn_error = Some(e.to_string());
+                                state.mode = Mode::Presets;
+                            }
+                        }
+                    }
+                    _ => {
+                        if let Some(field) = self.fields.get_mut(self.selected) {
+                            field.input(key_event);
+                        }
+                    }
+                }
+            }
+            AppEvent::ShowNotification(msg) => self.notification = Some(msg),
+            AppEvent::ClearNotification => self.notification = None,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyEvent;
+    use tmux::fake::FakeBackend;
+
+    #[tokio::test]
+    async fn test_launch_with_params() {
+        let tmux = FakeBackend::new();
+        let presets = parser::parse_config(
+            r#"session name="review-{{branch}}" cwd="{{dir}}" {
+                param "dir" default="/tmp"
+                param "branch"
+                window name="git" { pane command="git switch {{branch}}"; }
+            }"#,
+        )
+        .unwrap();
+
+        let mut state = AppState::with_fake(tmux.clone(), presets);
+        state.selected_preset = Some(0);
+        state.mode = Mode::Params;
+
+        let mut menu = ParamsMenu::default();
+        let key = |code| AppEvent::Key(KeyEvent::from(code));
+
+        let area = Rect::new(0, 0, 80, 24);
+        let mut buf = Buffer::empty(area);
+        menu.render(area, &mut buf, &mut state);
+        let text: String = buf.content().iter().map(|cell| cell.symbol()).collect();
+        // Defaults are shown until something else is typed
+        assert!(text.contains("Launch 'review-{{branch}}' with..."));
+        assert!(text.contains("branch:") && text.contains("required"));
+        assert!(text.contains("dir:") && text.contains("/tmp"));
+
+        // `branch` has no default
+        menu.handle_event(key(KeyCode::Enter), &mut state);
+        assert!(matches!(state.mode, Mode::Params));
+        assert!(matches!(
+            state.event_handler.next().await,
+            Ok(AppEvent::ShowNotification(msg))
+                if msg == "no value given for parameter `branch`"
+        ));
+
+        menu.handle_event(key(KeyCode::Tab), &mut state);
+        for c in "main".chars() {
+            menu.handle_event(key(KeyCode::Char(c)), &mut state);
+        }
+        menu.handle_event(key(KeyCode::Enter), &mut state);
+
+        assert!(matches!(state.mode, Mode::Sessions));
+        let session = tmux.session("review-main").unwrap();
+        assert_eq!(session.windows[0].panes[0].cwd, "/tmp");
+        assert_eq!(session.windows[0].panes[0].keys, ["git switch main", "Enter"]);
+    }
+}
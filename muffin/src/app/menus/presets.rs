@@ -1,7 +1,10 @@
+use std::collections::BTreeMap;
+
 use super::Menu;
 use crate::app::{
     driver::{AppEvent, AppState, Mode},
-    utils::{make_instructions, send_timed_notification},
+    filter::{Filter, FilterEvent, Match, highlight},
+    utils::{centered_fixed_rect, make_instructions, send_timed_notification, switch_to_session},
 };
 use crossterm::event::KeyCode;
 use ratatui::{
@@ -10,14 +13,19 @@ use ratatui::{
     symbols::border,
     text::Line,
     widgets::{
-        Block, Borders, Clear, HighlightSpacing, List, ListItem, ListState, Paragraph,
+        Block, Borders, Clear, HighlightSpacing, List, ListItem, ListState, Padding, Paragraph,
         StatefulWidget, Widget, Wrap,
     },
 };
+use tmux::Reconciled;
+
+/// Characters the menu does something with, which don't start the filter when pressed
+const KEYS: &str = "jkgMGqr";
 
 pub struct PresetsMenu {
     list_state: ListState,
     notification: Option<String>,
+    filter: Filter,
 }
 
 impl PresetsMenu {
@@ -27,6 +35,111 @@ impl PresetsMenu {
         Self {
             list_state,
             notification: None,
+            filter: Filter::default(),
+        }
+    }
+
+    /// Presets matching the filter, best match first, by their index in `state.presets`
+    fn matches(&self, state: &AppState) -> Vec<(usize, Match)> {
+        self.filter.apply(state.presets.values().map(|p| p.name.as_str()))
+    }
+
+    /// Points `selected_preset` at the preset listed on `row`
+    fn select_row(&mut self, row: Option<usize>, matches: &[(usize, Match)], state: &mut AppState) {
+        self.list_state.select(row);
+        state.selected_preset = row.and_then(|row| matches.get(row)).map(|(i, _)| *i);
+    }
+
+    /// Catches the list up with the presets and the filter, keeping the selected preset
+    /// selected as long as it's still listed
+    pub fn refresh(&mut self, state: &mut AppState) {
+        let matches = self.matches(state);
+        let row = matches
+            .iter()
+            .position(|(i, _)| Some(*i) == state.selected_preset)
+            .or((!matches.is_empty()).then_some(0));
+        self.select_row(row, &matches, state);
+    }
+
+    /// Handles the keys that weren't meant for the filter
+    fn handle_key(&mut self, code: KeyCode, state: &mut AppState) {
+        let matches = self.matches(state);
+        match code {
+            // Movement
+            KeyCode::Down | KeyCode::Char('j') => {
+                let row = self.select_next(matches.len());
+                self.select_row(row, &matches, state);
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                let row = self.select_previous(matches.len());
+                self.select_row(row, &matches, state);
+            }
+            KeyCode::Char('g') => {
+                let row = self.select_first(matches.len());
+                self.select_row(row, &matches, state);
+            }
+            KeyCode::Char('M') => {
+                let row = self.select_middle(matches.len());
+                self.select_row(row, &matches, state);
+            }
+            KeyCode::Char('G') => {
+                let row = self.select_last(matches.len());
+                self.select_row(row, &matches, state);
+            }
+
+            // Mode switching
+            KeyCode::Tab => state.mode = Mode::Sessions,
+
+            // Control
+            KeyCode::Char('q') => state.exit = true,
+            KeyCode::Char('r') => {
+                if let Some(index) = state.selected_preset {
+                    let preset = state.presets.values().nth(index).unwrap();
+                    // There's no telling which of its sessions is meant, or what it was
+                    // launched with
+                    if !preset.params.is_empty() {
+                        let message = format!(
+                            "'{}' takes parameters, use `muffin launch --reconcile` instead",
+                            preset.name
+                        );
+                        send_timed_notification(&state.event_handler, message);
+                        return;
+                    }
+                    let result = preset
+                        .with_params(&BTreeMap::new())
+                        .map_err(|e| e.to_string())
+                        .and_then(|preset| {
+                            tmux::reconcile_preset(state.tmux.as_ref(), &preset)
+                                .map(|reconciled| describe_reconciled(&preset.name, &reconciled))
+                                .map_err(|e| e.to_string())
+                        });
+                    match result {
+                        Ok(message) => send_timed_notification(&state.event_handler, message),
+                        Err(e) => state.spawn_error = Some(e),
+                    }
+                }
+            }
+            KeyCode::Enter => {
+                if let Some(index) = state.selected_preset {
+                    let preset = state.presets.values().nth(index).unwrap();
+                    // Presets with parameters ask for them first
+                    if !preset.params.is_empty() {
+                        state.mode = Mode::Params;
+                        return;
+                    }
+                    let name = preset.name.clone();
+                    match tmux::ensure_preset(state.tmux.as_ref(), preset) {
+                        Ok(true) => state.mode = Mode::Sessions,
+                        Ok(false) => {
+                            // It was running already, so go there instead
+                            state.mode = Mode::Sessions;
+                            switch_to_session(state, &name);
+                        }
+                        Err(e) => state.spawn_error = Some(e.to_string()),
+                    }
+                };
+            }
+            _ => {}
         }
     }
 
@@ -100,19 +213,27 @@ impl StatefulWidget for &mut PresetsMenu {
                 .render(title_area, buf);
         }
 
-        // Render notification
+        // Render notification, or the filter while there is one
         {
-            let content = match self.notification.clone() {
-                Some(msg) => msg.red(),
-                None => format!("Reading presets from {}", state.presets_path).into(),
+            let content = match (self.notification.clone(), self.filter.prompt()) {
+                (Some(msg), _) => Line::from(msg.red().italic()),
+                (None, Some(prompt)) => prompt,
+                (None, None) => {
+                    Line::from(format!("Reading presets from {}", state.presets_path).italic())
+                }
             };
-            Paragraph::new(Line::from(content.italic()))
-                .centered()
-                .render(notification_area, buf);
+            Paragraph::new(content).centered().render(notification_area, buf);
         }
 
-        // Render presets
-        {
+        let matches = self.matches(state);
+
+        // Render presets, or why there aren't any
+        if let Some(error) = &state.presets_error {
+            Paragraph::new(error.as_str())
+                .red()
+                .wrap(Wrap { trim: false })
+                .render(presets_area, buf);
+        } else {
             let sessions_width = 20;
             let [_, presets_area, running_status_area, _] = Layout::horizontal([
                 Constraint::Fill(1),
@@ -122,25 +243,22 @@ impl StatefulWidget for &mut PresetsMenu {
             ])
             .areas(presets_area);
 
-            let presets = state
-                .presets
-                .values()
-                .map(|s| {
-                    let truncated_name = if s.name.len() > sessions_width as usize - 8 {
-                        let mut name = s.name.clone();
-                        name.truncate(sessions_width as usize - 11);
-                        format!("{}...", name)
-                    } else {
-                        s.name.clone()
-                    };
-                    let text = format!("{:>2}  - {}", s.windows.len(), truncated_name);
-                    let item = Line::from(text.clone());
-                    ListItem::new(item)
+            let presets: Vec<_> = state.presets.values().collect();
+            let items = matches
+                .iter()
+                .map(|(i, m)| {
+                    let s = presets[*i];
+                    ListItem::new(highlight(
+                        format!("{:>2}  - ", s.windows.len()),
+                        &s.name,
+                        &m.positions,
+                        sessions_width as usize - 2,
+                    ))
                 })
                 .collect::<Vec<ListItem>>();
 
             StatefulWidget::render(
-                List::new(presets)
+                List::new(items)
                     .highlight_symbol("")
                     .highlight_spacing(HighlightSpacing::Always)
                     .highlight_style(Style::new().italic().bold().cyan()),
@@ -150,10 +268,10 @@ impl StatefulWidget for &mut PresetsMenu {
             );
 
             Paragraph::new(
-                state
-                    .presets
-                    .values()
-                    .map(|s| if s.running { "   running" } else { "" })
+                matches
+                    .iter()
+                    .skip(self.list_state.offset())
+                    .map(|(i, _)| if presets[*i].running { "   running" } else { "" })
                     .collect::<Vec<&str>>()
                     .join("\n"),
             )
@@ -165,7 +283,9 @@ impl StatefulWidget for &mut PresetsMenu {
         {
             let instructions = vec![
                 ("enter", "launch"),
+                ("r", "reconcile"),
                 ("q", "quit"),
+                ("/", "filter"),
                 ("j/↓", "next"),
                 ("k/↑", "prev"),
                 ("tab", "view sessions"),
@@ -179,44 +299,39 @@ impl StatefulWidget for &mut PresetsMenu {
         }
 
         block.render(area, buf);
+
+        // Render why the last launch failed on top of everything else
+        if let Some(error) = &state.spawn_error {
+            let popup_area = centered_fixed_rect(area, 60, 10);
+            Clear.render(popup_area, buf);
+
+            let popup = Block::bordered()
+                .border_style(Style::new().red())
+                .title(Line::from(" Launch failed ".red().bold()).centered())
+                .title_bottom(make_instructions(vec![("any key", "dismiss")]).centered());
+            Paragraph::new(error.as_str())
+                .wrap(Wrap { trim: true })
+                .block(popup.padding(Padding::uniform(1)))
+                .render(popup_area, buf);
+        }
     }
 }
 
 impl Menu for PresetsMenu {
     fn handle_event(&mut self, event: AppEvent, state: &mut AppState) {
         match event {
-            AppEvent::Key(key_event) => match key_event.code {
-                // Movement
-                KeyCode::Down | KeyCode::Char('j') => {
-                    state.selected_preset = self.select_next(state.presets.len())
+            // Any key dismisses the error from the last launch
+            AppEvent::Key(_) if state.spawn_error.is_some() => state.spawn_error = None,
+            AppEvent::Key(key_event) => match self.filter.handle_key(key_event.code, KEYS) {
+                FilterEvent::Ignored => self.handle_key(key_event.code, state),
+                FilterEvent::Handled => {}
+                // Stay put when the filter is cleared, and go to the best match while typing
+                FilterEvent::Changed if self.filter.query().is_empty() => self.refresh(state),
+                FilterEvent::Changed => {
+                    let matches = self.matches(state);
+                    let row = (!matches.is_empty()).then_some(0);
+                    self.select_row(row, &matches, state);
                 }
-                KeyCode::Up | KeyCode::Char('k') => {
-                    state.selected_preset = self.select_previous(state.presets.len())
-                }
-                KeyCode::Char('g') => {
-                    state.selected_preset = self.select_first(state.presets.len())
-                }
-                KeyCode::Char('M') => {
-                    state.selected_preset = self.select_middle(state.presets.len())
-                }
-                KeyCode::Char('G') => state.selected_preset = self.select_last(state.presets.len()),
-
-                // Mode switching
-                KeyCode::Tab => state.mode = Mode::Sessions,
-
-                // Control
-                KeyCode::Char('q') => state.exit = true,
-                KeyCode::Enter => {
-                    if let Some(index) = state.selected_preset {
-                        match tmux::spawn_preset(state.presets.values().nth(index).unwrap()) {
-                            Ok(_) => {
-                                state.mode = Mode::Sessions;
-                            }
-                            Err(s) => send_timed_notification(&state.event_handler, s),
-                        }
-                    };
-                }
-                _ => {}
             },
             AppEvent::ShowNotification(msg) => self.notification = Some(msg),
             AppEvent::ClearNotification => self.notification = None,
@@ -224,3 +339,204 @@ impl Menu for PresetsMenu {
         }
     }
 }
+
+/// Sums up what reconciling the session `name` added to it
+fn describe_reconciled(name: &str, reconciled: &Reconciled) -> String {
+    let plural = |count: usize, noun: &str| match count {
+        1 => format!("1 {noun}"),
+        n => format!("{n} {noun}s"),
+    };
+    match (reconciled.windows.len(), reconciled.panes) {
+        (0, 0) => format!("'{name}' is up to date"),
+        (0, panes) => format!("Added {} to '{name}'", plural(panes, "pane")),
+        (windows, 0) => format!("Added {} to '{name}'", plural(windows, "window")),
+        (windows, panes) => format!(
+            "Added {} and {} to '{name}'",
+            plural(windows, "window"),
+            plural(panes, "pane")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyEvent;
+    use tmux::fake::{self, FakeBackend, pane, window};
+
+    // Switching to a running session may notify about how that went
+    #[tokio::test]
+    async fn test_launch_selected_preset() {
+        let tmux = FakeBackend::new();
+        let editor = window("editor", pane("/tmp", Some("nvim"), 1));
+        let preset = fake::preset("project", vec![editor]);
+
+        let mut state =
+            AppState::with_fake(tmux.clone(), [("project".to_string(), preset)].into());
+        state.selected_preset = Some(0);
+        state.mode = Mode::Presets;
+
+        let mut menu = PresetsMenu::new(Some(0));
+        menu.handle_event(AppEvent::Key(KeyEvent::from(KeyCode::Enter)), &mut state);
+
+        assert!(matches!(state.mode, Mode::Sessions));
+        let session = tmux.session("project").unwrap();
+        assert_eq!(session.windows[0].name, "editor");
+        assert_eq!(session.windows[0].panes[0].keys, ["nvim", "Enter"]);
+        assert_eq!(session.windows[0].panes[0].cwd, "/tmp");
+
+        // Going back to it leaves the menu the same way launching it does
+        state.mode = Mode::Presets;
+        menu.handle_event(AppEvent::Key(KeyEvent::from(KeyCode::Enter)), &mut state);
+        assert!(matches!(state.mode, Mode::Sessions));
+        assert_eq!(tmux.sessions().len(), 1);
+    }
+
+    // Notifications are cleared from a tokio task
+    #[tokio::test]
+    async fn test_reconcile_selected_preset() {
+        let tmux = FakeBackend::new();
+        let presets = parser::parse_config(
+            r#"session name="project" cwd="/tmp" {
+                window name="editor" { pane command="nvim"; }
+                window name="logs" { pane command="tail -f log"; }
+            }"#,
+        )
+        .unwrap();
+        // Start it out without its logs
+        let mut drifted = presets["project"].clone();
+        drifted.windows.pop();
+        tmux::spawn_preset(&tmux, &drifted).unwrap();
+
+        let mut state = AppState::with_fake(tmux.clone(), presets);
+        state.selected_preset = Some(0);
+        state.mode = Mode::Presets;
+
+        let mut menu = PresetsMenu::new(Some(0));
+        menu.handle_event(AppEvent::Key(KeyEvent::from(KeyCode::Char('r'))), &mut state);
+        assert!(matches!(
+            state.event_handler.next().await,
+            Ok(AppEvent::ShowNotification(msg)) if msg == "Added 1 window to 'project'"
+        ));
+
+        let session = tmux.session("project").unwrap();
+        assert_eq!(session.windows.len(), 2);
+        assert_eq!(session.windows[1].panes[0].keys, ["tail -f log", "Enter"]);
+        // The editor wasn't started again
+        assert_eq!(session.windows[0].panes[0].keys, ["nvim", "Enter"]);
+
+        // Presets with parameters can't tell which session to reconcile
+        let presets = parser::parse_config(
+            r#"session name="review-{{branch}}" cwd="/tmp" {
+                param "branch" default="main"
+                window name="git" { pane; }
+            }"#,
+        )
+        .unwrap();
+        let mut state = AppState::with_fake(tmux.clone(), presets);
+        state.selected_preset = Some(0);
+        state.mode = Mode::Presets;
+        menu.handle_event(AppEvent::Key(KeyEvent::from(KeyCode::Char('r'))), &mut state);
+        assert!(matches!(
+            state.event_handler.next().await,
+            Ok(AppEvent::ShowNotification(msg))
+                if msg == "'review-{{branch}}' takes parameters, use `muffin launch --reconcile` instead"
+        ));
+        assert!(tmux.session("review-main").is_none());
+        assert_eq!(state.spawn_error, None);
+    }
+
+    #[test]
+    fn test_launch_failure() {
+        let tmux = FakeBackend::new();
+        let presets = parser::parse_config(
+            r#"session name="project" cwd="/tmp" {
+                window name="editor" { pane; }
+                window name="logs" { pane; }
+            }"#,
+        )
+        .unwrap();
+        let mut state = AppState::with_fake(tmux.clone(), presets);
+        state.selected_preset = Some(0);
+        state.mode = Mode::Presets;
+
+        tmux.fail("new-window", "index 1 in use");
+        let mut menu = PresetsMenu::new(Some(0));
+        menu.handle_event(AppEvent::Key(KeyEvent::from(KeyCode::Enter)), &mut state);
+
+        assert!(matches!(state.mode, Mode::Presets));
+        assert!(tmux.session("project").is_none());
+        let error = "couldn't spawn `project`, window `logs` failed: \
+                     tmux new-window failed: index 1 in use";
+        assert_eq!(state.spawn_error.as_deref(), Some(error));
+
+        let area = Rect::new(0, 0, 80, 24);
+        let mut buf = Buffer::empty(area);
+        menu.render(area, &mut buf, &mut state);
+        let text: String = buf.content().iter().map(|cell| cell.symbol()).collect();
+        assert!(text.contains("Launch failed"));
+        assert!(text.contains("window `logs` failed"));
+
+        // The next key only dismisses it
+        menu.handle_event(AppEvent::Key(KeyEvent::from(KeyCode::Char('j'))), &mut state);
+        assert_eq!(state.spawn_error, None);
+        assert_eq!(state.selected_preset, Some(0));
+    }
+
+    #[test]
+    fn test_filter_presets() {
+        let tmux = FakeBackend::new();
+        let presets = parser::parse_config(
+            r#"session name="api" cwd="/tmp" { window name="main" { pane; } }
+            session name="dotfiles" cwd="/tmp" { window name="main" { pane; } }
+            session name="web" cwd="/tmp" { window name="main" { pane; } }"#,
+        )
+        .unwrap();
+        let mut state = AppState::with_fake(tmux.clone(), presets);
+        state.selected_preset = Some(0);
+        state.mode = Mode::Presets;
+
+        let mut menu = PresetsMenu::new(Some(0));
+        let area = Rect::new(0, 0, 80, 24);
+        let mut press = |code, state: &mut AppState| {
+            menu.handle_event(AppEvent::Key(KeyEvent::from(code)), state);
+            let mut buf = Buffer::empty(area);
+            menu.render(area, &mut buf, state);
+            buf.content().iter().map(|cell| cell.symbol()).collect::<String>()
+        };
+
+        // Enter only stops typing, and the preset it lands on is the third one
+        for c in "/we".chars() {
+            press(KeyCode::Char(c), &mut state);
+        }
+        let text = press(KeyCode::Enter, &mut state);
+        assert!(text.contains("/we"));
+        assert!(!text.contains("dotfiles"));
+        assert_eq!(state.selected_preset, Some(2));
+
+        press(KeyCode::Enter, &mut state);
+        assert!(tmux.session("web").is_some());
+        assert!(tmux.session("api").is_none());
+
+        // Clearing the filter lists everything again, without losing the selection
+        state.mode = Mode::Presets;
+        let text = press(KeyCode::Esc, &mut state);
+        assert!(text.contains("dotfiles"));
+        assert_eq!(state.selected_preset, Some(2));
+        press(KeyCode::Char('k'), &mut state);
+        assert_eq!(state.selected_preset, Some(1));
+    }
+
+    #[test]
+    fn test_render_presets_error() {
+        let mut state = AppState::with_fake(FakeBackend::new(), Default::default());
+        state.presets_error = Some("presets.kdl:3:3: unknown node `windw`".to_string());
+
+        let area = Rect::new(0, 0, 80, 24);
+        let mut buf = Buffer::empty(area);
+        PresetsMenu::new(None).render(area, &mut buf, &mut state);
+
+        let text: String = buf.content().iter().map(|cell| cell.symbol()).collect();
+        assert!(text.contains("presets.kdl:3:3: unknown node `windw`"));
+    }
+}
@@ -1,6 +1,7 @@
 use super::Menu;
 use crate::app::{
     driver::{AppEvent, AppState, Mode},
+    tree::TreeItem,
     utils::{centered_fixed_rect, make_instructions, send_timed_notification},
 };
 use crossterm::event::KeyCode;
@@ -40,8 +41,11 @@ impl<'a> StatefulWidget for &mut RenameMenu<'a> {
         // Render title
         {
             let index = state.selected_session.unwrap();
-            let content = match self.notification.clone() {
-                Some(msg) => msg,
+            let content = match (self.notification.clone(), &state.selected_item) {
+                (Some(msg), _) => msg,
+                (None, Some(TreeItem::Window(_, window))) => {
+                    format!("Rename window '{}' to...", window.name)
+                }
                 _ => format!("Rename session '{}' to...", state.sessions[index].name),
             };
 
@@ -89,15 +93,23 @@ impl<'a> Menu for RenameMenu<'a> {
                 }
                 KeyCode::Enter => {
                     if let Some(index) = state.selected_session {
-                        match tmux::rename_session(
-                            &state.sessions[index].name,
-                            &self.text_area.lines().join(""),
-                        ) {
+                        let new_name = self.text_area.lines().join("");
+                        let result = match &state.selected_item {
+                            Some(TreeItem::Window(_, window)) => {
+                                state.tmux.rename_window(&window.id, &new_name)
+                            }
+                            _ => state
+                                .tmux
+                                .rename_session(&state.sessions[index].name, &new_name),
+                        };
+                        match result {
                             Ok(_) => {
                                 self.text_area = TextArea::default();
                                 state.mode = Mode::Sessions;
                             }
-                            Err(s) => send_timed_notification(&state.event_handler, s),
+                            Err(e) => {
+                                send_timed_notification(&state.event_handler, e.to_string())
+                            }
                         }
                     };
                 }
@@ -1,7 +1,12 @@
+use std::path::PathBuf;
+
 use super::Menu;
 use crate::app::{
     driver::{AppEvent, AppState, Mode},
-    utils::{make_instructions, send_timed_notification},
+    filter::{Filter, FilterEvent, Match, highlight},
+    preview::Preview,
+    tree::{Tree, TreeItem},
+    utils::{make_instructions, send_timed_notification, switch_to_session},
 };
 use crossterm::event::KeyCode;
 use ratatui::{
@@ -14,10 +19,28 @@ use ratatui::{
         StatefulWidget, Widget, Wrap,
     },
 };
+use tmux::WindowInfo;
+
+/// Characters the menu does something with, which don't start the filter when pressed
+const KEYS: &str = "jkgMGlhJKmadrsqez";
 
 pub struct SessionsMenu {
     list_state: ListState,
+    /// What tmux knows the selected row as, to find it again once sessions are refreshed and
+    /// rows have moved around
+    selected: Option<String>,
     notification: Option<String>,
+    /// Snapshot of the selected row, taken again when another row is selected or on a tick
+    /// rather than while drawing, as it takes a couple of tmux commands
+    preview: Option<Preview>,
+    /// Rows to show, rebuilt when sessions are refreshed, folded or filtered rather than on
+    /// every frame, as listing windows and panes takes a tmux command each
+    tree: Tree,
+    /// Window waiting to be moved to whichever session `m` is pressed on next
+    marked: Option<WindowInfo>,
+    filter: Filter,
+    /// Sessions matching the filter, best match first
+    matches: Vec<(usize, Match)>,
 }
 
 impl SessionsMenu {
@@ -26,7 +49,123 @@ impl SessionsMenu {
         list_state.select(index);
         Self {
             list_state,
+            selected: None,
             notification: None,
+            preview: None,
+            tree: Tree::default(),
+            marked: None,
+            filter: Filter::default(),
+            matches: vec![],
+        }
+    }
+
+    /// Fills the tree with the sessions matching the filter
+    fn rebuild_tree(&mut self, state: &AppState) {
+        self.matches = self.filter.apply(state.sessions.iter().map(|s| s.name.as_str()));
+        let order: Vec<usize> = self.matches.iter().map(|(s, _)| *s).collect();
+        self.tree.rebuild(state.tmux.as_ref(), &state.sessions, &order);
+    }
+
+    /// Catches the tree up with [`AppState::refresh`], as sessions, windows and panes may have
+    /// come and gone since. The selection follows what was selected, or stays on a row that's
+    /// still there if that's gone.
+    pub fn refresh(&mut self, state: &mut AppState) {
+        self.rebuild_tree(state);
+        let found = self.selected.as_ref().and_then(|target| self.find(target, state));
+        if found.is_some() {
+            self.select_row(found, state);
+            return;
+        }
+        let rows = self.tree.items().len();
+        let row = self.list_state.selected().map(|row| row.min(rows.saturating_sub(1)));
+        self.select_row(row.filter(|_| rows > 0), state);
+    }
+
+    fn selected_item(&self) -> Option<TreeItem> {
+        let row = self.list_state.selected()?;
+        self.tree.items().get(row).cloned()
+    }
+
+    /// Points `selected_session` and `selected_item` at the tree's `row`
+    fn select_row(&mut self, row: Option<usize>, state: &mut AppState) {
+        self.list_state.select(row);
+        let item = row.and_then(|row| self.tree.items().get(row));
+        self.selected = item.map(|item| item.target(&state.sessions));
+        state.selected_session = item.map(TreeItem::session);
+        state.selected_item = item
+            .filter(|item| !matches!(item, TreeItem::Session(_)))
+            .cloned();
+        self.update_preview(state);
+    }
+
+    /// Takes a snapshot of the selected row, unless the preview already shows it
+    fn update_preview(&mut self, state: &AppState) {
+        let Some(item) = self.selected_item() else {
+            self.preview = None;
+            return;
+        };
+        let target = item.target(&state.sessions);
+        if self.preview.as_ref().is_none_or(|p| p.target != target) {
+            let session = &state.sessions[item.session()].name;
+            self.preview = Some(Preview::capture(state.tmux.as_ref(), session, &target));
+        }
+    }
+
+    /// Row of whatever tmux knows as `target`, if the tree has it
+    fn find(&self, target: &str, state: &AppState) -> Option<usize> {
+        self.tree
+            .items()
+            .iter()
+            .position(|item| item.target(&state.sessions) == target)
+    }
+
+    /// Selects whatever tmux knows as `target` now, wherever it ended up in the tree
+    fn select_target(&mut self, target: &str, state: &mut AppState) {
+        self.rebuild_tree(state);
+        let row = self.find(target, state);
+        if row.is_some() {
+            self.select_row(row, state);
+        }
+    }
+
+    /// Swaps the selected window or pane with the one `offset` places away from it
+    fn swap_selected(&mut self, offset: isize, state: &mut AppState) {
+        let Some(item) = self.selected_item() else {
+            return;
+        };
+        let result = match (&item, self.tree.sibling(&item, offset)) {
+            (TreeItem::Window(_, window), Some(TreeItem::Window(_, other))) => {
+                state.tmux.swap_window(&window.id, &other.id)
+            }
+            (TreeItem::Pane(_, _, pane), Some(TreeItem::Pane(_, _, other))) => {
+                state.tmux.swap_pane(&pane.id, &other.id)
+            }
+            _ => return,
+        };
+        match result {
+            Ok(_) => self.select_target(&item.target(&state.sessions), state),
+            Err(e) => send_timed_notification(&state.event_handler, e.to_string()),
+        }
+    }
+
+    /// Picks the selected window up, or drops the one picked up before into the selected
+    /// session
+    fn move_selected(&mut self, state: &mut AppState) {
+        let Some(item) = self.selected_item() else {
+            return;
+        };
+        match (self.marked.take(), &item) {
+            // Pressing `m` on the same window again puts it back down
+            (Some(marked), TreeItem::Window(_, window)) if marked.id == window.id => {}
+            (Some(marked), _) => {
+                let session = &state.sessions[item.session()].name;
+                match state.tmux.move_window(&marked.id, session) {
+                    Ok(_) => self.select_target(&item.target(&state.sessions), state),
+                    Err(e) => send_timed_notification(&state.event_handler, e.to_string()),
+                }
+            }
+            (None, TreeItem::Window(_, window)) => self.marked = Some(window.clone()),
+            (None, _) => {}
         }
     }
 
@@ -100,20 +239,31 @@ impl StatefulWidget for &mut SessionsMenu {
                 .render(title_area, buf);
         }
 
-        // Render notification
+        // Render notification, or the filter while there is one
         {
-            let content = match self.notification.clone() {
-                Some(msg) => msg.red(),
-                None => "Select a session!".into(),
+            let content = match (self.notification.clone(), self.filter.prompt()) {
+                (Some(msg), _) => Line::from(msg.red().italic()),
+                (None, Some(prompt)) => prompt,
+                (None, None) => Line::from("Select a session!".italic()),
             };
-            Paragraph::new(Line::from(content.italic()))
-                .centered()
-                .render(notification_area, buf);
+            Paragraph::new(content).centered().render(notification_area, buf);
         }
 
-        // Render sessions
+        // Render a preview of the selected row next to the list, if there's room for it
+        let sessions_area = match &self.preview {
+            Some(preview) if sessions_area.width >= 80 => {
+                let [sessions_area, preview_area] =
+                    Layout::horizontal([Constraint::Length(34), Constraint::Fill(1)])
+                        .areas(sessions_area);
+                preview.render(preview_area, buf);
+                sessions_area
+            }
+            _ => sessions_area,
+        };
+
+        // Render sessions, along with the windows and panes of the unfolded ones
         {
-            let sessions_width = 20;
+            let sessions_width = 24;
             let [_, sessions_area, active_status_area, _] = Layout::horizontal([
                 Constraint::Fill(1),
                 Constraint::Length(sessions_width),
@@ -122,46 +272,83 @@ impl StatefulWidget for &mut SessionsMenu {
             ])
             .areas(sessions_area);
 
-            let sessions = state
-                .sessions
+            let fold = |item: &TreeItem| {
+                if self.tree.is_expanded(item, &state.sessions) {
+                    "▾"
+                } else {
+                    "▸"
+                }
+            };
+            let items = self
+                .tree
+                .items()
                 .iter()
-                .map(|s| {
-                    let truncated_name = if s.name.len() > sessions_width as usize - 8 {
-                        let mut name = s.name.clone();
-                        name.truncate(sessions_width as usize - 11);
-                        format!("{}...", name)
-                    } else {
-                        s.name.clone()
+                .map(|item| {
+                    let (text, style) = match item {
+                        TreeItem::Session(s) => {
+                            let session = &state.sessions[*s];
+                            let positions = self
+                                .matches
+                                .iter()
+                                .find(|(matched, _)| matched == s)
+                                .map(|(_, m)| m.positions.as_slice())
+                                .unwrap_or_default();
+                            let line = highlight(
+                                format!("{} {:>2}  - ", fold(item), session.windows),
+                                &session.name,
+                                positions,
+                                sessions_width as usize,
+                            );
+                            let style = if session.is_attached() {
+                                Style::new().green()
+                            } else {
+                                Style::new()
+                            };
+                            return ListItem::new(line.style(style));
+                        }
+                        TreeItem::Window(_, window) => {
+                            let text = format!("    {} {}: {}", fold(item), window.index, window.name);
+                            let style = match &self.marked {
+                                Some(marked) if marked.id == window.id => Style::new().yellow(),
+                                _ => Style::new(),
+                            };
+                            (text, style)
+                        }
+                        TreeItem::Pane(_, _, pane) => (
+                            format!("        {}: {}", pane.index, pane.command),
+                            Style::new().gray(),
+                        ),
                     };
-                    let text = format!("{:>2}  - {}", s.windows, truncated_name);
-                    let mut item = Line::from(text.clone());
-                    if s.active {
-                        item = item.green();
-                    }
-                    ListItem::new(item)
+                    ListItem::new(Line::from(truncate(text, sessions_width as usize)).style(style))
                 })
                 .collect::<Vec<ListItem>>();
 
-            Paragraph::new(
-                state
-                    .sessions
-                    .iter()
-                    .map(|s| if s.active { "   active" } else { "" })
-                    .collect::<Vec<&str>>()
-                    .join("\n"),
-            )
-            .green()
-            .render(active_status_area, buf);
-
             StatefulWidget::render(
-                List::new(sessions)
-                    .highlight_symbol("")
+                List::new(items)
+                    .highlight_symbol("")
                     .highlight_spacing(HighlightSpacing::Always)
                     .highlight_style(Style::new().italic().bold().cyan()),
                 sessions_area,
                 buf,
                 &mut self.list_state,
             );
+
+            // Follows the list as it scrolls
+            Paragraph::new(
+                self.tree
+                    .items()
+                    .iter()
+                    .skip(self.list_state.offset())
+                    .map(|item| match item {
+                        TreeItem::Session(s) if state.sessions[*s].is_attached() => "   active",
+                        TreeItem::Window(_, window) if self.marked.as_ref().is_some_and(|m| m.id == window.id) => "   moving",
+                        _ => "",
+                    })
+                    .collect::<Vec<&str>>()
+                    .join("\n"),
+            )
+            .green()
+            .render(active_status_area, buf);
         }
 
         // Render instructions
@@ -169,6 +356,10 @@ impl StatefulWidget for &mut SessionsMenu {
             let instructions = vec![
                 ("enter", "switch"),
                 ("q", "quit"),
+                ("/", "filter"),
+                ("h/l", "fold"),
+                ("J/K", "swap"),
+                ("m", "move"),
                 ("d", "delete"),
                 ("z", "detach"),
                 ("j/↓", "next"),
@@ -176,6 +367,7 @@ impl StatefulWidget for &mut SessionsMenu {
                 ("a", "create"),
                 ("r", "rename"),
                 ("s", "set default"),
+                ("e", "export"),
                 ("tab", "view presets"),
             ];
 
@@ -190,80 +382,401 @@ impl StatefulWidget for &mut SessionsMenu {
     }
 }
 
+/// Cuts `text` down to `width` characters, ending it with `...` if anything was cut
+fn truncate(text: String, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text;
+    }
+    let mut text: String = text.chars().take(width.saturating_sub(3)).collect();
+    text.push_str("...");
+    text
+}
+
+/// Saves the session at `index` as a new preset in the presets file, or in a file of its own
+/// if presets are read from a directory
+fn export_session(state: &mut AppState, index: usize) -> Result<String, String> {
+    let name = state.sessions[index].name.clone();
+    if state.presets.contains_key(&name) {
+        return Err(format!("a preset named `{name}` already exists"));
+    }
+
+    let mut preset =
+        tmux::export_session(state.tmux.as_ref(), &name).map_err(|e| e.to_string())?;
+
+    let mut path = PathBuf::from(&state.presets_path);
+    if path.is_dir() {
+        path.push(format!("{}.kdl", name.replace('/', "_")));
+    }
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.to_string()),
+    };
+    let contents = parser::update_config(&contents, &preset).map_err(|e| e.to_string())?;
+    std::fs::write(&path, contents).map_err(|e| e.to_string())?;
+    preset.source = Some(path);
+
+    state.presets.insert(name.clone(), preset);
+    Ok(name)
+}
+
 impl Menu for SessionsMenu {
     fn handle_event(&mut self, event: AppEvent, state: &mut AppState) {
+        if let AppEvent::Key(key_event) = event {
+            match self.filter.handle_key(key_event.code, KEYS) {
+                FilterEvent::Ignored => {}
+                FilterEvent::Handled => return,
+                FilterEvent::Changed => {
+                    let selected = self.selected_item().map(|item| item.target(&state.sessions));
+                    self.rebuild_tree(state);
+                    match selected {
+                        // Stay put when the filter is cleared...
+                        Some(target) if self.filter.query().is_empty() => {
+                            self.select_target(&target, state)
+                        }
+                        // ...and go to the best match while typing
+                        _ => {
+                            let row = (!self.tree.items().is_empty()).then_some(0);
+                            self.select_row(row, state);
+                        }
+                    }
+                    return;
+                }
+            }
+        }
+
         match event {
             AppEvent::Key(key_event) => match key_event.code {
                 // Movement
                 KeyCode::Down | KeyCode::Char('j') => {
-                    state.selected_session = self.select_next(state.sessions.len())
+                    let row = self.select_next(self.tree.items().len());
+                    self.select_row(row, state);
                 }
                 KeyCode::Up | KeyCode::Char('k') => {
-                    state.selected_session = self.select_previous(state.sessions.len())
+                    let row = self.select_previous(self.tree.items().len());
+                    self.select_row(row, state);
                 }
                 KeyCode::Char('g') => {
-                    state.selected_session = self.select_first(state.sessions.len())
+                    let row = self.select_first(self.tree.items().len());
+                    self.select_row(row, state);
                 }
                 KeyCode::Char('M') => {
-                    state.selected_session = self.select_middle(state.sessions.len())
+                    let row = self.select_middle(self.tree.items().len());
+                    self.select_row(row, state);
                 }
                 KeyCode::Char('G') => {
-                    state.selected_session = self.select_last(state.sessions.len())
+                    let row = self.select_last(self.tree.items().len());
+                    self.select_row(row, state);
+                }
+
+                // Folding
+                KeyCode::Right | KeyCode::Char('l') => {
+                    if let Some(item) = self.selected_item()
+                        && self.tree.expand(&item, &state.sessions)
+                    {
+                        self.rebuild_tree(state);
+                    }
+                }
+                KeyCode::Left | KeyCode::Char('h') => {
+                    if let Some(item) = self.selected_item() {
+                        if self.tree.collapse(&item, &state.sessions) {
+                            self.rebuild_tree(state);
+                        } else {
+                            // It's folded already, so go up a level instead
+                            let parent = self.tree.parent(&item);
+                            if parent.is_some() {
+                                self.select_row(parent, state);
+                            }
+                        }
+                    }
                 }
 
+                // Rearranging
+                KeyCode::Char('J') => self.swap_selected(1, state),
+                KeyCode::Char('K') => self.swap_selected(-1, state),
+                KeyCode::Char('m') => self.move_selected(state),
+
                 // Mode switching
                 KeyCode::Char('a') => state.mode = Mode::Create,
-                KeyCode::Char('r') => state.mode = Mode::Rename,
+                KeyCode::Char('r') => match state.selected_item {
+                    Some(TreeItem::Pane(..)) => send_timed_notification(
+                        &state.event_handler,
+                        "Panes can't be renamed".into(),
+                    ),
+                    _ => state.mode = Mode::Rename,
+                },
                 KeyCode::Char('d') => state.mode = Mode::Delete,
                 KeyCode::Char('s') => state.mode = Mode::SetDefaultPreset,
                 KeyCode::Tab => state.mode = Mode::Presets,
 
                 // Control
                 KeyCode::Char('q') => state.exit = true,
+                KeyCode::Char('e') => {
+                    if let Some(index) = state.selected_session {
+                        let msg = match export_session(state, index) {
+                            Ok(name) => format!("Saved `{name}` to {}", state.presets_path),
+                            Err(e) => format!("Failed to export session: {e}"),
+                        };
+                        send_timed_notification(&state.event_handler, msg);
+                    }
+                }
                 KeyCode::Char('z') => {
-                    if let Err(e) = tmux::detach_session() {
+                    if let Err(e) = state.tmux.detach_session() {
                         send_timed_notification(
                             &state.event_handler,
                             format!("Failed to detach session: {}", e),
                         );
                     }
                 }
-                KeyCode::Enter => {
-                    if let Some(index) = state.selected_session {
-                        if state.sessions[index].active {
-                            send_timed_notification(
-                                &state.event_handler,
-                                "Already attached!".into(),
-                            );
-                        } else {
-                            let session_name = &state.sessions[index].name;
-                            if std::env::var("TMUX").is_ok() {
-                                // Muffin is running inside tmux, so we can switch clients
-                                match tmux::switch_session(session_name) {
-                                    Ok(_) => {},
-                                    Err(e) => send_timed_notification(
-                                        &state.event_handler,
-                                        format!("Failed to switch session: {}", e),
-                                    ),
-                                };
-                            } else {
-                                // Muffin is running outside tmux, so we need to attach and exit
-                                match tmux::attach_session(session_name) {
-                                    Ok(_) => state.exit = true, // Exit muffin to let tmux take over
-                                    Err(e) => send_timed_notification(
-                                        &state.event_handler,
-                                        format!("Failed to attach to session: {}", e),
-                                    ),
-                                };
+                KeyCode::Enter => match state.selected_item.clone() {
+                    // Bring the window or pane up first, then go to its session
+                    Some(item) => {
+                        let result = match &item {
+                            TreeItem::Window(_, window) => state.tmux.select_window(&window.id),
+                            TreeItem::Pane(_, window, pane) => state
+                                .tmux
+                                .select_window(&window.id)
+                                .and_then(|_| state.tmux.select_pane(&pane.id)),
+                            TreeItem::Session(_) => Ok(()),
+                        };
+                        match result {
+                            Ok(_) => {
+                                let session_name = state.sessions[item.session()].name.clone();
+                                switch_to_session(state, &session_name);
+                            }
+                            Err(e) => {
+                                send_timed_notification(&state.event_handler, e.to_string())
                             }
                         }
-                    };
-                }
+                    }
+                    None => {
+                        if let Some(index) = state.selected_session {
+                            if state.sessions[index].is_attached() {
+                                send_timed_notification(
+                                    &state.event_handler,
+                                    "Already attached!".into(),
+                                );
+                            } else {
+                                let session_name = state.sessions[index].name.clone();
+                                switch_to_session(state, &session_name);
+                            }
+                        };
+                    }
+                },
                 _ => {}
             },
+            // Whatever runs in the selected row may look different by now
+            AppEvent::Tick => {
+                self.preview = None;
+                self.update_preview(state);
+            }
             AppEvent::ShowNotification(msg) => self.notification = Some(msg),
             AppEvent::ClearNotification => self.notification = None,
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyEvent;
+    use ratatui::prelude::{Buffer, Rect};
+    use tmux::TmuxBackend;
+    use tmux::fake::FakeBackend;
+
+    // Notifications are cleared from a tokio task
+    #[tokio::test]
+    async fn test_export_selected_session() {
+        let tmux = FakeBackend::new();
+        tmux.create_session("work", Some("/tmp"), &[]).unwrap();
+        tmux.send_keys("work:0.0", &["htop", "Enter"]).unwrap();
+
+        let presets_path = std::env::temp_dir().join(format!("muffin-{}.kdl", std::process::id()));
+        let mut state = AppState::with_fake(tmux, Default::default());
+        state.presets_path = presets_path.to_string_lossy().into_owned();
+        state.selected_session = Some(0);
+
+        let mut menu = SessionsMenu::new(Some(0));
+        menu.handle_event(AppEvent::Key(KeyEvent::from(KeyCode::Char('e'))), &mut state);
+        assert!(state.presets["work"].running);
+        // Exporting again would shadow the preset we just saved
+        menu.handle_event(AppEvent::Key(KeyEvent::from(KeyCode::Char('e'))), &mut state);
+
+        let saved = std::fs::read_to_string(&presets_path).unwrap();
+        let _ = std::fs::remove_file(&presets_path);
+        assert_eq!(saved.matches("session ").count(), 1);
+
+        let presets = parser::parse_config(&saved).unwrap();
+        assert!(matches!(
+            &presets["work"].windows[0].layout,
+            tmux::LayoutNode::Pane { cwd, command: Some(command), .. }
+                if cwd == "/tmp" && command == "htop"
+        ));
+    }
+
+    #[test]
+    fn test_render_preview() {
+        let tmux = FakeBackend::new();
+        tmux.create_session("work", Some("/tmp"), &[]).unwrap();
+        tmux.rename_window("work:0", "editor").unwrap();
+        tmux.new_window("work", "logs", "/tmp", &[]).unwrap();
+        tmux.send_keys("work:0.0", &["nvim", "Enter"]).unwrap();
+        tmux.create_session("play", Some("/tmp"), &[]).unwrap();
+        tmux.send_keys("play:0.0", &["htop", "Enter"]).unwrap();
+
+        let mut state = AppState::with_fake(tmux.clone(), Default::default());
+        state.selected_session = Some(0);
+        let mut menu = SessionsMenu::new(Some(0));
+        menu.refresh(&mut state);
+
+        let render = |menu: &mut SessionsMenu, state: &mut AppState| {
+            let area = Rect::new(0, 0, 100, 24);
+            let mut buf = Buffer::empty(area);
+            menu.render(area, &mut buf, state);
+            buf.content().iter().map(|cell| cell.symbol()).collect::<String>()
+        };
+
+        let text = render(&mut menu, &mut state);
+        assert!(text.contains(" work "));
+        assert!(text.contains("0:editor 1:logs"));
+        assert!(text.contains("$ nvim"));
+
+        // It follows the selection around
+        menu.handle_event(AppEvent::Key(KeyEvent::from(KeyCode::Down)), &mut state);
+        let text = render(&mut menu, &mut state);
+        assert!(text.contains(" play "));
+        assert!(text.contains("$ htop"));
+        assert!(!text.contains("$ nvim"));
+
+        // Pressing keys doesn't capture it again, but a tick does
+        tmux.send_keys("play:0.0", &["q", "Enter"]).unwrap();
+        menu.handle_event(AppEvent::Key(KeyEvent::from(KeyCode::Null)), &mut state);
+        assert!(!render(&mut menu, &mut state).contains("$ q"));
+        menu.handle_event(AppEvent::Tick, &mut state);
+        assert!(render(&mut menu, &mut state).contains("$ q"));
+    }
+
+    #[test]
+    fn test_tree_actions() {
+        let tmux = FakeBackend::new();
+        tmux.create_session("work", Some("/tmp"), &[]).unwrap();
+        tmux.rename_window("work:0", "editor").unwrap();
+        tmux.new_window("work", "logs", "/tmp", &[]).unwrap();
+        tmux.create_session("play", Some("/tmp"), &[]).unwrap();
+        let names = |session: &str| -> Vec<String> {
+            let windows = tmux.list_windows(session).unwrap();
+            windows.into_iter().map(|w| w.name).collect()
+        };
+
+        let mut state = AppState::with_fake(tmux.clone(), Default::default());
+        state.selected_session = Some(0);
+        let mut menu = SessionsMenu::new(Some(0));
+        let area = Rect::new(0, 0, 100, 24);
+        let mut press = |code, state: &mut AppState| {
+            menu.handle_event(AppEvent::Key(KeyEvent::from(code)), state);
+            state.refresh().unwrap();
+            menu.refresh(state);
+            menu.render(area, &mut Buffer::empty(area), state);
+        };
+        press(KeyCode::Null, &mut state);
+
+        // Unfold `work` and go down to its first window
+        press(KeyCode::Char('l'), &mut state);
+        press(KeyCode::Char('j'), &mut state);
+        assert!(matches!(&state.selected_item, Some(TreeItem::Window(0, w)) if w.name == "editor"));
+
+        // It stays selected as it moves down
+        press(KeyCode::Char('J'), &mut state);
+        assert_eq!(names("work"), ["logs", "editor"]);
+        assert!(matches!(&state.selected_item, Some(TreeItem::Window(0, w)) if w.name == "editor"));
+
+        // Pick it up and drop it into `play`
+        press(KeyCode::Char('m'), &mut state);
+        press(KeyCode::Char('j'), &mut state);
+        assert_eq!(state.selected_session, Some(1));
+        press(KeyCode::Char('m'), &mut state);
+        assert_eq!(names("work"), ["logs"]);
+        assert_eq!(names("play"), ["shell", "editor"]);
+
+        // Folding a window that has nothing to fold goes up to its session
+        press(KeyCode::Char('k'), &mut state);
+        press(KeyCode::Char('h'), &mut state);
+        assert_eq!(state.selected_session, Some(0));
+        assert_eq!(state.selected_item, None);
+    }
+
+    #[test]
+    fn test_refresh_keeps_selection() {
+        let tmux = FakeBackend::new();
+        for name in ["b", "c", "d"] {
+            tmux.create_session(name, Some("/tmp"), &[]).unwrap();
+        }
+
+        let mut state = AppState::with_fake(tmux.clone(), Default::default());
+        let mut menu = SessionsMenu::new(Some(1));
+        menu.refresh(&mut state);
+        let selected =
+            |state: &AppState| state.selected_session.map(|s| state.sessions[s].name.clone());
+        assert_eq!(selected(&state).as_deref(), Some("c"));
+
+        // Sessions killed outside of muffin pull it up a row, and it follows
+        tmux.kill_session("b").unwrap();
+        state.refresh().unwrap();
+        menu.refresh(&mut state);
+        assert_eq!(selected(&state).as_deref(), Some("c"));
+
+        // Once it's gone, whatever took its row is selected
+        tmux.kill_session("c").unwrap();
+        state.refresh().unwrap();
+        menu.refresh(&mut state);
+        assert_eq!(selected(&state).as_deref(), Some("d"));
+    }
+
+    #[test]
+    fn test_filter_sessions() {
+        let tmux = FakeBackend::new();
+        for name in ["api", "dotfiles", "web"] {
+            tmux.create_session(name, Some("/tmp"), &[]).unwrap();
+        }
+
+        let mut state = AppState::with_fake(tmux, Default::default());
+        state.selected_session = Some(0);
+        let mut menu = SessionsMenu::new(Some(0));
+        menu.refresh(&mut state);
+        let area = Rect::new(0, 0, 100, 24);
+        let mut press = |code, state: &mut AppState| {
+            menu.handle_event(AppEvent::Key(KeyEvent::from(code)), state);
+            let mut buf = Buffer::empty(area);
+            menu.render(area, &mut buf, state);
+            buf.content().iter().map(|cell| cell.symbol()).collect::<String>()
+        };
+        press(KeyCode::Null, &mut state);
+
+        // Typing goes to the filter rather than moving around
+        for c in "/wb".chars() {
+            press(KeyCode::Char(c), &mut state);
+        }
+        let text = press(KeyCode::Char('j'), &mut state);
+        assert!(text.contains("/wbj"));
+        assert_eq!(state.selected_session, None);
+
+        let text = press(KeyCode::Backspace, &mut state);
+        assert!(!text.contains("dotfiles"));
+        let selected = state.selected_session.map(|s| state.sessions[s].name.as_str());
+        assert_eq!(selected, Some("web"));
+
+        // Stopping the filter keeps it, clearing it keeps the selection
+        press(KeyCode::Enter, &mut state);
+        let text = press(KeyCode::Esc, &mut state);
+        assert!(text.contains("dotfiles"));
+        let selected = state.selected_session.map(|s| state.sessions[s].name.as_str());
+        assert_eq!(selected, Some("web"));
+
+        // Keys the menu doesn't use start filtering without `/`
+        let text = press(KeyCode::Char('p'), &mut state);
+        assert!(text.contains("/p"));
+        let selected = state.selected_session.map(|s| state.sessions[s].name.as_str());
+        assert_eq!(selected, Some("api"));
+    }
+}
@@ -0,0 +1,106 @@
+use ansi_to_tui::IntoText;
+use ratatui::{
+    prelude::{Buffer, Constraint, Layout, Rect},
+    style::{Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{Block, Paragraph, Widget},
+};
+use tmux::{TmuxBackend, WindowInfo};
+
+/// Snapshot of a session's windows, and of what one of its panes shows
+pub struct Preview {
+    session: String,
+    /// Pane that was captured, or the window or session whose active pane it was
+    pub target: String,
+    windows: Vec<WindowInfo>,
+    screen: Text<'static>,
+}
+
+impl Preview {
+    /// Takes a snapshot of `session` and its `target` pane. If tmux can't show the pane, the
+    /// preview says why instead.
+    pub fn capture(tmux: &dyn TmuxBackend, session: &str, target: &str) -> Self {
+        let screen = match tmux.capture_pane(target) {
+            Ok(screen) => to_text(&screen),
+            Err(e) => Text::from(e.to_string().red()),
+        };
+        Self {
+            session: session.to_string(),
+            target: target.to_string(),
+            windows: tmux.list_windows(session).unwrap_or_default(),
+            screen,
+        }
+    }
+}
+
+/// Turns what `capture-pane -e` printed into styled text, without the empty rows below the
+/// prompt
+fn to_text(screen: &str) -> Text<'static> {
+    let screen = screen.trim_end();
+    screen
+        .into_text()
+        .unwrap_or_else(|_| Text::raw(screen.to_string()))
+}
+
+impl Widget for &Preview {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered()
+            .border_style(Style::new().dark_gray())
+            .title(Line::from(format!(" {} ", self.session).cyan()));
+        let inner_area = block.inner(area);
+        block.render(area, buf);
+
+        let [windows_area, screen_area] =
+            Layout::vertical([Constraint::Length(1), Constraint::Fill(1)])
+                .spacing(1)
+                .horizontal_margin(1)
+                .areas(inner_area);
+
+        // Render the windows, highlighting the one the pane belongs to
+        {
+            let windows: Vec<Span> = self
+                .windows
+                .iter()
+                .map(|w| {
+                    let label = format!("{}:{} ", w.index, w.name);
+                    if w.active {
+                        label.cyan().bold()
+                    } else {
+                        label.dark_gray()
+                    }
+                })
+                .collect();
+            Line::from(windows).render(windows_area, buf);
+        }
+
+        // Render the bottom of the pane, which is where the prompt usually is
+        {
+            let hidden = self
+                .screen
+                .lines
+                .len()
+                .saturating_sub(screen_area.height as usize);
+            Paragraph::new(self.screen.clone())
+                .scroll((hidden as u16, 0))
+                .render(screen_area, buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::style::Color;
+
+    #[test]
+    fn test_to_text() {
+        let text = to_text("$ ls\n\x1b[31mCargo.toml\x1b[0m  src\n$ \n\n\n");
+
+        assert_eq!(text.lines.len(), 3);
+        let spans = &text.lines[1].spans;
+        assert_eq!(spans[0].content, "Cargo.toml");
+        assert_eq!(spans[0].style.fg, Some(Color::Red));
+        assert_eq!(spans[1].content, "  src");
+        assert_eq!(spans[1].style.fg, Some(Color::Reset));
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+
+use tmux::{PaneInfo, Session, TmuxBackend, WindowInfo};
+
+/// One row of the sessions tree. Sessions are referred to by their index in
+/// [`AppState::sessions`](crate::app::driver::AppState::sessions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeItem {
+    Session(usize),
+    Window(usize, WindowInfo),
+    Pane(usize, WindowInfo, PaneInfo),
+}
+
+impl TreeItem {
+    /// Index of the session the item belongs to
+    pub fn session(&self) -> usize {
+        match self {
+            TreeItem::Session(s) | TreeItem::Window(s, _) | TreeItem::Pane(s, _, _) => *s,
+        }
+    }
+
+    /// What tmux commands can target the item with. Windows and panes go by id, so that
+    /// renaming or moving them around doesn't get in the way.
+    pub fn target(&self, sessions: &[Session]) -> String {
+        match self {
+            TreeItem::Session(s) => sessions[*s].name.clone(),
+            TreeItem::Window(_, window) => window.id.clone(),
+            TreeItem::Pane(_, _, pane) => pane.id.clone(),
+        }
+    }
+}
+
+/// Sessions, with the windows and panes of whichever ones are unfolded
+#[derive(Debug, Default)]
+pub struct Tree {
+    /// Ids of the unfolded sessions and windows
+    expanded: HashSet<String>,
+    items: Vec<TreeItem>,
+}
+
+impl Tree {
+    pub fn items(&self) -> &[TreeItem] {
+        &self.items
+    }
+
+    /// Lists the sessions at the indices in `order`, and the windows and panes of everything
+    /// that's unfolded, again. Whatever tmux can't list, like a session that just went away,
+    /// stays folded.
+    pub fn rebuild(&mut self, tmux: &dyn TmuxBackend, sessions: &[Session], order: &[usize]) {
+        self.items.clear();
+        for &s in order {
+            let session = &sessions[s];
+            self.items.push(TreeItem::Session(s));
+            if !self.expanded.contains(&session.id) {
+                continue;
+            }
+
+            for window in tmux.list_windows(&session.id).unwrap_or_default() {
+                let expanded = self.expanded.contains(&window.id);
+                self.items.push(TreeItem::Window(s, window.clone()));
+                if !expanded {
+                    continue;
+                }
+
+                for pane in tmux.list_panes(&window.id).unwrap_or_default() {
+                    self.items.push(TreeItem::Pane(s, window.clone(), pane));
+                }
+            }
+        }
+    }
+
+    pub fn is_expanded(&self, item: &TreeItem, sessions: &[Session]) -> bool {
+        match item {
+            TreeItem::Session(s) => self.expanded.contains(&sessions[*s].id),
+            TreeItem::Window(_, window) => self.expanded.contains(&window.id),
+            TreeItem::Pane(..) => false,
+        }
+    }
+
+    /// Unfolds `item`, returning whether there was anything to unfold
+    pub fn expand(&mut self, item: &TreeItem, sessions: &[Session]) -> bool {
+        match item {
+            TreeItem::Session(s) => self.expanded.insert(sessions[*s].id.clone()),
+            TreeItem::Window(_, window) => self.expanded.insert(window.id.clone()),
+            TreeItem::Pane(..) => false,
+        }
+    }
+
+    /// Folds `item` away, returning whether it was unfolded
+    pub fn collapse(&mut self, item: &TreeItem, sessions: &[Session]) -> bool {
+        match item {
+            TreeItem::Session(s) => self.expanded.remove(&sessions[*s].id),
+            TreeItem::Window(_, window) => self.expanded.remove(&window.id),
+            TreeItem::Pane(..) => false,
+        }
+    }
+
+    /// Row of the session or window `item` belongs to
+    pub fn parent(&self, item: &TreeItem) -> Option<usize> {
+        self.items.iter().position(|other| match (item, other) {
+            (TreeItem::Window(s, _), TreeItem::Session(other)) => s == other,
+            (TreeItem::Pane(_, window, _), TreeItem::Window(_, other)) => window.id == other.id,
+            _ => false,
+        })
+    }
+
+    /// The window or pane right before (`offset` -1) or after (1) `item` under the same
+    /// parent, if there is one
+    pub fn sibling(&self, item: &TreeItem, offset: isize) -> Option<&TreeItem> {
+        let siblings: Vec<&TreeItem> = self
+            .items
+            .iter()
+            .filter(|other| match (item, other) {
+                (TreeItem::Window(s, _), TreeItem::Window(other, _)) => s == other,
+                (TreeItem::Pane(_, window, _), TreeItem::Pane(_, other, _)) => {
+                    window.id == other.id
+                }
+                _ => false,
+            })
+            .collect();
+        let position = siblings.iter().position(|other| *other == item)?;
+        siblings.get(position.checked_add_signed(offset)?).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tmux::SplitDirection;
+    use tmux::fake::FakeBackend;
+
+    #[test]
+    fn test_tree() {
+        let tmux = FakeBackend::new();
+        tmux.create_session("work", Some("/tmp"), &[]).unwrap();
+        tmux.new_window("work", "logs", "/tmp", &[]).unwrap();
+        tmux.split_window("work:logs", SplitDirection::Vertical, "/tmp", &[])
+            .unwrap();
+        tmux.create_session("play", Some("/tmp"), &[]).unwrap();
+        let sessions = tmux.list_sessions().unwrap();
+
+        let mut tree = Tree::default();
+        tree.rebuild(&tmux, &sessions, &[0, 1]);
+        assert_eq!(tree.items(), [TreeItem::Session(0), TreeItem::Session(1)]);
+
+        assert!(tree.expand(&TreeItem::Session(0), &sessions));
+        tree.rebuild(&tmux, &sessions, &[0, 1]);
+        let logs = tree.items()[2].clone();
+        assert!(matches!(&logs, TreeItem::Window(0, w) if w.name == "logs"));
+        assert!(tree.expand(&logs, &sessions));
+        tree.rebuild(&tmux, &sessions, &[0, 1]);
+
+        let rows: Vec<String> = tree
+            .items()
+            .iter()
+            .map(|item| match item {
+                TreeItem::Session(s) => sessions[*s].name.clone(),
+                TreeItem::Window(_, w) => format!("  {}", w.name),
+                TreeItem::Pane(_, _, p) => format!("    {}", p.index),
+            })
+            .collect();
+        assert_eq!(rows, ["work", "  shell", "  logs", "    0", "    1", "play"]);
+
+        let pane = &tree.items()[4];
+        assert_eq!(tree.parent(pane), Some(2));
+        assert_eq!(tree.sibling(pane, -1), Some(&tree.items()[3]));
+        assert_eq!(tree.sibling(pane, 1), None);
+        assert_eq!(tree.sibling(&logs, -1), Some(&tree.items()[1]));
+        assert_eq!(pane.target(&sessions), "%5");
+
+        // Folding a session hides its windows, but they stay unfolded underneath
+        assert!(tree.collapse(&TreeItem::Session(0), &sessions));
+        tree.rebuild(&tmux, &sessions, &[0, 1]);
+        assert_eq!(tree.items().len(), 2);
+        assert!(tree.is_expanded(&logs, &sessions));
+
+        // Rows keep pointing at the sessions they show, whatever order they're listed in
+        tree.rebuild(&tmux, &sessions, &[1]);
+        assert_eq!(tree.items(), [TreeItem::Session(1)]);
+    }
+}
@@ -6,7 +6,7 @@ use ratatui::{
     text::{Line, Span},
 };
 
-use crate::app::driver::{AppEvent, EventHandler};
+use crate::app::driver::{AppEvent, AppState, EventHandler};
 
 #[allow(unused)]
 /// helper function to create a centered rect using up certain percentage of the available rect `r`
@@ -60,3 +60,26 @@ pub fn send_timed_notification(event_handler: &EventHandler, msg: String) {
         let _ = tx.send(AppEvent::ClearNotification);
     });
 }
+
+/// Moves the client over to session `name`, or attaches to it and exits when muffin isn't
+/// running inside tmux
+pub fn switch_to_session(state: &mut AppState, name: &str) {
+    if std::env::var("TMUX").is_ok() {
+        // Muffin is running inside tmux, so we can switch clients
+        if let Err(e) = state.tmux.switch_session(name) {
+            send_timed_notification(
+                &state.event_handler,
+                format!("Failed to switch session: {}", e),
+            );
+        }
+    } else {
+        // Muffin is running outside tmux, so we need to attach and exit
+        match state.tmux.attach_session(name) {
+            Ok(_) => state.exit = true, // Exit muffin to let tmux take over
+            Err(e) => send_timed_notification(
+                &state.event_handler,
+                format!("Failed to attach to session: {}", e),
+            ),
+        };
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use parser::ConfigError;
+use tmux::{ParamError, Preset, Session, TmuxBackend};
+
+/// A simple TUI for managing tmux sessions. Without a command, the TUI starts.
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    after_help = "Exit codes: 0 on success, 1 when tmux fails, 2 for bad arguments, 3 when the \
+                  session or preset doesn't exist and 4 when the presets don't load."
+)]
+pub struct Cli {
+    /// KDL file with session presets, or a directory of them
+    #[arg(
+        short,
+        long,
+        global = true,
+        value_name = "PATH",
+        default_value = "~/.config/muffin/presets.kdl"
+    )]
+    pub presets: String,
+    /// Name of the tmux server socket to use
+    #[arg(short = 'L', long, global = true, value_name = "NAME", conflicts_with = "socket_path")]
+    pub socket_name: Option<String>,
+    /// Path to the tmux server socket to use
+    #[arg(short = 'S', long, global = true, value_name = "PATH")]
+    pub socket_path: Option<String>,
+    /// Same as `launch`, from before there were subcommands
+    #[arg(short, long, hide = true, value_name = "PRESET", conflicts_with = "export")]
+    launch: Option<String>,
+    #[arg(long = "set", hide = true, value_parser = parse_param, requires = "launch")]
+    params: Vec<(String, String)>,
+    #[arg(long, hide = true, requires = "launch")]
+    reconcile: bool,
+    /// Same as `export`, from before there were subcommands
+    #[arg(short, long, hide = true, value_name = "SESSION")]
+    export: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// Takes the command to run out, which `-l` and `-e` stand in for when there's none
+    pub fn take_command(&mut self) -> Option<Command> {
+        if let Some(command) = self.command.take() {
+            return Some(command);
+        }
+        if let Some(preset) = self.launch.take() {
+            return Some(Command::Launch {
+                preset,
+                params: std::mem::take(&mut self.params),
+                reconcile: self.reconcile,
+                detached: false,
+            });
+        }
+        self.export.take().map(|session| Command::Export { session })
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// List the running sessions
+    Ls {
+        /// Print the sessions as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the presets, and which of them are running
+    Presets {
+        /// Print the presets as JSON, layouts included
+        #[arg(long)]
+        json: bool,
+    },
+    /// Launch a preset, or go to its session if it's running already
+    Launch {
+        preset: String,
+        /// Value for one of the preset's parameters
+        #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_param)]
+        params: Vec<(String, String)>,
+        /// Add whatever the preset's session is missing
+        #[arg(long)]
+        reconcile: bool,
+        /// Don't switch to or attach to the session
+        #[arg(short, long)]
+        detached: bool,
+    },
+    /// Kill a session
+    Kill { session: String },
+    /// Rename a session
+    Rename { old: String, new: String },
+    /// Switch to a session, or attach to it outside of tmux
+    Switch { session: String },
+    /// Check that the presets load, and warn about anything that looks off in them
+    Validate,
+    /// Print a running session as a KDL preset
+    Export { session: String },
+}
+
+fn parse_param(param: &str) -> Result<(String, String), String> {
+    let (key, value) = param
+        .split_once('=')
+        .ok_or(format!("expected key=value, not '{param}'"))?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Why a command failed, which decides what muffin exits with
+#[derive(Debug)]
+pub enum Failure {
+    Tmux(tmux::Error),
+    /// The values given with `--set` don't fit the preset
+    Params(ParamError),
+    /// There's no session or preset by the name that was given
+    NotFound(String),
+    /// The presets couldn't be loaded
+    Invalid(ConfigError),
+}
+
+impl Failure {
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            Failure::Params(_) => 2,
+            Failure::Tmux(tmux::Error::SessionNotFound(_)) | Failure::NotFound(_) => 3,
+            Failure::Invalid(_) => 4,
+            Failure::Tmux(_) => 1,
+        })
+    }
+
+    /// Prints the failure to stderr, with every problem in the presets pointed out in place
+    pub fn report(&self) {
+        match self {
+            Failure::Invalid(e) => {
+                for report in e.reports() {
+                    eprintln!("{report:?}");
+                }
+            }
+            _ => eprintln!("muffin: {self}"),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Tmux(e) => write!(f, "{e}"),
+            Failure::Params(e) => write!(f, "{e}"),
+            Failure::NotFound(what) => write!(f, "{what} doesn't exist"),
+            Failure::Invalid(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<tmux::Error> for Failure {
+    fn from(e: tmux::Error) -> Self {
+        Failure::Tmux(e)
+    }
+}
+
+impl From<ParamError> for Failure {
+    fn from(e: ParamError) -> Self {
+        Failure::Params(e)
+    }
+}
+
+/// Runs `command` against `tmux`, printing whatever it lists to `out`
+pub fn run(
+    command: Command,
+    tmux: &dyn TmuxBackend,
+    presets_path: &Path,
+    out: &mut impl Write,
+) -> Result<(), Failure> {
+    match command {
+        Command::Ls { json: true } => print_json(out, &list_sessions(tmux)?),
+        Command::Ls { json: false } => {
+            for session in list_sessions(tmux)? {
+                let attached = if session.is_attached() { " (attached)" } else { "" };
+                let windows = plural(session.windows, "window");
+                let _ = writeln!(out, "{}: {windows}{attached}", session.name);
+            }
+        }
+        Command::Presets { json } => {
+            let mut presets = load_presets(presets_path)?;
+            let sessions = list_sessions(tmux)?;
+            for preset in presets.values_mut() {
+                preset.running = sessions.iter().any(|s| s.preset_name() == preset.name);
+            }
+            if json {
+                let presets: Vec<&Preset> = presets.values().collect();
+                print_json(out, &presets);
+                return Ok(());
+            }
+
+            for preset in presets.values() {
+                let running = if preset.running { " (running)" } else { "" };
+                let windows = plural(preset.windows.len(), "window");
+                let _ = writeln!(out, "{}: {windows}{running}", preset.name);
+            }
+        }
+        Command::Launch {
+            preset,
+            params,
+            reconcile,
+            detached,
+        } => {
+            let presets = load_presets(presets_path)?;
+            let preset = presets
+                .get(&preset)
+                .ok_or(Failure::NotFound(format!("preset `{preset}`")))?
+                .with_params(&params.into_iter().collect())?;
+            // Launching a preset that's running already just goes back to it
+            if reconcile {
+                tmux::reconcile_preset(tmux, &preset)?;
+            } else {
+                tmux::ensure_preset(tmux, &preset)?;
+            }
+            if !detached {
+                switch_to(tmux, &preset.name)?;
+            }
+        }
+        Command::Kill { session } => {
+            let found = find_session(tmux, &session)?;
+            // Sessions spawned from a preset get to clean up after themselves, but presets that
+            // don't load shouldn't keep anyone from killing a session
+            let presets = parser::load_presets(presets_path).unwrap_or_default();
+            match found.preset(&presets) {
+                Some(preset) => tmux::kill_preset(tmux, &found.id, &preset)?,
+                None => tmux.kill_session(&found.id)?,
+            }
+        }
+        Command::Rename { old, new } => {
+            find_session(tmux, &old)?;
+            tmux.rename_session(&old, &new)?;
+        }
+        Command::Switch { session } => {
+            find_session(tmux, &session)?;
+            switch_to(tmux, &session)?;
+        }
+        Command::Validate => {
+            let (presets, error) = parser::lint_presets(presets_path);
+            if error.has_errors() {
+                return Err(Failure::Invalid(error));
+            }
+            for report in error.reports() {
+                eprintln!("{report:?}");
+            }
+            let summary = match error.problems().len() {
+                0 => "fine".to_string(),
+                warnings => format!("with {}", plural(warnings, "warning")),
+            };
+            let _ = writeln!(
+                out,
+                "{} in {} loaded {summary}",
+                plural(presets.len(), "preset"),
+                presets_path.display()
+            );
+        }
+        Command::Export { session } => {
+            find_session(tmux, &session)?;
+            let preset = tmux::export_session(tmux, &session)?;
+            let _ = write!(out, "{}", parser::serialize_preset(&preset));
+        }
+    }
+    Ok(())
+}
+
+fn print_json(out: &mut impl Write, value: &impl serde::Serialize) {
+    let _ = serde_json::to_writer(&mut *out, value);
+    let _ = writeln!(out);
+}
+
+/// The running sessions, of which there are none when tmux isn't running at all
+fn list_sessions(tmux: &dyn TmuxBackend) -> Result<Vec<Session>, Failure> {
+    match tmux.list_sessions() {
+        Ok(sessions) => Ok(sessions),
+        Err(tmux::Error::NoServer) => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Looks `name` up by its exact name, as tmux would settle for a session it merely starts
+fn find_session(tmux: &dyn TmuxBackend, name: &str) -> Result<Session, Failure> {
+    list_sessions(tmux)?
+        .into_iter()
+        .find(|s| s.name == name)
+        .ok_or(Failure::NotFound(format!("session `{name}`")))
+}
+
+fn load_presets(path: &Path) -> Result<BTreeMap<String, Preset>, Failure> {
+    parser::load_presets(path).map_err(Failure::Invalid)
+}
+
+/// Switches the client over to session `name` from inside tmux, or attaches to it
+fn switch_to(tmux: &dyn TmuxBackend, name: &str) -> Result<(), tmux::Error> {
+    if std::env::var("TMUX").is_ok() {
+        tmux.switch_session(name)
+    } else {
+        tmux.attach_session(name)
+    }
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {noun}"),
+        n => format!("{n} {noun}s"),
+    }
+}
+
+/// Where the presets are read from, with `~` and environment variables expanded
+pub fn presets_path(path: &str) -> Result<PathBuf, String> {
+    shellexpand::full(path)
+        .map(|path| PathBuf::from(path.as_ref()))
+        .map_err(|e| format!("Failed to expand presets path: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tmux::fake::FakeBackend;
+
+    fn run_cli(args: &[&str], tmux: &FakeBackend, presets: &Path) -> (Result<(), Failure>, String) {
+        let mut cli = Cli::try_parse_from([&["muffin"], args].concat()).unwrap();
+        let mut out = Vec::new();
+        let result = run(cli.take_command().unwrap(), tmux, presets, &mut out);
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_session_commands() {
+        let tmux = FakeBackend::new();
+        let presets = Path::new("/nonexistent");
+        let (result, out) = run_cli(&["ls"], &tmux, presets);
+        assert!(result.is_ok());
+        assert_eq!(out, "");
+
+        tmux.create_session("work", Some("/tmp"), &[]).unwrap();
+        tmux.new_window("work", "logs", "/tmp", &[]).unwrap();
+        tmux.create_session("play", Some("/tmp"), &[]).unwrap();
+        let (_, out) = run_cli(&["ls"], &tmux, presets);
+        assert_eq!(out, "work: 2 windows\nplay: 1 window\n");
+        let (_, out) = run_cli(&["ls", "--json"], &tmux, presets);
+        let sessions: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(sessions[0]["name"], "work");
+        assert_eq!(sessions[0]["windows"], 2);
+        assert_eq!(sessions[1]["attached"], 0);
+
+        run_cli(&["rename", "play", "games"], &tmux, presets).0.unwrap();
+        run_cli(&["kill", "games"], &tmux, presets).0.unwrap();
+        let (_, out) = run_cli(&["ls"], &tmux, presets);
+        assert_eq!(out, "work: 2 windows\n");
+
+        // Close isn't good enough
+        let (result, _) = run_cli(&["kill", "wor"], &tmux, presets);
+        let failure = result.unwrap_err();
+        assert_eq!(failure.to_string(), "session `wor` doesn't exist");
+        assert_eq!(failure.exit_code(), ExitCode::from(3));
+
+        let (_, out) = run_cli(&["export", "work"], &tmux, presets);
+        assert!(out.contains(r#"window name="logs""#));
+    }
+
+    #[test]
+    fn test_preset_commands() {
+        let dir = std::env::temp_dir().join(format!("muffin-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let presets = dir.join("presets.kdl");
+        std::fs::write(
+            &presets,
+            r#"session name="api" cwd="/tmp" {
+                param "port" default="8080"
+                window name="server" { pane command="serve --port {{port}}"; }
+            }
+            session name="web" cwd="/tmp" { window name="main" { pane; } }"#,
+        )
+        .unwrap();
+
+        let tmux = FakeBackend::new();
+        // `serve` isn't a real program, but that's only a warning
+        let (result, out) = run_cli(&["validate"], &tmux, &presets);
+        assert!(result.is_ok());
+        assert!(out.starts_with("2 presets in"));
+        assert!(out.ends_with("loaded with 1 warning\n"));
+
+        run_cli(&["launch", "api", "--set", "port=3000", "-d"], &tmux, &presets)
+            .0
+            .unwrap();
+        let session = tmux.session("api").unwrap();
+        assert_eq!(session.windows[0].panes[0].keys, ["serve --port 3000", "Enter"]);
+        let (_, out) = run_cli(&["presets"], &tmux, &presets);
+        assert_eq!(out, "api: 1 window (running)\nweb: 1 window\n");
+
+        let (_, out) = run_cli(&["presets", "--json"], &tmux, &presets);
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(json[0]["running"], true);
+        assert_eq!(json[0]["source"], presets.to_str().unwrap());
+        let layout = &json[0]["windows"][0]["layout"];
+        assert_eq!(layout["type"], "pane");
+        assert_eq!(layout["command"], "serve --port {{port}}");
+        assert_eq!(layout["size"], serde_json::json!({ "weight": 1 }));
+        assert_eq!(json[1]["running"], false);
+
+        let (result, _) = run_cli(&["launch", "api", "--set", "host=x"], &tmux, &presets);
+        assert_eq!(result.unwrap_err().exit_code(), ExitCode::from(2));
+        let (result, _) = run_cli(&["launch", "nope"], &tmux, &presets);
+        assert_eq!(result.unwrap_err().exit_code(), ExitCode::from(3));
+
+        std::fs::write(
+            &presets,
+            "session name=\"api\" { windw; }\nsession cwd=1",
+        )
+        .unwrap();
+        let (result, _) = run_cli(&["validate"], &tmux, &presets);
+        let _ = std::fs::remove_dir_all(&dir);
+        let failure = result.unwrap_err();
+        assert_eq!(failure.exit_code(), ExitCode::from(4));
+        // Every problem is reported, not just the first one
+        assert_eq!(failure.to_string().lines().count(), 3);
+    }
+
+    #[test]
+    fn test_kill_preset() {
+        let dir = std::env::temp_dir().join(format!("muffin-cli-kill-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let presets = dir.join("presets.kdl");
+        let preset = |on_kill: &str| {
+            let preset = format!(
+                r#"session name="db" cwd="{}" {{
+                    on_kill "{on_kill}"
+                    window name="main" {{ pane; }}
+                }}"#,
+                dir.display()
+            );
+            std::fs::write(&presets, preset).unwrap();
+        };
+
+        let tmux = FakeBackend::new();
+        preset("touch killed");
+        run_cli(&["launch", "db", "-d"], &tmux, &presets).0.unwrap();
+        run_cli(&["kill", "db"], &tmux, &presets).0.unwrap();
+        assert!(tmux.session("db").is_none());
+        assert!(dir.join("killed").exists());
+
+        // The session is gone either way, but the failed hook isn't a success
+        preset("exit 1");
+        run_cli(&["launch", "db", "-d"], &tmux, &presets).0.unwrap();
+        let (result, _) = run_cli(&["kill", "db"], &tmux, &presets);
+        let _ = std::fs::remove_dir_all(&dir);
+        let failure = result.unwrap_err();
+        assert!(matches!(failure, Failure::Tmux(tmux::Error::Hook { .. })));
+        assert_eq!(failure.exit_code(), ExitCode::from(1));
+        assert!(tmux.session("db").is_none());
+    }
+
+    #[test]
+    fn test_kill_parameterized_preset() {
+        let dir = std::env::temp_dir().join(format!("muffin-cli-params-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("api")).unwrap();
+        let presets = dir.join("presets.kdl");
+        std::fs::write(
+            &presets,
+            r#"session name="db-{{name}}" cwd="{{dir}}/{{name}}" {
+                param "dir"
+                param "name"
+                on_kill "touch killed"
+                window name="main" { pane; }
+            }"#,
+        )
+        .unwrap();
+
+        let tmux = FakeBackend::new();
+        let dir_param = format!("dir={}", dir.display());
+        let launch = ["launch", "db-{{name}}", "--set", &dir_param, "--set", "name=api", "-d"];
+        run_cli(&launch, &tmux, &presets).0.unwrap();
+        let (_, out) = run_cli(&["presets"], &tmux, &presets);
+        assert_eq!(out, "db-{{name}}: 1 window (running)\n");
+
+        // The hook runs in the directory the session was launched with
+        run_cli(&["rename", "db-api", "db"], &tmux, &presets).0.unwrap();
+        run_cli(&["kill", "db"], &tmux, &presets).0.unwrap();
+        let killed = dir.join("api/killed").exists();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(killed);
+        assert!(tmux.session("db").is_none());
+    }
+
+    #[test]
+    fn test_old_flags() {
+        let parse = |args: &[&str]| {
+            let mut cli = Cli::try_parse_from([&["muffin"], args].concat()).unwrap();
+            cli.take_command()
+        };
+
+        assert!(matches!(
+            parse(&["-l", "api", "--set", "port=8080", "--reconcile"]),
+            Some(Command::Launch { preset, params, reconcile: true, detached: false })
+                if preset == "api" && params == [("port".to_string(), "8080".to_string())]
+        ));
+        assert!(matches!(
+            parse(&["--launch", "api"]),
+            Some(Command::Launch { preset, reconcile: false, .. }) if preset == "api"
+        ));
+        assert!(matches!(
+            parse(&["--export", "work"]),
+            Some(Command::Export { session }) if session == "work"
+        ));
+        assert!(matches!(
+            parse(&["-e", "work"]),
+            Some(Command::Export { session }) if session == "work"
+        ));
+        assert!(parse(&[]).is_none());
+        assert!(Cli::try_parse_from(["muffin", "--set", "port=8080"]).is_err());
+    }
+}
@@ -1,89 +1,87 @@
+use std::collections::BTreeMap;
+use std::process::ExitCode;
+
 use app::driver::App;
 use app::config;
+use clap::Parser;
+use cli::Cli;
+use tmux::{CommandBackend, Server, TmuxBackend};
 mod app;
+mod cli;
 
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), String> {
-    let mut args = std::env::args();
-    let arg0 = args.next().unwrap();
-
-    let mut presets_path = "~/.config/muffin/presets.kdl".to_string();
-    let mut launch_preset = None;
+async fn main() -> Result<ExitCode, String> {
+    let mut cli = Cli::parse();
+    let command = cli.take_command();
 
-    while let Some(arg) = args.next() {
-        match arg.as_str() {
-            "--presets" | "-p" => {
-                presets_path = args.next().ok_or(format!("{arg} expects a path"))?;
-            }
-            "--help" | "-h" => {
-                eprintln!(
-                    r"
-Usage: {arg0} [OPTIONS]
+    let config = config::load_config().unwrap_or_default();
 
-OPTIONS:
-    -p, --presets <FILE>    Path to KDL file with session presets
-    -l, --launch <PRESET>   Launch a preset
-    -h, --help              Print help
-                        ",
-                );
-                std::process::exit(1);
-            }
-            "--launch" | "-l" => {
-                launch_preset = Some(args.next().ok_or(format!("{arg} expects a preset name"))?);
-            }
-            x => {
-                eprintln!("Unknown flag or value '{x}'. Run '{arg0} --help' for usage.",);
-                std::process::exit(1);
-            }
+    // Inside tmux, we can only switch clients between sessions of the server we're running in,
+    // so `$TMUX` takes priority over the config file
+    let server = match (cli.socket_name, cli.socket_path) {
+        (Some(name), _) => Some(Server::Name(name)),
+        (_, Some(path)) => {
+            let path = shellexpand::full(&path)
+                .map_err(|e| format!("Failed to expand socket path: {e}"))?;
+            Some(Server::Path(path.to_string().into()))
         }
-    }
-
-    let presets_path = shellexpand::full(&presets_path)
-        .expect("Failed to expand environment variables in path")
-        .to_string();
+        (None, None) => None,
+    };
+    let server = server
+        .or_else(Server::from_env)
+        .or_else(|| config.socket.as_deref().map(Server::from_socket))
+        .unwrap_or_default();
+    let backend = CommandBackend::new(server);
 
-    let presets_str: String = std::fs::read(&presets_path)
-        .expect("Error reading file.")
-        .try_into()
-        .expect("Error parsing file into a string.");
-    let presets = parser::parse_config(&presets_str)?;
+    let presets_path = cli::presets_path(&cli.presets)?;
 
-    if let Some(preset_name) = launch_preset {
-        if let Some(preset) = presets.get(&preset_name) {
-            tmux::spawn_preset(preset)?;
-            if std::env::var("TMUX").is_ok() {
-                tmux::switch_session(&preset.name)?;
-            } else {
-                tmux::attach_session(&preset.name)?;
-            }
-            return Ok(());
-        } else {
-            return Err(format!("Preset '{preset_name}' not found."));
-        }
+    if let Some(command) = command {
+        return Ok(
+            match cli::run(command, &backend, &presets_path, &mut std::io::stdout()) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(failure) => {
+                    failure.report();
+                    failure.exit_code()
+                }
+            },
+        );
     }
 
-    let sessions = match tmux::list_sessions() {
+    // A broken presets file shouldn't lock anyone out of their sessions, so the TUI starts
+    // anyway and shows what's wrong with it
+    let presets = parser::load_presets(&presets_path).map_err(|e| e.to_string());
+
+    let sessions = match backend.list_sessions() {
         Ok(sessions) => sessions,
-        Err(_) => {
-            let config = config::load_config().unwrap_or_default();
-            if let Some(default_preset_name) = config.default_preset {
-                if let Some(preset) = presets.get(&default_preset_name) {
-                    tmux::spawn_preset(preset)?;
-                } else {
-                    tmux::create_session("")?;
+        Err(tmux::Error::NoServer) => {
+            let default_preset = config
+                .default_preset
+                .and_then(|name| presets.as_ref().ok()?.get(&name));
+            match default_preset {
+                Some(preset) => {
+                    let preset = preset
+                        .with_params(&BTreeMap::new())
+                        .map_err(|e| e.to_string())?;
+                    tmux::spawn_preset(&backend, &preset)
                 }
-            } else {
-                tmux::create_session("")?;
+                None => backend.create_session("", None, &[]),
             }
-            tmux::list_sessions()?
+            .map_err(|e| e.to_string())?;
+            backend.list_sessions().map_err(|e| e.to_string())?
         }
+        Err(e) => return Err(e.to_string()),
     };
 
-    let mut app = App::new(sessions, presets, presets_path.to_string());
+    let mut app = App::new(
+        Box::new(backend),
+        sessions,
+        presets,
+        presets_path.display().to_string(),
+    );
 
     let mut terminal = ratatui::init();
     let app_result = app.run(&mut terminal).await;
 
     ratatui::restore();
-    app_result
+    app_result.map(|_| ExitCode::SUCCESS)
 }
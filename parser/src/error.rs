@@ -0,0 +1,259 @@
+use std::fmt;
+
+use kdl::KdlError;
+use miette::{Diagnostic, LabeledSpan, NamedSource, Report, Severity, SourceSpan};
+
+/// One thing wrong with a presets file, and where it is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub message: String,
+    pub span: SourceSpan,
+    /// Short note shown right under the offending code
+    pub label: Option<String>,
+    pub help: Option<String>,
+    /// Warnings point out things that look wrong, but don't stop presets from loading
+    pub severity: Severity,
+    /// Index of the file `span` points into, among the ones the error read. Problems that
+    /// happened before there was anything to read, like a missing presets file, have none.
+    pub(crate) source: Option<usize>,
+}
+
+impl Problem {
+    pub(crate) fn new(message: impl Into<String>, span: SourceSpan) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            label: None,
+            help: None,
+            severity: Severity::Error,
+            source: None,
+        }
+    }
+
+    pub(crate) fn warning(message: impl Into<String>, span: SourceSpan) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new(message, span)
+        }
+    }
+
+    pub fn is_warning(&self) -> bool {
+        self.severity == Severity::Warning
+    }
+
+    pub(crate) fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub(crate) fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Problem {}
+
+impl Diagnostic for Problem {
+    fn severity(&self) -> Option<Severity> {
+        Some(self.severity)
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.help
+            .as_ref()
+            .map(|help| Box::new(help) as Box<dyn fmt::Display>)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        // Without a file there's nothing to point at
+        self.source?;
+        let label = LabeledSpan::new_with_span(self.label.clone(), self.span);
+        Some(Box::new(std::iter::once(label)))
+    }
+}
+
+/// Why presets couldn't be loaded.
+///
+/// Displays as one `file:line:column: message` line per problem, which is what the TUI shows.
+/// On the command line, [`ConfigError::reports`] point at the offending code instead.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigError {
+    /// Every file that was read, so that problems can point into any of them
+    sources: Vec<Source>,
+    problems: Vec<Problem>,
+}
+
+#[derive(Debug, Clone)]
+struct Source {
+    file_name: String,
+    text: String,
+}
+
+impl Source {
+    /// 1-based line and column of byte `offset`
+    fn line_col(&self, offset: usize) -> (usize, usize) {
+        let before = &self.text.as_bytes()[..offset.min(self.text.len())];
+        let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+        let line_start = before
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let column = String::from_utf8_lossy(&before[line_start..])
+            .chars()
+            .count()
+            + 1;
+        (line, column)
+    }
+}
+
+impl ConfigError {
+    pub(crate) fn new(source: &str, problems: Vec<Problem>) -> Self {
+        let mut error = Self::default();
+        let index = error.add_source("presets.kdl", source);
+        for problem in problems {
+            error.push(index, problem);
+        }
+        error
+    }
+
+    /// Names the file the presets were read from in messages
+    pub fn with_file_name(mut self, file_name: impl Into<String>) -> Self {
+        if let Some(source) = self.sources.first_mut() {
+            source.file_name = file_name.into();
+        }
+        self
+    }
+
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    /// Whether any of the problems is worse than a warning
+    pub fn has_errors(&self) -> bool {
+        self.problems.iter().any(|problem| !problem.is_warning())
+    }
+
+    /// Remembers the contents of a file that problems can then point into, returning its index
+    pub(crate) fn add_source(&mut self, file_name: impl Into<String>, text: &str) -> usize {
+        self.sources.push(Source {
+            file_name: file_name.into(),
+            text: text.to_string(),
+        });
+        self.sources.len() - 1
+    }
+
+    /// Adds a problem found in source `index`, or one that isn't in any file if that's `None`
+    pub(crate) fn push(&mut self, index: impl Into<Option<usize>>, mut problem: Problem) {
+        problem.source = index.into();
+        self.problems.push(problem);
+    }
+
+    /// Takes over the problems of `other`, along with the files they point into
+    pub(crate) fn extend(&mut self, other: ConfigError) {
+        let offset = self.sources.len();
+        self.sources.extend(other.sources);
+        self.problems
+            .extend(other.problems.into_iter().map(|mut problem| {
+                problem.source = problem.source.map(|index| index + offset);
+                problem
+            }));
+    }
+
+    /// `file:line:column` of `offset` in source `index`
+    pub(crate) fn location(&self, index: usize, offset: usize) -> String {
+        let source = &self.sources[index];
+        let (line, column) = source.line_col(offset);
+        format!("{}:{line}:{column}", source.file_name)
+    }
+
+    /// One report per problem, carrying the source code needed to render it
+    pub fn reports(&self) -> impl Iterator<Item = Report> + '_ {
+        self.problems.iter().map(|problem| {
+            let report = Report::new(problem.clone());
+            match problem.source {
+                Some(index) => {
+                    let source = &self.sources[index];
+                    report
+                        .with_source_code(NamedSource::new(&source.file_name, source.text.clone()))
+                }
+                None => report,
+            }
+        })
+    }
+}
+
+impl From<KdlError> for ConfigError {
+    fn from(e: KdlError) -> Self {
+        let problems = e
+            .diagnostics
+            .into_iter()
+            .map(|d| Problem {
+                message: d.message.unwrap_or_else(|| "invalid KDL".to_string()),
+                span: d.span,
+                label: d.label,
+                help: d.help,
+                severity: Severity::Error,
+                source: None,
+            })
+            .collect();
+        Self::new(&e.input, problems)
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            let warning = if problem.is_warning() {
+                "warning: "
+            } else {
+                ""
+            };
+            match problem.source {
+                Some(index) => {
+                    let location = self.location(index, problem.span.offset());
+                    write!(f, "{location}: {warning}{problem}")?;
+                }
+                None => write!(f, "{warning}{problem}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let source = "session name=\"a\"\nsession name=\"é\" {\n  windw\n}\n";
+        let offset = source.find("windw").unwrap();
+        let error = ConfigError::new(
+            source,
+            vec![
+                Problem::new("unknown node `windw`", (offset, 5).into()),
+                Problem::new("missing name", (0, 7).into()),
+                Problem::warning("`a` isn't a directory", (8, 8).into()),
+            ],
+        )
+        .with_file_name("presets.kdl");
+
+        assert_eq!(
+            error.to_string(),
+            "presets.kdl:3:3: unknown node `windw`\npresets.kdl:1:1: missing name\n\
+             presets.kdl:1:9: warning: `a` isn't a directory"
+        );
+    }
+}
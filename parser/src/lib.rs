@@ -1,232 +1,644 @@
-use std::collections::BTreeMap;
+mod error;
+mod lint;
+mod load;
+mod serialize;
+mod template;
 
-use kdl::{KdlDocument, KdlNode};
-use tmux::{LayoutNode, Preset, SplitDirection, Window};
+use std::collections::BTreeMap;
 
-pub fn parse_config(doc_str: &str) -> Result<BTreeMap<String, Preset>, String> {
-    let doc: KdlDocument = doc_str.parse().unwrap();
+use kdl::{KdlDocument, KdlNode, KdlValue};
+use miette::SourceSpan;
+use tmux::{Env, Hooks, LayoutNode, Param, Preset, Size, SplitDirection, Window};
 
-    let nodes: &[KdlNode] = doc.nodes();
+pub use error::{ConfigError, Problem};
+pub use load::{lint_presets, load_presets};
 
-    let mut map = BTreeMap::<String, Preset>::new();
+use load::Loader;
+pub use serialize::{
+    remove_from_document, serialize_preset, serialize_presets, to_document, update_config,
+    update_document,
+};
 
-    // nodes.iter().map(|node| parse_session(node)).collect()
-    for node in nodes.iter().map(parse_session) {
-        let node = node?;
-        map.insert(node.name.clone(), node);
-    }
-    Ok(map)
+/// Parses every session in a presets file, including those of the files it includes relative
+/// to the current directory.
+///
+/// Each session is checked even if an earlier one is broken, so that the error lists all of
+/// them at once.
+pub fn parse_config(doc_str: &str) -> Result<BTreeMap<String, Preset>, ConfigError> {
+    let mut loader = Loader::default();
+    loader.load_str(doc_str, "presets.kdl".to_string(), None);
+    loader.finish()
 }
 
-fn parse_session(session: &KdlNode) -> Result<Preset, String> {
+/// Parses a session, carrying on past each problem in it so that they're all reported at once
+fn parse_session(session: &KdlNode) -> Result<Preset, Vec<Problem>> {
     if session.name().value() != "session" {
-        return Err("Node is not a session".to_string());
+        return Err(vec![
+            Problem::new(
+                format!("unknown node `{}`", session.name().value()),
+                session.name().span(),
+            )
+            .with_label("expected `session`, `template` or `include`"),
+        ]);
     }
+    let mut problems = vec![];
 
-    let session_name: &str = session
-        .get("name")
-        .and_then(|name| name.as_string())
-        .ok_or("Missing or invalid session name!")?;
-
-    let home_dir = &std::env::var("HOME").unwrap_or(".".to_string());
-    let session_cwd: &str = session
-        .get("cwd")
-        .and_then(|name| name.as_string())
-        .unwrap_or(home_dir);
+    let session_name =
+        or_default(string_prop(session, "name"), &mut problems).unwrap_or_else(|| {
+            problems.push(
+                Problem::new("session is missing a name", session.name().span())
+                    .with_help("name it like `session name=\"work\"`"),
+            );
+            ""
+        });
 
-    let windows: Vec<Window> = match session.children() {
-        Some(session_children) => parse_windows(session_children.nodes(), session_cwd)?,
+    let session_cwd =
+        &or_default(expandable_prop(session, "cwd"), &mut problems).unwrap_or("~".to_string());
 
-        // If the session does not specify any windows, assume single window with single pane that
-        // inherits cwd from session
-        None => vec![Window {
-            name: "main".to_string(),
-            cwd: session_cwd.to_string(),
-            layout: LayoutNode::Pane {
-                cwd: session_cwd.to_string(),
-                command: None,
-                size: 100,
-            },
-        }],
-    };
+    // If the session does not specify any windows, assume single window with single pane that
+    // inherits cwd from session
+    let session_children = session
+        .children()
+        .map(KdlDocument::nodes)
+        .unwrap_or_default();
+    let hooks = parse_hooks(session_children, &mut problems);
+    let broken = problems.len();
+    let params = parse_params(session_children, &mut problems);
+    // A broken parameter would only be reported again wherever it's used
+    if problems.len() == broken {
+        check_placeholders(session, &params, &mut problems);
+    }
+    let session_env = parse_env(session_children, &Env::new(), &mut problems);
+    let windows = parse_windows(session_children, session_cwd, &session_env, &mut problems);
 
+    if !problems.is_empty() {
+        return Err(problems);
+    }
     Ok(Preset {
         name: session_name.to_string(),
         cwd: session_cwd.to_string(),
+        env: session_env,
+        hooks,
+        params,
         windows,
         running: false,
+        source: None,
+        origin: None,
     })
 }
 
-fn parse_windows(windows: &[KdlNode], parent_cwd: &str) -> Result<Vec<Window>, String> {
+/// The value in `result`, or the default one once its problem is added to `problems`
+fn or_default<T: Default>(result: Result<T, Problem>, problems: &mut Vec<Problem>) -> T {
+    result.unwrap_or_else(|problem| {
+        problems.push(problem);
+        T::default()
+    })
+}
+
+fn parse_windows(
+    session_children: &[KdlNode],
+    parent_cwd: &str,
+    parent_env: &Env,
+    problems: &mut Vec<Problem>,
+) -> Vec<Window> {
+    let windows: Vec<&KdlNode> = session_children
+        .iter()
+        .filter(|node| !is_session_setting(node))
+        .collect();
+
     if windows.is_empty() {
-        return Ok(vec![Window {
+        return vec![Window {
             name: "main".to_string(),
             cwd: parent_cwd.to_string(),
+            env: parent_env.clone(),
             layout: LayoutNode::Pane {
                 cwd: parent_cwd.to_string(),
                 command: None,
-                size: 100,
+                env: parent_env.clone(),
+                size: Size::default(),
             },
-        }]);
+        }];
     }
 
     let mut ret = vec![];
-    for (idx, window) in windows.iter().enumerate() {
-        let node_name = window.name().value();
-        if node_name != "window" {
-            return Err(format!("Unknown session child node: `{node_name}`"));
-        } else {
-            // Extract window properties
-            // ex: window name="bobby" cwd="~/bobby/" { ... }
-            let window_cwd = window
-                .get("cwd")
-                .and_then(|cwd| cwd.as_string())
-                .unwrap_or(parent_cwd);
-
-            let idx_str = idx.to_string();
-
-            let window_name = window
-                .get("name")
-                .and_then(|cwd| cwd.as_string())
-                .unwrap_or(idx_str.as_str());
-
-            let panes: LayoutNode = match window.children() {
-                Some(window_children) => parse_panes(window_children.nodes(), window_cwd)?,
-                None => LayoutNode::Pane {
-                    cwd: window_cwd.to_string(),
-                    command: None,
-                    size: 100,
-                },
-            };
-
-            ret.push(Window {
-                name: window_name.to_string(),
-                cwd: window_cwd.to_string(),
-                layout: panes,
-            });
+    for (idx, window) in windows.into_iter().enumerate() {
+        match parse_window(window, idx, parent_cwd, parent_env, problems) {
+            Ok(window) => ret.push(window),
+            Err(problem) => problems.push(problem),
         }
     }
-    // make a vec of windows, push each iterations generated value to it, return that value
-    if ret.is_empty() {
-        ret.push(Window {
-            name: "name".to_string(),
-            cwd: parent_cwd.to_string(),
-            layout: LayoutNode::Pane {
-                cwd: parent_cwd.to_string(),
-                command: None,
-                size: 100,
-            },
-        });
+    ret
+}
+
+fn parse_window(
+    window: &KdlNode,
+    idx: usize,
+    parent_cwd: &str,
+    parent_env: &Env,
+    problems: &mut Vec<Problem>,
+) -> Result<Window, Problem> {
+    let node_name = window.name().value();
+    if node_name != "window" {
+        return Err(Problem::new(
+            format!("unknown session child node `{node_name}`"),
+            window.name().span(),
+        )
+        .with_label("expected `window`, `env` or a hook"));
     }
-    Ok(ret)
+
+    // Extract window properties
+    // ex: window name="bobby" cwd="~/bobby/" { ... }
+    let window_cwd = &or_default(expandable_prop(window, "cwd"), problems)
+        .unwrap_or_else(|| parent_cwd.to_string());
+
+    let idx_str = idx.to_string();
+
+    let window_name = or_default(string_prop(window, "name"), problems).unwrap_or(idx_str.as_str());
+
+    let window_children = window
+        .children()
+        .map(KdlDocument::nodes)
+        .unwrap_or_default();
+    let window_env = parse_env(window_children, parent_env, problems);
+    let panes = parse_panes(window_children, window_cwd, &window_env, problems)?;
+
+    Ok(Window {
+        name: window_name.to_string(),
+        cwd: window_cwd.to_string(),
+        env: window_env,
+        layout: panes,
+    })
 }
 
-fn parse_panes(window_children: &[KdlNode], window_cwd: &str) -> Result<LayoutNode, String> {
+fn parse_panes(
+    window_children: &[KdlNode],
+    window_cwd: &str,
+    window_env: &Env,
+    problems: &mut Vec<Problem>,
+) -> Result<LayoutNode, Problem> {
+    let window_children: Vec<&KdlNode> = window_children
+        .iter()
+        .filter(|node| !is_env(node))
+        .collect();
+
     if window_children.is_empty() {
         return Ok(LayoutNode::Pane {
             cwd: window_cwd.to_string(),
             command: None,
-            size: 100,
+            env: window_env.clone(),
+            size: Size::default(),
         });
     }
 
     if window_children.len() != 1 {
-        return Err("Expected exactly one root `split` or `pane` node".into());
+        return Err(Problem::new(
+            "expected exactly one root `split` or `pane` node",
+            window_children[1].span(),
+        )
+        .with_label("extra node")
+        .with_help("wrap the window's panes in a `split`"));
     }
 
-    // The root node of a window should always occupy 100%
-    let mut root_node = parse_node_recursive(&window_children[0], window_cwd)?;
-    set_size(&mut root_node, 100);
+    // The root node of a window always takes up all of it
+    let mut root_node = parse_node_recursive(window_children[0], window_cwd, window_env, problems)?;
+    set_size(&mut root_node, Size::default());
     Ok(root_node)
 }
 
-fn parse_node_recursive(node: &KdlNode, parent_cwd: &str) -> Result<LayoutNode, String> {
+fn parse_node_recursive(
+    node: &KdlNode,
+    parent_cwd: &str,
+    parent_env: &Env,
+    problems: &mut Vec<Problem>,
+) -> Result<LayoutNode, Problem> {
     let node_name = node.name().value();
 
-    // We try to get the size, but keep it as Option to know if it was omitted
-    let explicit_size = node
-        .get("size")
-        .and_then(|v| v.as_integer())
-        .map(|v| v as u8);
+    // Panes and splits without a size share what's left equally
+    let size = or_default(parse_size(node), problems).unwrap_or_default();
+
+    let node_children = node.children().map(KdlDocument::nodes).unwrap_or_default();
+    let env = parse_env(node_children, parent_env, problems);
 
     match node_name {
         "pane" => {
-            let cwd = node
-                .get("cwd")
-                .and_then(|v| v.as_string())
-                .unwrap_or(parent_cwd)
-                .to_string();
+            let cwd = or_default(expandable_prop(node, "cwd"), problems)
+                .unwrap_or_else(|| parent_cwd.to_string());
 
-            let command = node
-                .get("command")
-                .and_then(|v| v.as_string())
-                .map(|s| s.to_string());
+            // Commands often use shell variables, which would otherwise be taken for muffin's
+            let command = expandable_prop(node, "command").map_err(|problem| {
+                problem.with_help(
+                    "write `$$` for a `$` that isn't a variable, including shell variables like \
+                     `$$1` that are left for the shell to expand",
+                )
+            });
+            let command = or_default(command, problems);
+
+            for child in node_children.iter().filter(|child| !is_env(child)) {
+                problems.push(
+                    Problem::new(
+                        format!("unknown pane child node `{}`", child.name().value()),
+                        child.name().span(),
+                    )
+                    .with_label("expected `env`"),
+                );
+            }
 
             Ok(LayoutNode::Pane {
                 cwd,
                 command,
-                size: explicit_size.unwrap_or(0), // Placeholder
+                env,
+                size,
             })
         }
         "split" => {
-            let dir_str = node
-                .get("direction")
-                .and_then(|v| v.as_string())
-                .unwrap_or("v");
+            let dir_str = or_default(string_prop(node, "direction"), problems).unwrap_or("v");
 
             let direction = match dir_str {
                 "h" | "horizontal" => SplitDirection::Horizontal,
                 "v" | "vertical" => SplitDirection::Vertical,
-                _ => return Err(format!("Invalid direction: `{}`", dir_str)),
+                _ => {
+                    problems.push(
+                        Problem::new(
+                            format!("invalid direction `{dir_str}`"),
+                            node.entry("direction").unwrap().span(),
+                        )
+                        .with_help("use \"h\" (side by side) or \"v\" (stacked)"),
+                    );
+                    SplitDirection::Vertical
+                }
             };
 
-            let mut children = Vec::new();
-            let mut total_explicit = 0u8;
-            let mut missing_indices = Vec::new();
-
-            if let Some(document) = node.children() {
-                for (i, child_node) in document.nodes().iter().enumerate() {
-                    let mut layout_child = parse_node_recursive(child_node, parent_cwd)?;
-
-                    // Check if this specific child had a size defined
-                    if let Some(p) = child_node.get("size").and_then(|v| v.as_integer()) {
-                        let p = p as u8;
-                        set_size(&mut layout_child, p);
-                        total_explicit += p;
-                    } else {
-                        missing_indices.push(i);
-                    }
-                    children.push(layout_child);
-                }
+            // Splits have no environment of their own, they just pass theirs on
+            let child_nodes: Vec<&KdlNode> = node_children
+                .iter()
+                .filter(|child| !is_env(child))
+                .collect();
+            if child_nodes.is_empty() {
+                return Err(Problem::new("split has no panes", node.span())
+                    .with_help("add at least one `pane` or `split` inside of it"));
             }
-
-            if children.is_empty() {
-                return Err("Split nodes must contain children".into());
+            let mut children = vec![];
+            for child in child_nodes {
+                match parse_node_recursive(child, parent_cwd, &env, problems) {
+                    Ok(child) => children.push(child),
+                    Err(problem) => problems.push(problem),
+                }
             }
 
-            // --- Equal Distribution Logic ---
-            if !missing_indices.is_empty() {
-                let remaining = 100_u8.saturating_sub(total_explicit);
-                let share = remaining / (missing_indices.len() as u8);
-
-                for idx in missing_indices {
-                    set_size(&mut children[idx], share);
-                }
+            let percent: u32 = children
+                .iter()
+                .filter_map(|child| match child.size() {
+                    Size::Percent(percent) => Some(percent as u32),
+                    _ => None,
+                })
+                .sum();
+            let weighted = children
+                .iter()
+                .any(|child| matches!(child.size(), Size::Weight(_)));
+            if percent > 100 || (percent == 100 && weighted) {
+                let problem = Problem::new(
+                    format!("sizes in this split add up to {percent}%"),
+                    node.name().span(),
+                );
+                problems.push(if percent > 100 {
+                    problem.with_help("percentages can add up to 100% at most")
+                } else {
+                    problem.with_help("panes without a percentage would have no room left")
+                });
             }
 
             Ok(LayoutNode::Split {
                 direction,
                 children,
-                size: explicit_size.unwrap_or(0), // Placeholder
+                size,
             })
         }
-        x => Err(format!("Unexpected node: `{x}`")),
+        x => Err(
+            Problem::new(format!("unknown node `{x}`"), node.name().span())
+                .with_label("expected `pane` or `split`"),
+        ),
+    }
+}
+
+/// Parses a node's `size`, which is either a weight like `2`, a percentage like `"30%"` or a
+/// number of cells like `"80c"`
+fn parse_size(node: &KdlNode) -> Result<Option<Size>, Problem> {
+    let Some(entry) = node.entry("size") else {
+        return Ok(None);
+    };
+    let invalid = |message: &str| {
+        Problem::new(message, entry.span()).with_help(
+            "use a weight like `size=2`, a percentage like `size=\"30%\"` \
+             or a number of cells like `size=\"80c\"`",
+        )
+    };
+
+    let size = match entry.value() {
+        KdlValue::Integer(weight) => u32::try_from(*weight)
+            .map(Size::Weight)
+            .map_err(|_| invalid("size is out of range"))?,
+        KdlValue::String(size) => {
+            if let Some(percent) = size.strip_suffix('%') {
+                match percent.trim().parse::<u8>() {
+                    Ok(percent @ 0..=100) => Size::Percent(percent),
+                    _ => return Err(invalid("percentages must be between 1% and 100%")),
+                }
+            } else if let Some(cells) = size.strip_suffix('c') {
+                let cells = cells.trim().parse().map_err(|_| invalid("invalid size"))?;
+                Size::Cells(cells)
+            } else {
+                return Err(invalid("invalid size"));
+            }
+        }
+        _ => return Err(invalid("invalid size")),
+    };
+
+    if matches!(size, Size::Weight(0) | Size::Percent(0) | Size::Cells(0)) {
+        return Err(invalid("size must be greater than zero"));
+    }
+    Ok(Some(size))
+}
+
+fn is_env(node: &KdlNode) -> bool {
+    node.name().value() == "env"
+}
+
+const HOOKS: [&str; 4] = ["on_create", "on_attach", "on_detach", "on_kill"];
+
+/// Whether a child of a session describes the session itself, rather than one of its windows
+fn is_session_setting(node: &KdlNode) -> bool {
+    is_env(node) || is_param(node) || HOOKS.contains(&node.name().value())
+}
+
+fn is_param(node: &KdlNode) -> bool {
+    node.name().value() == "param"
+}
+
+/// Reads parameters like `param "dir" default="~/src"` from the children of a session,
+/// skipping the broken ones
+fn parse_params(session_children: &[KdlNode], problems: &mut Vec<Problem>) -> Vec<Param> {
+    let mut params: Vec<Param> = vec![];
+
+    for node in session_children.iter().filter(|node| is_param(node)) {
+        match parse_param(node, &params) {
+            Ok(param) => params.push(param),
+            Err(problem) => problems.push(problem),
+        }
+    }
+
+    params
+}
+
+fn parse_param(node: &KdlNode, params: &[Param]) -> Result<Param, Problem> {
+    let help = "declare it like `param \"dir\"`, or `param \"dir\" default=\"~/src\"`";
+
+    let mut name = None;
+    for entry in node.entries() {
+        match entry.name().map(|key| key.value()) {
+            None if name.is_none() => {
+                let value = entry.value().as_string().ok_or_else(|| {
+                    Problem::new("parameter names must be strings", entry.span())
+                        .with_label("not a string")
+                })?;
+                name = Some((value, entry.span()));
+            }
+            Some("default") => {}
+            _ => {
+                return Err(
+                    Problem::new("unexpected value on `param`", entry.span()).with_help(help)
+                );
+            }
+        }
+    }
+
+    let Some((name, span)) = name else {
+        return Err(Problem::new("`param` needs a name", node.span()).with_help(help));
+    };
+    if name.is_empty() || name.trim() != name || name.contains("}}") {
+        return Err(
+            Problem::new(format!("invalid parameter name `{name}`"), span)
+                .with_help("names can't be empty, contain `}}` or start or end with spaces"),
+        );
+    }
+    if params.iter().any(|param| param.name == name) {
+        return Err(Problem::new(
+            format!("parameter `{name}` is declared more than once"),
+            span,
+        ));
+    }
+    if let Some(children) = node.children() {
+        return Err(
+            Problem::new("parameters can't have children", children.span())
+                .with_label("unexpected block"),
+        );
+    }
+
+    Ok(Param {
+        name: name.to_string(),
+        default: string_prop(node, "default")?.map(str::to_string),
+    })
+}
+
+/// Makes sure every `{{param}}` in the names, directories and commands under `node` was
+/// declared
+fn check_placeholders(node: &KdlNode, params: &[Param], problems: &mut Vec<Problem>) {
+    for entry in node.entries() {
+        let Some(key) = entry.name() else {
+            continue;
+        };
+        if !["name", "cwd", "command"].contains(&key.value()) {
+            continue;
+        }
+        let Some(value) = entry.value().as_string() else {
+            continue;
+        };
+        if let Some(name) = tmux::placeholders(value)
+            .into_iter()
+            .find(|name| !params.iter().any(|param| param.name == *name))
+        {
+            problems.push(
+                Problem::new(
+                    format!("`{{{{{name}}}}}` isn't a parameter of this session"),
+                    entry.span(),
+                )
+                .with_help(format!("declare it like `param \"{name}\"`")),
+            );
+        }
+    }
+
+    for child in node.iter_children() {
+        if !is_env(child) && !is_param(child) {
+            check_placeholders(child, params, problems);
+        }
     }
 }
 
+/// Reads hooks like `on_create "docker compose up -d"` from the children of a session
+fn parse_hooks(session_children: &[KdlNode], problems: &mut Vec<Problem>) -> Hooks {
+    let mut hooks = Hooks::default();
+
+    for node in session_children {
+        let hook = match node.name().value() {
+            "on_create" => &mut hooks.on_create,
+            "on_attach" => &mut hooks.on_attach,
+            "on_detach" => &mut hooks.on_detach,
+            "on_kill" => &mut hooks.on_kill,
+            _ => continue,
+        };
+        let name = node.name().value();
+
+        let command = match string_arg(node, "command", "\"make start\"") {
+            Ok(command) => command,
+            Err(problem) => {
+                problems.push(problem);
+                continue;
+            }
+        };
+        if hook.is_some() {
+            problems.push(
+                Problem::new(
+                    format!("`{name}` is set more than once"),
+                    node.name().span(),
+                )
+                .with_help("chain the commands with `&&` instead"),
+            );
+            continue;
+        }
+        *hook = Some(command.to_string());
+    }
+
+    hooks
+}
+
+/// Adds the variables of every `env` block among `nodes` to the inherited `parent_env`, like
+/// `env { RUST_LOG "debug"; }`. Broken variables are left out.
+fn parse_env(nodes: &[KdlNode], parent_env: &Env, problems: &mut Vec<Problem>) -> Env {
+    let mut env = parent_env.clone();
+
+    for block in nodes.iter().filter(|node| is_env(node)) {
+        if let Some(entry) = block.entries().first() {
+            problems.push(
+                Problem::new("unexpected value on `env`", entry.span())
+                    .with_help("set variables inside of it, like `env { KEY \"value\"; }`"),
+            );
+        }
+
+        for var in block.iter_children() {
+            match parse_var(var) {
+                Ok((key, value)) => {
+                    env.insert(key.to_string(), value);
+                }
+                Err(problem) => problems.push(problem),
+            }
+        }
+    }
+
+    env
+}
+
+/// A single variable like `RUST_LOG "debug"` in an `env` block
+fn parse_var(var: &KdlNode) -> Result<(&str, String), Problem> {
+    let key = var.name().value();
+    if key.is_empty() || key.contains('=') {
+        return Err(
+            Problem::new(format!("invalid variable name `{key}`"), var.name().span())
+                .with_help("names can't be empty or contain `=`"),
+        );
+    }
+
+    let value = match var.entries() {
+        [entry] if entry.name().is_none() => match entry.value() {
+            // Expanded once the preset is spawned, like directories and commands
+            KdlValue::String(value) => {
+                expand(value, entry.span())?;
+                value.to_string()
+            }
+            KdlValue::Integer(value) => value.to_string(),
+            _ => {
+                return Err(
+                    Problem::new(format!("`{key}` must be a string"), entry.span())
+                        .with_label("not a string"),
+                );
+            }
+        },
+        _ => {
+            return Err(
+                Problem::new(format!("`{key}` needs exactly one value"), var.span())
+                    .with_help(format!("set it like `{key} \"value\"`")),
+            );
+        }
+    };
+    if let Some(children) = var.children() {
+        return Err(
+            Problem::new("variables can't have children", children.span())
+                .with_label("unexpected block"),
+        );
+    }
+
+    Ok((key, value))
+}
+
+/// The single string argument of nodes like `include "shared.kdl"`, which is called `what` in
+/// errors, with an `example` of what it might be
+fn string_arg<'a>(node: &'a KdlNode, what: &str, example: &str) -> Result<&'a str, Problem> {
+    let name = node.name().value();
+    match node.entries() {
+        [entry] if entry.name().is_none() => entry.value().as_string().ok_or_else(|| {
+            Problem::new(format!("`{name}` must be a string"), entry.span())
+                .with_label("not a string")
+        }),
+        _ => Err(
+            Problem::new(format!("`{name}` needs exactly one {what}"), node.span())
+                .with_help(format!("set it like `{name} {example}`")),
+        ),
+    }
+}
+
+/// Looks up property `key`, which has to be a string if it's there at all
+fn string_prop<'a>(node: &'a KdlNode, key: &str) -> Result<Option<&'a str>, Problem> {
+    let Some(entry) = node.entry(key) else {
+        return Ok(None);
+    };
+    match entry.value().as_string() {
+        Some(value) => Ok(Some(value)),
+        None => Err(
+            Problem::new(format!("`{key}` must be a string"), entry.span())
+                .with_label("not a string"),
+        ),
+    }
+}
+
+/// Looks up string property `key` like [`string_prop`], and makes sure it expands like
+/// [`expand`] does. The value is kept as it's written though, and only expanded once the
+/// preset is spawned, so that saving the preset again doesn't tie it to this machine.
+fn expandable_prop(node: &KdlNode, key: &str) -> Result<Option<String>, Problem> {
+    match string_prop(node, key)? {
+        Some(value) => {
+            expand(value, node.entry(key).unwrap().span())?;
+            Ok(Some(value.to_string()))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Expands `~` and environment variables like `$WORKSPACE` or `${WORKSPACE}` in `value`, which
+/// is at `span`. `$$` stands for a `$` that isn't expanded.
+fn expand(value: &str, span: SourceSpan) -> Result<String, Problem> {
+    shellexpand::full(value)
+        .map(|value| value.into_owned())
+        .map_err(|e| {
+            let message = match e.cause {
+                std::env::VarError::NotPresent => format!("`${}` is not set", e.var_name),
+                std::env::VarError::NotUnicode(_) => {
+                    format!("`${}` is not valid unicode", e.var_name)
+                }
+            };
+            Problem::new(message, span).with_help("write `$$` for a `$` that isn't a variable")
+        })
+}
+
 // Helper to set size regardless of enum variant
-fn set_size(node: &mut LayoutNode, val: u8) {
+fn set_size(node: &mut LayoutNode, val: Size) {
     match node {
         LayoutNode::Pane { size, .. } => *size = val,
         LayoutNode::Split { size, .. } => *size = val,
@@ -235,7 +647,406 @@ fn set_size(node: &mut LayoutNode, val: u8) {
 
 #[cfg(test)]
 mod tests {
-    use crate::parse_config;
+    use super::*;
+
+    #[test]
+    fn test_syntax_error() {
+        let doc_str = "session name=\"ok\"\nsession name=\"broken {\n";
+        let error = parse_config(doc_str).unwrap_err();
+        assert!(!error.problems().is_empty());
+        assert!(error.to_string().starts_with("presets.kdl:2:"));
+    }
+
+    #[test]
+    fn test_schema_errors() {
+        let doc_str = r#"session name="a" {
+  window name="w" {
+    split direction="diagonal" {
+      pane
+    }
+  }
+}
+
+session name="b" {
+  windw name="typo"
+}
+
+session cwd="~"
+"#;
+        let error = parse_config(doc_str).unwrap_err().with_file_name("my.kdl");
+        let problems = error.problems();
+        assert_eq!(problems.len(), 3);
+
+        assert_eq!(problems[0].message, "invalid direction `diagonal`");
+        let offset = doc_str.find("direction=").unwrap();
+        assert_eq!(problems[0].span.offset(), offset);
+
+        assert_eq!(problems[1].message, "unknown session child node `windw`");
+        assert_eq!(problems[2].message, "session is missing a name");
+        assert_eq!(
+            error.to_string().lines().collect::<Vec<_>>(),
+            [
+                "my.kdl:3:11: invalid direction `diagonal`",
+                "my.kdl:10:3: unknown session child node `windw`",
+                "my.kdl:13:1: session is missing a name",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let doc_str = r#"session name="School" {
+  window name="notes"
+  window name="notes"
+}
+session name="School"
+"#;
+        let error = parse_config(doc_str).unwrap_err();
+        assert_eq!(
+            error.to_string().lines().collect::<Vec<_>>(),
+            [
+                "presets.kdl:3:10: there's already a window called `notes`",
+                "presets.kdl:5:9: there's already a preset called `School`",
+            ]
+        );
+        let help: Vec<_> = error.problems().iter().map(|p| p.help.clone()).collect();
+        assert_eq!(
+            help,
+            [
+                Some("the first one is at presets.kdl:2:10".to_string()),
+                Some("the first one is at presets.kdl:1:9".to_string()),
+            ]
+        );
+
+        // Replacing a window of a template is what a window with the same name is for there
+        let doc_str = r#"template name="t" { window name="notes"; }
+session name="School" extends="t" { window name="notes"; }
+"#;
+        assert!(parse_config(doc_str).is_ok());
+
+        // The template's first window is called `0` without a name, like the session's would be
+        let doc_str = r#"template name="t" { window; }
+session name="School" extends="t" { window name="0"; }
+"#;
+        let error = parse_config(doc_str).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "presets.kdl:2:9: this session has two windows called `0`"
+        );
+        let error =
+            parse_config("session name=\"School\" { window; window name=\"0\"; }").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "presets.kdl:1:9: this session has two windows called `0`"
+        );
+    }
+
+    #[test]
+    fn test_every_problem() {
+        let doc_str = r#"session name=1 cwd="$MUFFIN_UNSET" {
+  on_create
+  param "p" oops=1
+  env { "" "x"; }
+  window name="w" {
+    split direction="up" {
+      pane size="lots"
+      pane command=2
+    }
+  }
+  window name="ok"
+}
+"#;
+        let error = parse_config(doc_str).unwrap_err();
+        let messages: Vec<&str> = error
+            .problems()
+            .iter()
+            .map(|problem| problem.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "`name` must be a string",
+                "session is missing a name",
+                "`$MUFFIN_UNSET` is not set",
+                "`on_create` needs exactly one command",
+                "unexpected value on `param`",
+                "invalid variable name ``",
+                "invalid direction `up`",
+                "invalid size",
+                "`command` must be a string",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sizes() {
+        let doc_str = r#"session name="a" {
+  window {
+    split direction="h" {
+      pane size="30%"
+      pane size="80c"
+      pane size=2
+      pane
+    }
+  }
+}"#;
+        let presets = parse_config(doc_str).unwrap();
+        let LayoutNode::Split { children, .. } = &presets["a"].windows[0].layout else {
+            panic!("expected a split");
+        };
+        let sizes: Vec<Size> = children.iter().map(LayoutNode::size).collect();
+        assert_eq!(
+            sizes,
+            [
+                Size::Percent(30),
+                Size::Cells(80),
+                Size::Weight(2),
+                Size::Weight(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_sizes() {
+        let error = |split: &str| {
+            let doc_str = format!("session name=\"a\" {{\n  window {{\n{split}\n  }}\n}}");
+            let error = parse_config(&doc_str).unwrap_err();
+            error.problems()[0].message.clone()
+        };
+
+        assert_eq!(
+            error(r#"split { pane size=0; pane; }"#),
+            "size must be greater than zero"
+        );
+        assert_eq!(
+            error(r#"split { pane size=-2; pane; }"#),
+            "size is out of range"
+        );
+        assert_eq!(
+            error(r#"split { pane size="120%"; pane; }"#),
+            "percentages must be between 1% and 100%"
+        );
+        assert_eq!(
+            error(r#"split { pane size="wide"; pane; }"#),
+            "invalid size"
+        );
+        assert_eq!(
+            error(r#"split { pane size="60%"; pane size="50%"; }"#),
+            "sizes in this split add up to 110%"
+        );
+        assert_eq!(
+            error(r#"split { pane size="60%"; pane size="40%"; pane; }"#),
+            "sizes in this split add up to 100%"
+        );
+    }
+
+    #[test]
+    fn test_env() {
+        let doc_str = r#"session name="a" {
+  env {
+    RUST_LOG "info"
+    PORT 8080
+  }
+  window {
+    env { KUBECONFIG "~/.kube/work"; }
+    split {
+      env { RUST_LOG "debug"; }
+      pane
+      pane { env { PORT "9090"; }; }
+    }
+  }
+  window name="other"
+}"#;
+        let env = |vars: &[(&str, &str)]| -> Env {
+            vars.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        let presets = parse_config(doc_str).unwrap();
+        let preset = &presets["a"];
+        assert_eq!(preset.env, env(&[("RUST_LOG", "info"), ("PORT", "8080")]));
+        assert_eq!(
+            preset.windows[0].env,
+            env(&[
+                ("RUST_LOG", "info"),
+                ("PORT", "8080"),
+                ("KUBECONFIG", "~/.kube/work")
+            ])
+        );
+        let panes = preset.windows[0].layout.panes();
+        let LayoutNode::Pane { env: first, .. } = panes[0] else {
+            unreachable!()
+        };
+        let LayoutNode::Pane { env: second, .. } = panes[1] else {
+            unreachable!()
+        };
+        assert_eq!(first["RUST_LOG"], "debug");
+        assert_eq!(first["PORT"], "8080");
+        assert_eq!(second["PORT"], "9090");
+        assert_eq!(second["KUBECONFIG"], "~/.kube/work");
+        assert_eq!(preset.windows[1].env, preset.env);
+        assert_eq!(preset.windows[1].name, "other");
+
+        let error = |doc_str: &str| {
+            parse_config(doc_str).unwrap_err().problems()[0]
+                .message
+                .clone()
+        };
+        assert_eq!(
+            error("session name=\"a\" { env { KEY; }; }"),
+            "`KEY` needs exactly one value"
+        );
+        assert_eq!(
+            error("session name=\"a\" { env { KEY #true; }; }"),
+            "`KEY` must be a string"
+        );
+        assert_eq!(
+            error("session name=\"a\" { env { \"A=B\" \"c\"; }; }"),
+            "invalid variable name `A=B`"
+        );
+        assert_eq!(
+            error("session name=\"a\" { window { pane { cwd \"/\"; }; }; }"),
+            "unknown pane child node `cwd`"
+        );
+    }
+
+    #[test]
+    fn test_hooks() {
+        let doc_str = r#"session name="a" {
+  on_create "docker compose up -d"
+  window name="main"
+  on_kill "docker compose down"
+}"#;
+        let presets = parse_config(doc_str).unwrap();
+        let preset = &presets["a"];
+        assert_eq!(
+            preset.hooks,
+            Hooks {
+                on_create: Some("docker compose up -d".to_string()),
+                on_kill: Some("docker compose down".to_string()),
+                ..Hooks::default()
+            }
+        );
+        assert_eq!(preset.windows.len(), 1);
+
+        let error = |doc_str: &str| {
+            parse_config(doc_str).unwrap_err().problems()[0]
+                .message
+                .clone()
+        };
+        assert_eq!(
+            error("session name=\"a\" { on_attach; }"),
+            "`on_attach` needs exactly one command"
+        );
+        assert_eq!(
+            error("session name=\"a\" { on_detach 1; }"),
+            "`on_detach` must be a string"
+        );
+        assert_eq!(
+            error("session name=\"a\" { on_kill \"a\"; on_kill \"b\"; }"),
+            "`on_kill` is set more than once"
+        );
+    }
+
+    #[test]
+    fn test_params() {
+        let doc_str = r#"session name="review-{{branch}}" cwd="{{ dir }}" {
+  param "dir" default="~/src"
+  param "branch"
+  window name="git" { pane command="git switch {{branch}}"; }
+}"#;
+        let presets = parse_config(doc_str).unwrap();
+        let preset = &presets["review-{{branch}}"];
+        assert_eq!(
+            preset.params,
+            [
+                Param {
+                    name: "dir".to_string(),
+                    default: Some("~/src".to_string()),
+                },
+                Param {
+                    name: "branch".to_string(),
+                    default: None,
+                },
+            ]
+        );
+        assert_eq!(preset.windows.len(), 1);
+
+        let error = |doc_str: &str| {
+            parse_config(doc_str).unwrap_err().problems()[0]
+                .message
+                .clone()
+        };
+        assert_eq!(
+            error("session name=\"a\" { window { pane command=\"cd {{dir}}\"; }; }"),
+            "`{{dir}}` isn't a parameter of this session"
+        );
+        assert_eq!(
+            error("session name=\"a\" { param; }"),
+            "`param` needs a name"
+        );
+        assert_eq!(
+            error("session name=\"a\" { param \"x\" \"y\"; }"),
+            "unexpected value on `param`"
+        );
+        assert_eq!(
+            error("session name=\"a\" { param \" x\"; }"),
+            "invalid parameter name ` x`"
+        );
+        assert_eq!(
+            error("session name=\"a\" { param \"x\"; param \"x\"; }"),
+            "parameter `x` is declared more than once"
+        );
+    }
+
+    #[test]
+    fn test_expansion() {
+        // Left for spawning to expand, so that saving doesn't write this machine's paths out
+        let doc_str = r#"session name="a" cwd="~/src" {
+  env { DATA "${HOME}/data"; }
+  window cwd="$HOME/api" { pane command="for f in *; do echo $$f; done"; }
+}"#;
+        let presets = parse_config(doc_str).unwrap();
+        let preset = &presets["a"];
+        assert_eq!(preset.cwd, "~/src");
+        assert_eq!(preset.env["DATA"], "${HOME}/data");
+        assert_eq!(preset.windows[0].cwd, "$HOME/api");
+        assert!(matches!(
+            &preset.windows[0].layout,
+            LayoutNode::Pane { command: Some(command), .. }
+                if command == "for f in *; do echo $$f; done"
+        ));
+
+        let error = parse_config("session name=\"a\" {\n  window cwd=\"$MUFFIN_UNSET/api\"\n}")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "presets.kdl:2:10: `$MUFFIN_UNSET` is not set"
+        );
+
+        // Shell variables in commands have to be escaped, or muffin tries to expand them
+        let command = |command: &str| {
+            let doc_str =
+                format!("session name=\"a\" {{ window {{ pane command=\"{command}\"; }} }}");
+            parse_config(&doc_str)
+        };
+        let error = command("awk '{print $1}' log").unwrap_err();
+        assert_eq!(error.problems()[0].message, "`$1` is not set");
+        assert!(error.problems()[0].help.as_ref().unwrap().contains("`$$1`"));
+        let presets = command("awk '{print $$1}' log").unwrap();
+        assert!(matches!(
+            &presets["a"].windows[0].layout,
+            LayoutNode::Pane { command: Some(command), .. } if command == "awk '{print $$1}' log"
+        ));
+    }
+
+    #[test]
+    fn test_wrong_property_type() {
+        let error = parse_config("session name=1").unwrap_err();
+        assert_eq!(error.problems()[0].message, "`name` must be a string");
+        assert_eq!(error.problems()[0].span, (8, 6).into());
+    }
 
     #[test]
     fn test_example() {
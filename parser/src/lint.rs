@@ -0,0 +1,142 @@
+use std::path::Path;
+
+use kdl::KdlNode;
+use miette::SourceSpan;
+use tmux::Size;
+
+use crate::{ConfigError, HOOKS, Problem, expand, is_env, is_session_setting, parse_size};
+
+/// Shell builtins and keywords, which are fine to start a command with even though they aren't
+/// on `$PATH`
+const BUILTINS: [&str; 20] = [
+    ".", "[", "alias", "cd", "echo", "eval", "exec", "exit", "export", "false", "for", "if",
+    "printf", "read", "set", "source", "test", "time", "true", "unset",
+];
+
+/// Things in a session or template node that load fine, but probably don't do what was meant:
+/// directories and commands that don't exist, and percentages that leave part of a split
+/// unaccounted for. They're added to `error` as warnings in source `index`.
+pub(crate) fn lint_session(session: &KdlNode, index: usize, error: &mut ConfigError) {
+    let mut problems = vec![];
+    check_cwd(session, &mut problems);
+
+    for child in session.iter_children() {
+        if HOOKS.contains(&child.name().value()) {
+            if let Some(entry) = child.entries().first() {
+                check_command(entry.value().as_string(), entry.span(), &mut problems);
+            }
+            continue;
+        }
+        if is_session_setting(child) {
+            continue;
+        }
+
+        check_cwd(child, &mut problems);
+        for node in child.iter_children().filter(|node| !is_env(node)) {
+            lint_layout(node, &mut problems);
+        }
+    }
+
+    for problem in problems {
+        error.push(index, problem);
+    }
+}
+
+fn lint_layout(node: &KdlNode, problems: &mut Vec<Problem>) {
+    check_cwd(node, problems);
+    if let Some(entry) = node.entry("command") {
+        check_command(entry.value().as_string(), entry.span(), problems);
+    }
+
+    let children: Vec<&KdlNode> = node.iter_children().filter(|node| !is_env(node)).collect();
+    if node.name().value() == "split" {
+        let sizes: Vec<Option<Size>> = children
+            .iter()
+            .map(|child| parse_size(child).unwrap_or_default())
+            .collect();
+        let percent: u32 = sizes
+            .iter()
+            .map(|size| match size {
+                Some(Size::Percent(percent)) => *percent as u32,
+                _ => 0,
+            })
+            .sum();
+        // Anything without a size, or with a weight or a number of cells, takes the rest
+        let all_percent = sizes
+            .iter()
+            .all(|size| matches!(size, Some(Size::Percent(_))));
+        if all_percent && percent < 100 && !sizes.is_empty() {
+            problems.push(
+                Problem::warning(
+                    format!("sizes in this split only add up to {percent}%"),
+                    node.name().span(),
+                )
+                .with_help(
+                    "they're stretched to fill it, unless one of them has no size to take the rest",
+                ),
+            );
+        }
+    }
+
+    for child in children {
+        lint_layout(child, problems);
+    }
+}
+
+/// Warns about a `cwd` that isn't a directory. Relative ones depend on where muffin runs, so
+/// they're left alone, as are ones with parameters in them.
+fn check_cwd(node: &KdlNode, problems: &mut Vec<Problem>) {
+    let Some(entry) = node.entry("cwd") else {
+        return;
+    };
+    let Some(value) = entry.value().as_string() else {
+        return;
+    };
+    // Expanding failed already, and that's an error of its own
+    let Ok(cwd) = expand(value, entry.span()) else {
+        return;
+    };
+    if cwd.contains("{{") || !Path::new(&cwd).is_absolute() || Path::new(&cwd).is_dir() {
+        return;
+    }
+    problems.push(
+        Problem::warning(format!("`{cwd}` isn't a directory"), entry.span())
+            .with_label("doesn't exist"),
+    );
+}
+
+/// Warns about a command whose program can't be found on `$PATH`
+fn check_command(command: Option<&str>, span: SourceSpan, problems: &mut Vec<Problem>) {
+    let Some(Ok(command)) = command.map(|command| expand(command, span)) else {
+        return;
+    };
+    // Skip over variables set for the command, like `RUST_LOG=debug cargo run`
+    let Some(program) = command.split_whitespace().find(|word| !is_assignment(word)) else {
+        return;
+    };
+    if program.contains("{{") || BUILTINS.contains(&program) || program_exists(program) {
+        return;
+    }
+    problems.push(
+        Problem::warning(format!("`{program}` isn't on $PATH"), span)
+            .with_label("command not found"),
+    );
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// Whether `program` is a file on `$PATH`, or at the path it is if it has a `/` in it.
+/// Relative paths depend on the pane's directory, so they're given the benefit of the doubt.
+fn program_exists(program: &str) -> bool {
+    if program.contains('/') {
+        return !Path::new(program).is_absolute() || Path::new(program).is_file();
+    }
+    let Some(path) = std::env::var_os("PATH") else {
+        return true;
+    };
+    std::env::split_paths(&path).any(|dir| dir.join(program).is_file())
+}
@@ -0,0 +1,405 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use kdl::{KdlDocument, KdlNode};
+use miette::SourceSpan;
+use tmux::Preset;
+
+use crate::lint::lint_session;
+use crate::template::Templates;
+use crate::{ConfigError, Problem, expand, is_session_setting, parse_session, string_arg};
+
+/// Loads the presets in the file at `path`, or in every `*.kdl` file directly inside of it if
+/// it's a directory.
+///
+/// Files can pull in more presets with `include "shared.kdl"`, relative to themselves. Each
+/// preset remembers which file it came from, and no two of them can have the same name.
+pub fn load_presets(path: &Path) -> Result<BTreeMap<String, Preset>, ConfigError> {
+    let mut loader = Loader::default();
+    loader.load_path(path, None);
+    loader.finish()
+}
+
+/// Loads presets like [`load_presets`], but also looks for things that load fine and still
+/// look wrong, like a `cwd` that doesn't exist or a command that isn't on `$PATH`.
+///
+/// Those come as warnings, alongside any errors. The presets are only the ones that loaded.
+pub fn lint_presets(path: &Path) -> (BTreeMap<String, Preset>, ConfigError) {
+    let mut loader = Loader {
+        lint: true,
+        ..Loader::default()
+    };
+    loader.load_path(path, None);
+    loader.finish_all()
+}
+
+/// Where a problem can point: the index of a source in the error, and a span in it
+type Location = (usize, SourceSpan);
+
+/// Collects presets from any number of files, along with everything wrong with them
+#[derive(Default)]
+pub(crate) struct Loader {
+    presets: BTreeMap<String, Preset>,
+    /// Where the name of each preset is
+    definitions: HashMap<String, Location>,
+    error: ConfigError,
+    /// Files that were loaded already, which is fine to include again but not to load twice
+    loaded: HashSet<PathBuf>,
+    /// Canonical and displayed paths of the files being loaded right now, outermost first
+    stack: Vec<(PathBuf, String)>,
+    templates: Templates,
+    /// Sessions wait for every template to be defined, along with their source and file
+    sessions: Vec<(KdlNode, usize, Option<PathBuf>)>,
+    /// Whether to warn about sessions and templates that look wrong, see [`lint_presets`]
+    lint: bool,
+}
+
+impl Loader {
+    /// Loads a file or directory, which the `include` node at `site` asked for if there is one
+    fn load_path(&mut self, path: &Path, site: Option<Location>) {
+        if !path.is_dir() {
+            self.load_file(path, site);
+            return;
+        }
+
+        let entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                let message = format!("failed to read {}: {e}", path.display());
+                self.problem(site, message);
+                return;
+            }
+        };
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension() == Some("kdl".as_ref()) && path.is_file())
+            .collect();
+        // Whatever order the file system has them in, problems should come in the same one
+        files.sort();
+
+        for file in files {
+            self.load_file(&file, site);
+        }
+    }
+
+    fn load_file(&mut self, path: &Path, site: Option<Location>) {
+        let read = path
+            .canonicalize()
+            .and_then(|canonical| Ok((std::fs::read_to_string(path)?, canonical)));
+        let (text, canonical) = match read {
+            Ok(read) => read,
+            Err(e) => {
+                self.problem(site, format!("failed to read {}: {e}", path.display()));
+                return;
+            }
+        };
+
+        let file_name = path.display().to_string();
+        if let Some(start) = self.stack.iter().position(|(p, _)| *p == canonical) {
+            let cycle: Vec<&str> = self.stack[start..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .chain([file_name.as_str()])
+                .collect();
+            let message = format!("files include each other: {}", cycle.join(" -> "));
+            self.problem(site, message);
+            return;
+        }
+        if !self.loaded.insert(canonical.clone()) {
+            return;
+        }
+
+        self.stack.push((canonical, file_name.clone()));
+        self.load_str(&text, file_name, Some(path));
+        self.stack.pop();
+    }
+
+    /// Loads the presets in `text`, which was read from `path` if there is one. Includes are
+    /// relative to that file, or the current directory otherwise.
+    pub(crate) fn load_str(&mut self, text: &str, file_name: String, path: Option<&Path>) {
+        let doc: KdlDocument = match text.parse() {
+            Ok(doc) => doc,
+            Err(e) => {
+                self.error
+                    .extend(ConfigError::from(e).with_file_name(file_name));
+                return;
+            }
+        };
+        let index = self.error.add_source(file_name, text);
+        let dir = path.and_then(Path::parent).unwrap_or(Path::new(""));
+
+        for node in doc.nodes() {
+            if node.name().value() == "include" {
+                let include = string_arg(node, "path", "\"shared.kdl\"")
+                    .and_then(|include| expand(include, node.entries()[0].span()));
+                match include {
+                    Ok(include) => self.load_path(&dir.join(include), Some((index, node.span()))),
+                    Err(problem) => self.error.push(index, problem),
+                }
+                continue;
+            }
+
+            self.check_windows(node, index);
+            if self.lint {
+                lint_session(node, index, &mut self.error);
+            }
+            if node.name().value() == "template" {
+                self.templates.define(node, index, &mut self.error);
+                continue;
+            }
+
+            self.sessions
+                .push((node.clone(), index, path.map(Path::to_path_buf)));
+        }
+    }
+
+    fn load_session(&mut self, node: &KdlNode, index: usize, source: Option<PathBuf>) {
+        let session = match self.templates.apply(node) {
+            Ok(Some(session)) => session,
+            Ok(None) => return,
+            Err(problem) => {
+                self.error.push(index, problem);
+                return;
+            }
+        };
+
+        match parse_session(&session) {
+            Ok(mut preset) => {
+                preset.source = source;
+                let name_span = node.entry("name").map_or(node.span(), |e| e.span());
+                // Names written twice were pointed out already, but windows named after their
+                // index and the windows of a template can still end up with the same name
+                if duplicate_windows(node).is_empty()
+                    && let Some(window) = preset.duplicate_window()
+                {
+                    let problem = Problem::new(
+                        format!("this session has two windows called `{window}`"),
+                        name_span,
+                    )
+                    .with_help(
+                        "windows without a name are called by their index, and the windows of \
+                         the template it extends come first",
+                    );
+                    self.error.push(index, problem);
+                    return;
+                }
+                self.insert(preset, (index, name_span));
+            }
+            Err(problems) => {
+                for problem in problems {
+                    self.error.push(index, problem);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, preset: Preset, (index, span): Location) {
+        if let Some(&(other, other_span)) = self.definitions.get(&preset.name) {
+            let first = self.error.location(other, other_span.offset());
+            let problem = Problem::new(
+                format!("there's already a preset called `{}`", preset.name),
+                span,
+            )
+            .with_label("second definition")
+            .with_help(format!("the first one is at {first}"));
+            self.error.push(index, problem);
+            return;
+        }
+
+        self.definitions.insert(preset.name.clone(), (index, span));
+        self.presets.insert(preset.name.clone(), preset);
+    }
+
+    /// Makes sure no two windows of the session or template `node` have the same name, since
+    /// one of them would be unreachable by name
+    fn check_windows(&mut self, node: &KdlNode, index: usize) {
+        for (name, first, span) in duplicate_windows(node) {
+            let first = self.error.location(index, first.offset());
+            let problem = Problem::new(format!("there's already a window called `{name}`"), span)
+                .with_label("second definition")
+                .with_help(format!("the first one is at {first}"));
+            self.error.push(index, problem);
+        }
+    }
+
+    /// Reports a problem with the `include` node at `site`, or with no file at all
+    fn problem(&mut self, site: Option<Location>, message: String) {
+        match site {
+            Some((index, span)) => self.error.push(index, Problem::new(message, span)),
+            None => self.error.push(None, Problem::new(message, (0, 0).into())),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Result<BTreeMap<String, Preset>, ConfigError> {
+        let (presets, error) = self.finish_all();
+        if error.has_errors() {
+            Err(error)
+        } else {
+            Ok(presets)
+        }
+    }
+
+    /// The presets that loaded, and everything wrong with the rest
+    fn finish_all(mut self) -> (BTreeMap<String, Preset>, ConfigError) {
+        self.templates.resolve(&mut self.error);
+        for (node, index, source) in std::mem::take(&mut self.sessions) {
+            self.load_session(&node, index, source);
+        }
+        (self.presets, self.error)
+    }
+}
+
+/// Every window of the session or template `node` whose `name` was written for an earlier
+/// one already, along with where that was first and where it is again
+fn duplicate_windows(node: &KdlNode) -> Vec<(&str, SourceSpan, SourceSpan)> {
+    let mut windows: HashMap<&str, SourceSpan> = HashMap::new();
+    let mut duplicates = vec![];
+    for window in node
+        .iter_children()
+        .filter(|child| !is_session_setting(child))
+    {
+        let Some(entry) = window.entry("name") else {
+            continue;
+        };
+        let Some(name) = entry.value().as_string() else {
+            continue;
+        };
+        match windows.get(name) {
+            Some(&first) => duplicates.push((name, first, entry.span())),
+            None => {
+                windows.insert(name, entry.span());
+            }
+        }
+    }
+    duplicates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory to write presets into, unique to the test `name`
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("muffin-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_load_directory() {
+        let dir = temp_dir("load");
+        std::fs::create_dir(dir.join("shared")).unwrap();
+        std::fs::write(
+            dir.join("personal.kdl"),
+            "include \"shared/team.kdl\"\nsession name=\"home\"",
+        )
+        .unwrap();
+        std::fs::write(dir.join("shared/team.kdl"), "session name=\"work\"").unwrap();
+        // Including something that was loaded already is fine
+        std::fs::write(dir.join("work.kdl"), "include \"personal.kdl\"").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a preset").unwrap();
+
+        let presets = load_presets(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<&str> = presets.keys().map(String::as_str).collect();
+        assert_eq!(names, ["home", "work"]);
+        assert_eq!(presets["home"].source, Some(dir.join("personal.kdl")));
+        assert_eq!(presets["work"].source, Some(dir.join("shared/team.kdl")));
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let dir = temp_dir("cycle");
+        std::fs::write(dir.join("a.kdl"), "include \"b.kdl\"").unwrap();
+        std::fs::write(dir.join("b.kdl"), "session name=\"b\"\ninclude \"a.kdl\"").unwrap();
+
+        let error = load_presets(&dir.join("a.kdl")).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let a = dir.join("a.kdl").display().to_string();
+        let b = dir.join("b.kdl").display().to_string();
+        assert_eq!(
+            error.to_string(),
+            format!("{b}:2:1: files include each other: {a} -> {b} -> {a}")
+        );
+    }
+
+    #[test]
+    fn test_name_collision() {
+        let dir = temp_dir("collision");
+        std::fs::write(dir.join("a.kdl"), "session name=\"work\"").unwrap();
+        std::fs::write(dir.join("b.kdl"), "\nsession name=\"work\" cwd=\"/\"").unwrap();
+
+        let error = load_presets(&dir).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let a = dir.join("a.kdl").display().to_string();
+        let b = dir.join("b.kdl").display().to_string();
+        assert_eq!(
+            error.to_string(),
+            format!("{b}:2:9: there's already a preset called `work`")
+        );
+        assert_eq!(
+            error.problems()[0].help,
+            Some(format!("the first one is at {a}:1:9"))
+        );
+    }
+
+    #[test]
+    fn test_lint() {
+        let dir = temp_dir("lint");
+        let text = r#"session name="work" cwd="/nonexistent/work" {
+  param "dir"
+  on_create "RUST_LOG=debug cd . && muffin-no-such-program"
+  window name="edit" {
+    split {
+      pane size="30%" command="ls"
+      pane size="30%" command="/nonexistent/bin/tool"
+    }
+  }
+  window name="run" cwd="{{dir}}" { pane command="./run.sh"; }
+}
+session name="broken" { windw; }
+"#;
+        std::fs::write(dir.join("presets.kdl"), text).unwrap();
+
+        let (presets, error) = lint_presets(&dir.join("presets.kdl"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Warnings don't keep a preset from loading, unlike errors
+        assert_eq!(presets.keys().collect::<Vec<_>>(), ["work"]);
+        assert!(error.has_errors());
+        let problems: Vec<(bool, &str)> = error
+            .problems()
+            .iter()
+            .map(|problem| (problem.is_warning(), problem.message.as_str()))
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                (true, "`/nonexistent/work` isn't a directory"),
+                (true, "sizes in this split only add up to 60%"),
+                (true, "`/nonexistent/bin/tool` isn't on $PATH"),
+                (false, "unknown session child node `windw`"),
+            ]
+        );
+
+        // Loading without linting only cares about the error
+        let error = crate::parse_config(text).unwrap_err();
+        assert_eq!(error.problems().len(), 1);
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let error = load_presets(Path::new("/nonexistent/presets.kdl")).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("failed to read /nonexistent/presets.kdl: ")
+        );
+        // Nothing to point at, but it still makes a report
+        assert_eq!(error.reports().count(), 1);
+    }
+}
@@ -0,0 +1,583 @@
+use std::fmt::Write;
+
+use kdl::{KdlDocument, KdlNode};
+use tmux::{Env, LayoutNode, Preset, Size, SplitDirection, Window};
+
+use crate::{ConfigError, is_session_setting, parse_session, parse_window};
+
+const INDENT: &str = "  ";
+
+/// Builds a fresh document holding `presets`
+pub fn to_document<'a>(presets: impl IntoIterator<Item = &'a Preset>) -> KdlDocument {
+    serialize_presets(presets)
+        .parse()
+        .expect("serialized presets are valid KDL")
+}
+
+/// Writes `preset` into `doc`, replacing the session of the same name or appending a new one.
+///
+/// Sessions and windows that already describe the same thing are left alone, so that whatever
+/// comments and formatting the user gave them survive.
+pub fn update_document(doc: &mut KdlDocument, preset: &Preset) {
+    let mut new: KdlDocument = serialize_preset(preset)
+        .parse()
+        .expect("serialized presets are valid KDL");
+    let mut new = new.nodes_mut().remove(0);
+
+    let Some(old) = doc
+        .nodes_mut()
+        .iter_mut()
+        .find(|node| is_session(node, &preset.name))
+    else {
+        // Keep a blank line between sessions
+        if let Some(last) = doc.nodes_mut().last_mut()
+            && let Some(format) = last.format_mut()
+            && format.terminator.is_empty()
+        {
+            format.terminator = "\n".to_string();
+        }
+        if !doc.nodes().is_empty()
+            && let Some(format) = new.format_mut()
+        {
+            format.leading = "\n".to_string();
+        }
+        doc.nodes_mut().push(new);
+        return;
+    };
+
+    let unchanged = parse_session(old).is_ok_and(|mut old| {
+        old.running = preset.running;
+        old.source = preset.source.clone();
+        old == *preset
+    });
+    if unchanged {
+        return;
+    }
+
+    // Windows are interpreted relative to the session they end up in, which is the new one
+    if let Some(old_children) = old.children()
+        && let Some(new_children) = new.children_mut()
+    {
+        let old_windows: Vec<&KdlNode> = old_children
+            .nodes()
+            .iter()
+            .filter(|node| !is_session_setting(node))
+            .collect();
+        for (idx, (node, window)) in new_children
+            .nodes_mut()
+            .iter_mut()
+            .filter(|node| !is_session_setting(node))
+            .zip(&preset.windows)
+            .enumerate()
+        {
+            if let Some(old_window) = old_windows.get(idx)
+                && unchanged_window(old_window, idx, preset, window)
+            {
+                *node = (*old_window).clone();
+            }
+        }
+    }
+
+    if let Some(old_format) = old.format()
+        && let Some(format) = new.format_mut()
+    {
+        format.leading = old_format.leading.clone();
+        format.terminator = old_format.terminator.clone();
+        format.trailing = old_format.trailing.clone();
+    }
+    *old = new;
+}
+
+/// Whether the window node `old` still parses, without problems, into `window`
+fn unchanged_window(old: &KdlNode, idx: usize, preset: &Preset, window: &Window) -> bool {
+    let mut problems = vec![];
+    let parsed = parse_window(old, idx, &preset.cwd, &preset.env, &mut problems);
+    problems.is_empty() && parsed.as_ref() == Ok(window)
+}
+
+/// Writes `preset` into the KDL config `doc_str`, see [`update_document`]
+pub fn update_config(doc_str: &str, preset: &Preset) -> Result<String, ConfigError> {
+    let mut doc: KdlDocument = doc_str.parse()?;
+    update_document(&mut doc, preset);
+    Ok(doc.to_string())
+}
+
+/// Removes the session called `name` from `doc`, returning whether there was one
+pub fn remove_from_document(doc: &mut KdlDocument, name: &str) -> bool {
+    let len = doc.nodes().len();
+    doc.nodes_mut().retain(|node| !is_session(node, name));
+    doc.nodes().len() != len
+}
+
+fn is_session(node: &KdlNode, name: &str) -> bool {
+    node.name().value() == "session" && node.get("name").and_then(|n| n.as_string()) == Some(name)
+}
+
+/// Writes `presets` as KDL that [`parse_config`](crate::parse_config) reads back into the
+/// same presets
+pub fn serialize_presets<'a>(presets: impl IntoIterator<Item = &'a Preset>) -> String {
+    presets
+        .into_iter()
+        .map(serialize_preset)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Writes the `session` node for `preset`, leaving out anything the parser would infer anyway
+pub fn serialize_preset(preset: &Preset) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "session name={} cwd={} {{",
+        quote(&preset.name),
+        quote(&preset.cwd)
+    )
+    .unwrap();
+    for param in &preset.params {
+        write!(out, "{INDENT}param {}", quote(&param.name)).unwrap();
+        if let Some(default) = &param.default {
+            write!(out, " default={}", quote(default)).unwrap();
+        }
+        out.push('\n');
+    }
+    write_env(&mut out, &preset.env, &Env::new(), 1);
+    let hooks = [
+        ("on_create", &preset.hooks.on_create),
+        ("on_attach", &preset.hooks.on_attach),
+        ("on_detach", &preset.hooks.on_detach),
+        ("on_kill", &preset.hooks.on_kill),
+    ];
+    for (name, command) in hooks {
+        if let Some(command) = command {
+            writeln!(out, "{INDENT}{name} {}", quote(command)).unwrap();
+        }
+    }
+
+    for window in &preset.windows {
+        write!(out, "{INDENT}window name={}", quote(&window.name)).unwrap();
+        if window.cwd != preset.cwd {
+            write!(out, " cwd={}", quote(&window.cwd)).unwrap();
+        }
+
+        // A window with nothing but a shell in its cwd is what the parser assumes by default
+        let is_default = matches!(
+            &window.layout,
+            LayoutNode::Pane { cwd, command: None, env, .. }
+                if *cwd == window.cwd && *env == window.env
+        );
+        if is_default && window.env == preset.env {
+            out.push('\n');
+        } else {
+            out.push_str(" {\n");
+            write_env(&mut out, &window.env, &preset.env, 2);
+            if !is_default {
+                write_layout(&mut out, &window.layout, &window.cwd, &window.env, 2);
+            }
+            writeln!(out, "{INDENT}}}").unwrap();
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+fn write_layout(
+    out: &mut String,
+    node: &LayoutNode,
+    window_cwd: &str,
+    window_env: &Env,
+    depth: usize,
+) {
+    let indent = INDENT.repeat(depth);
+    // The window's root node always takes up all of it, whatever its size says
+    let size = match node.size() {
+        _ if depth == 2 => String::new(),
+        size if size == Size::default() => String::new(),
+        Size::Weight(weight) => format!(" size={weight}"),
+        Size::Percent(percent) => format!(" size=\"{percent}%\""),
+        Size::Cells(cells) => format!(" size=\"{cells}c\""),
+    };
+
+    match node {
+        LayoutNode::Pane {
+            cwd, command, env, ..
+        } => {
+            write!(out, "{indent}pane").unwrap();
+            if cwd != window_cwd {
+                write!(out, " cwd={}", quote(cwd)).unwrap();
+            }
+            if let Some(command) = command {
+                write!(out, " command={}", quote(command)).unwrap();
+            }
+            if env == window_env {
+                writeln!(out, "{size}").unwrap();
+            } else {
+                writeln!(out, "{size} {{").unwrap();
+                write_env(out, env, window_env, depth + 1);
+                writeln!(out, "{indent}}}").unwrap();
+            }
+        }
+        LayoutNode::Split {
+            direction,
+            children,
+            ..
+        } => {
+            let direction = match direction {
+                SplitDirection::Horizontal => "h",
+                SplitDirection::Vertical => "v",
+            };
+            writeln!(out, "{indent}split direction=\"{direction}\"{size} {{").unwrap();
+
+            for child in children {
+                write_layout(out, child, window_cwd, window_env, depth + 1);
+            }
+            writeln!(out, "{indent}}}").unwrap();
+        }
+    }
+}
+
+/// Writes an `env` block with whatever `env` doesn't already inherit from `parent_env`
+fn write_env(out: &mut String, env: &Env, parent_env: &Env, depth: usize) {
+    let mut vars = env
+        .iter()
+        .filter(|(key, value)| parent_env.get(*key) != Some(*value))
+        .peekable();
+    if vars.peek().is_none() {
+        return;
+    }
+
+    let indent = INDENT.repeat(depth);
+    writeln!(out, "{indent}env {{").unwrap();
+    for (key, value) in vars {
+        writeln!(out, "{indent}{INDENT}{} {}", identifier(key), quote(value)).unwrap();
+    }
+    writeln!(out, "{indent}}}").unwrap();
+}
+
+/// Writes `s` as a bare KDL identifier if it is one, like most variable names, and quotes it
+/// otherwise
+fn identifier(s: &str) -> String {
+    let is_bare = s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !matches!(s, "true" | "false" | "null" | "inf" | "nan");
+    if is_bare { s.to_string() } else { quote(s) }
+}
+
+/// Quotes `s` as a KDL string
+fn quote(s: &str) -> String {
+    // Code points KDL either treats as newlines or doesn't allow in a document at all
+    let is_disallowed = |c: char| {
+        c.is_control()
+            || matches!(
+                c,
+                '\u{200E}'..='\u{200F}'
+                    | '\u{202A}'..='\u{202E}'
+                    | '\u{2066}'..='\u{2069}'
+                    | '\u{2028}'
+                    | '\u{2029}'
+                    | '\u{FEFF}'
+            )
+    };
+
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if is_disallowed(c) => write!(quoted, "\\u{{{:x}}}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_config, set_size};
+    use proptest::prelude::*;
+    use tmux::{Hooks, Param, Window};
+
+    #[test]
+    fn test_serialize_presets() {
+        let doc_str = std::fs::read_to_string("../examples/presets.kdl").unwrap();
+        let presets = parse_config(&doc_str).unwrap();
+
+        let serialized = serialize_presets(presets.values());
+        assert_eq!(parse_config(&serialized).unwrap(), presets);
+
+        let school = serialize_preset(&presets["School"]);
+        assert!(school.contains("\n  window name=\"Class A\" cwd=\"~/School/ClassA\"\n"));
+        assert!(
+            school.contains("\n    split direction=\"h\" {\n      pane command=\"nvim\" size=3\n")
+        );
+    }
+
+    #[test]
+    fn test_serialize_env() {
+        let doc_str = r#"session name="work" cwd="/tmp" {
+  env {
+    RUST_LOG "info"
+  }
+  window name="server" {
+    env {
+      KUBECONFIG "/tmp/kube"
+    }
+    split direction="v" {
+      pane command="cargo run" {
+        env {
+          "MY VAR" "1"
+          RUST_LOG "debug"
+        }
+      }
+      pane
+    }
+  }
+}
+"#;
+        let presets = parse_config(doc_str).unwrap();
+        assert_eq!(serialize_preset(&presets["work"]), doc_str);
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("my session"), r#""my session""#);
+        assert_eq!(quote(r#"echo "hi" \o/"#), r#""echo \"hi\" \\o/""#);
+        assert_eq!(quote("a\tb"), r#""a\tb""#);
+        assert_eq!(quote("\u{2028}"), r#""\u{2028}""#);
+    }
+
+    #[test]
+    fn test_update_document() {
+        let original = r#"// Hand-written, please keep this comment
+session name="work" cwd="~/work" {
+  window name="editor" { // where the magic happens
+    pane   command="nvim"
+  }
+  window name="shell"
+}
+
+/- session name="disabled"
+
+session name="other"
+"#;
+        let mut doc: KdlDocument = original.parse().unwrap();
+        let mut presets = parse_config(original).unwrap();
+
+        // Nothing changed, so nothing is rewritten
+        update_document(&mut doc, &presets["work"]);
+        assert_eq!(doc.to_string(), original);
+
+        let work = presets.get_mut("work").unwrap();
+        work.windows[1].name = "logs".to_string();
+        update_document(&mut doc, work);
+        let updated = doc.to_string();
+        assert!(updated.starts_with("// Hand-written, please keep this comment\n"));
+        assert!(updated.contains("  window name=\"editor\" { // where the magic happens\n"));
+        assert!(updated.contains("  window name=\"logs\"\n"));
+        assert!(updated.contains("/- session name=\"disabled\""));
+        assert_eq!(parse_config(&updated).unwrap(), presets);
+
+        let mut new = presets["other"].clone();
+        new.name = "new".to_string();
+        update_document(&mut doc, &new);
+        assert!(
+            doc.to_string()
+                .contains("session name=\"other\"\n\nsession name=\"new\" cwd=")
+        );
+        assert!(remove_from_document(&mut doc, "other"));
+        assert!(!remove_from_document(&mut doc, "other"));
+
+        let names: Vec<String> = parse_config(&doc.to_string())
+            .unwrap()
+            .into_keys()
+            .collect();
+        assert_eq!(names, ["new", "work"]);
+    }
+
+    fn any_string() -> impl Strategy<Value = String> {
+        "[a-zA-Z0-9 ~/._\\-\"\t\n{}=$é\u{2028}]{0,12}"
+    }
+
+    /// Strings that expand once spawned, so every `$` in them has to be escaped
+    fn any_expanded() -> impl Strategy<Value = String> {
+        any_string().prop_map(|s| s.replace('$', "$$"))
+    }
+
+    /// Up to 3 percentages per split can never add up to more than 100%
+    fn any_size() -> impl Strategy<Value = Size> {
+        prop_oneof![
+            (1..=u32::MAX).prop_map(Size::Weight),
+            (1..=33u8).prop_map(Size::Percent),
+            (1..=u16::MAX).prop_map(Size::Cells),
+        ]
+    }
+
+    /// Variables that only make sense on top of their parent's, see [`inherit_env`]
+    fn any_env() -> impl Strategy<Value = Env> {
+        let key = prop_oneof!["[A-Z_]{1,6}", "[a-z0-9 \"é]{1,6}"];
+        proptest::collection::btree_map(key, any_expanded(), 0..3)
+    }
+
+    /// Layouts shaped like the ones `parse_config` produces
+    fn any_layout() -> impl Strategy<Value = LayoutNode> {
+        let pane = (
+            any_expanded(),
+            proptest::option::of(any_expanded()),
+            any_env(),
+            any_size(),
+        )
+            .prop_map(|(cwd, command, env, size)| LayoutNode::Pane {
+                cwd,
+                command,
+                env,
+                size,
+            });
+
+        pane.prop_recursive(3, 16, 4, |inner| {
+            let children = proptest::collection::vec(inner, 1..4);
+            (any::<bool>(), children, any_size()).prop_map(|(horizontal, children, size)| {
+                let direction = if horizontal {
+                    SplitDirection::Horizontal
+                } else {
+                    SplitDirection::Vertical
+                };
+                LayoutNode::Split {
+                    direction,
+                    children,
+                    size,
+                }
+            })
+        })
+    }
+
+    /// Puts every variable of `parent_env` that `env` doesn't override into it, like the
+    /// parser does
+    fn inherit_env(env: &mut Env, parent_env: &Env) {
+        for (key, value) in parent_env {
+            env.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+
+    fn inherit_pane_env(node: &mut LayoutNode, window_env: &Env) {
+        match node {
+            LayoutNode::Pane { env, .. } => inherit_env(env, window_env),
+            LayoutNode::Split { children, .. } => {
+                for child in children {
+                    inherit_pane_env(child, window_env);
+                }
+            }
+        }
+    }
+
+    fn any_preset() -> impl Strategy<Value = Preset> {
+        let window = (any_string(), any_expanded(), any_env(), any_layout()).prop_map(
+            |(name, cwd, env, mut layout)| {
+                set_size(&mut layout, Size::default());
+                Window {
+                    name,
+                    cwd,
+                    env,
+                    layout,
+                }
+            },
+        );
+        let command = || proptest::option::of(any_string());
+        let hooks = (command(), command(), command(), command()).prop_map(
+            |(on_create, on_attach, on_detach, on_kill)| Hooks {
+                on_create,
+                on_attach,
+                on_detach,
+                on_kill,
+            },
+        );
+        (
+            any_string(),
+            any_expanded(),
+            any_env(),
+            hooks,
+            proptest::collection::btree_map("[a-z_]{1,6}", command(), 0..3),
+            proptest::collection::vec(window, 1..4),
+        )
+            .prop_map(|(name, cwd, env, hooks, params, mut windows)| {
+                // Windows of a preset can't share a name
+                let mut names = std::collections::HashSet::new();
+                for window in &mut windows {
+                    while !names.insert(window.name.clone()) {
+                        window.name.push('_');
+                    }
+                    inherit_env(&mut window.env, &env);
+                    inherit_pane_env(&mut window.layout, &window.env);
+                }
+                let params = params
+                    .into_iter()
+                    .map(|(name, default)| Param { name, default })
+                    .collect();
+                let mut preset = Preset {
+                    name,
+                    cwd,
+                    env,
+                    hooks,
+                    params,
+                    running: false,
+                    source: None,
+                    windows,
+                    origin: None,
+                };
+                declare_placeholders(&mut preset);
+                preset
+            })
+    }
+
+    /// Declares whatever parameters the strings of `preset` happen to refer to, which the
+    /// parser insists on
+    fn declare_placeholders(preset: &mut Preset) {
+        fn layout_strings<'a>(node: &'a LayoutNode, out: &mut Vec<&'a str>) {
+            match node {
+                LayoutNode::Pane { cwd, command, .. } => {
+                    out.push(cwd);
+                    out.extend(command.as_deref());
+                }
+                LayoutNode::Split { children, .. } => {
+                    for child in children {
+                        layout_strings(child, out);
+                    }
+                }
+            }
+        }
+
+        let mut strings = vec![preset.name.as_str(), preset.cwd.as_str()];
+        for window in &preset.windows {
+            strings.extend([window.name.as_str(), window.cwd.as_str()]);
+            layout_strings(&window.layout, &mut strings);
+        }
+        let mut missing: Vec<String> = vec![];
+        for name in strings.into_iter().flat_map(tmux::placeholders) {
+            if !preset.params.iter().any(|p| p.name == name) && !missing.iter().any(|m| m == name) {
+                missing.push(name.to_string());
+            }
+        }
+        preset.params.extend(missing.into_iter().map(|name| Param {
+            name,
+            default: None,
+        }));
+    }
+
+    proptest! {
+        #[test]
+        fn test_serialize_round_trip(preset in any_preset()) {
+            let parsed = parse_config(&serialize_preset(&preset)).unwrap();
+            prop_assert_eq!(&parsed[&preset.name], &preset);
+
+            let mut doc = to_document([&preset]);
+            update_document(&mut doc, &preset);
+            let parsed = parse_config(&doc.to_string()).unwrap();
+            prop_assert_eq!(&parsed[&preset.name], &preset);
+        }
+    }
+}
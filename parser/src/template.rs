@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+
+use kdl::{KdlDocument, KdlNode, KdlValue};
+
+use crate::{ConfigError, HOOKS, Problem, parse_session, string_prop};
+
+/// A `template` node, which reads like a session but is only ever used through the sessions
+/// (and other templates) that extend it
+struct Template {
+    name: String,
+    node: KdlNode,
+    /// Source the node is in
+    index: usize,
+}
+
+/// Every template in the files being loaded, so sessions can extend ones from any of them
+#[derive(Default)]
+pub(crate) struct Templates {
+    templates: Vec<Template>,
+    by_name: HashMap<String, usize>,
+    /// What each template looks like with everything it extends merged in, or `None` if that
+    /// failed and was reported already
+    resolved: Vec<Option<KdlNode>>,
+}
+
+impl Templates {
+    /// Adds the `template` node `node` from source `index`
+    pub(crate) fn define(&mut self, node: &KdlNode, index: usize, error: &mut ConfigError) {
+        let name = match string_prop(node, "name") {
+            Ok(Some(name)) => name.to_string(),
+            Ok(None) => {
+                let problem = Problem::new("template is missing a name", node.name().span())
+                    .with_help("name it like `template name=\"rust\"`");
+                error.push(index, problem);
+                return;
+            }
+            Err(problem) => {
+                error.push(index, problem);
+                return;
+            }
+        };
+
+        if let Some(&other) = self.by_name.get(&name) {
+            let other = &self.templates[other];
+            let span = node.entry("name").unwrap().span();
+            let first_span = other.node.entry("name").unwrap().span();
+            let first = error.location(other.index, first_span.offset());
+            let problem = Problem::new(format!("there's already a template called `{name}`"), span)
+                .with_label("second definition")
+                .with_help(format!("the first one is at {first}"));
+            error.push(index, problem);
+            return;
+        }
+
+        self.by_name.insert(name.clone(), self.templates.len());
+        self.templates.push(Template {
+            name,
+            node: node.clone(),
+            index,
+        });
+    }
+
+    /// Checks every template and merges in what they extend, which has to happen once all of
+    /// them are defined
+    pub(crate) fn resolve(&mut self, error: &mut ConfigError) {
+        let mut resolved = vec![None; self.templates.len()];
+        let mut done = vec![false; self.templates.len()];
+        for i in 0..self.templates.len() {
+            self.resolve_one(i, &mut resolved, &mut done, &mut vec![], error);
+        }
+        self.resolved = resolved;
+    }
+
+    fn resolve_one(
+        &self,
+        i: usize,
+        resolved: &mut Vec<Option<KdlNode>>,
+        done: &mut Vec<bool>,
+        chain: &mut Vec<usize>,
+        error: &mut ConfigError,
+    ) {
+        if done[i] {
+            return;
+        }
+        let template = &self.templates[i];
+
+        // Templates are checked on their own, so that problems point at them rather than at
+        // every session that extends them
+        let mut node = template.node.clone();
+        node.set_name("session");
+        if let Err(problems) = parse_session(&node) {
+            for problem in problems {
+                error.push(template.index, problem);
+            }
+            done[i] = true;
+            return;
+        }
+
+        let base = match self.parent(&template.node) {
+            Ok(None) => None,
+            Ok(Some(parent)) => {
+                if let Some(start) = chain.iter().position(|&t| t == parent) {
+                    let cycle: Vec<&str> = chain[start..]
+                        .iter()
+                        .chain([&i, &parent])
+                        .map(|&t| self.templates[t].name.as_str())
+                        .collect();
+                    let span = template.node.entry("extends").unwrap().span();
+                    let message = format!("templates extend each other: {}", cycle.join(" -> "));
+                    error.push(template.index, Problem::new(message, span));
+                    done[i] = true;
+                    return;
+                }
+
+                chain.push(i);
+                self.resolve_one(parent, resolved, done, chain, error);
+                chain.pop();
+                match &resolved[parent] {
+                    Some(base) => Some(base.clone()),
+                    // Whatever is wrong with the parent was reported already
+                    None => {
+                        done[i] = true;
+                        return;
+                    }
+                }
+            }
+            Err(problem) => {
+                error.push(template.index, problem);
+                done[i] = true;
+                return;
+            }
+        };
+
+        resolved[i] = Some(match base {
+            Some(base) => merge(&base, &node),
+            None => node,
+        });
+        done[i] = true;
+    }
+
+    /// Merges whatever template `session` extends into it, returning `None` if that template
+    /// is broken and has been reported already
+    pub(crate) fn apply(&self, session: &KdlNode) -> Result<Option<KdlNode>, Problem> {
+        match self.parent(session)? {
+            Some(parent) => Ok(self.resolved[parent]
+                .as_ref()
+                .map(|base| merge(base, session))),
+            None => Ok(Some(session.clone())),
+        }
+    }
+
+    /// The template that `node` extends, if any
+    fn parent(&self, node: &KdlNode) -> Result<Option<usize>, Problem> {
+        let Some(name) = string_prop(node, "extends")? else {
+            return Ok(None);
+        };
+        match self.by_name.get(name) {
+            Some(&parent) => Ok(Some(parent)),
+            None => Err(Problem::new(
+                format!("there's no template called `{name}`"),
+                node.entry("extends").unwrap().span(),
+            )
+            .with_help(format!(
+                "define it like `template name=\"{name}\" {{ ... }}`"
+            ))),
+        }
+    }
+}
+
+/// Builds `child` on top of `base`.
+///
+/// The child's `cwd` wins if it has one, its variables are added after the base's, and its
+/// hooks and windows replace those of the base with the same name. Any other windows of the
+/// child come after the base's.
+fn merge(base: &KdlNode, child: &KdlNode) -> KdlNode {
+    let mut merged = child.clone();
+    merged.remove("extends");
+    if merged.entry("cwd").is_none()
+        && let Some(cwd) = base.entry("cwd")
+    {
+        merged.push(cwd.clone());
+    }
+
+    let base_children = base.children().map(KdlDocument::nodes).unwrap_or_default();
+    let child_children = child.children().map(KdlDocument::nodes).unwrap_or_default();
+    let replacement = |node: &KdlNode| child_children.iter().find(|c| replaces(c, node));
+
+    let mut nodes: Vec<KdlNode> = base_children
+        .iter()
+        .map(|node| replacement(node).unwrap_or(node).clone())
+        .collect();
+    nodes.extend(
+        child_children
+            .iter()
+            .filter(|c| {
+                !base_children
+                    .iter()
+                    .any(|node| replacement(node).is_some_and(|r| std::ptr::eq(r, *c)))
+            })
+            .cloned(),
+    );
+
+    if !nodes.is_empty() {
+        let mut doc = KdlDocument::new();
+        *doc.nodes_mut() = nodes;
+        merged.set_children(doc);
+    }
+    merged
+}
+
+/// Whether `node` of a session takes the place of `base` from its template
+fn replaces(node: &KdlNode, base: &KdlNode) -> bool {
+    let name = node.name().value();
+    if name != base.name().value() {
+        return false;
+    }
+    // Windows without a name are told apart by position, which means nothing across files
+    fn window_name(node: &KdlNode) -> Option<&str> {
+        node.get("name").and_then(KdlValue::as_string)
+    }
+    // Parameters are named by their argument instead
+    fn param_name(node: &KdlNode) -> Option<&str> {
+        node.entries().first()?.value().as_string()
+    }
+    HOOKS.contains(&name)
+        || (name == "param" && param_name(node).is_some_and(|n| param_name(base) == Some(n)))
+        || (name == "window" && window_name(node).is_some_and(|n| window_name(base) == Some(n)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tmux::{Hooks, LayoutNode};
+
+    use crate::parse_config;
+
+    #[test]
+    fn test_extends() {
+        let doc_str = r#"template name="base" cwd="/srv" {
+  env { RUST_LOG "info"; EDITOR "nvim"; }
+  on_create "make deps"
+  on_kill "make clean"
+  window name="editor" { pane command="nvim"; }
+  window name="shell"
+}
+
+template name="rust" extends="base" {
+  env { RUST_LOG "debug"; }
+  window name="tests" { pane command="cargo watch -x test"; }
+}
+
+session name="api" cwd="/src/api" extends="rust" {
+  on_kill "docker compose down"
+  window name="shell" cwd="/tmp"
+  window name="logs"
+}
+
+session name="plain" extends="base"
+"#;
+        let presets = parse_config(doc_str).unwrap();
+
+        let api = &presets["api"];
+        assert_eq!(api.cwd, "/src/api");
+        assert_eq!(api.env["RUST_LOG"], "debug");
+        assert_eq!(api.env["EDITOR"], "nvim");
+        assert_eq!(
+            api.hooks,
+            Hooks {
+                on_create: Some("make deps".to_string()),
+                on_kill: Some("docker compose down".to_string()),
+                ..Hooks::default()
+            }
+        );
+        let windows: Vec<(&str, &str)> = api
+            .windows
+            .iter()
+            .map(|w| (w.name.as_str(), w.cwd.as_str()))
+            .collect();
+        // Template windows are laid out in the session's directory
+        assert_eq!(
+            windows,
+            [
+                ("editor", "/src/api"),
+                ("shell", "/tmp"),
+                ("tests", "/src/api"),
+                ("logs", "/src/api")
+            ]
+        );
+        assert!(matches!(
+            &api.windows[0].layout,
+            LayoutNode::Pane { command: Some(command), .. } if command == "nvim"
+        ));
+
+        // Without a cwd of its own, a session uses its template's
+        assert_eq!(presets["plain"].cwd, "/srv");
+        assert_eq!(presets["plain"].env["RUST_LOG"], "info");
+        // Templates are not presets themselves
+        assert_eq!(presets.len(), 2);
+    }
+
+    #[test]
+    fn test_template_errors() {
+        let error = |doc_str: &str| {
+            let error = parse_config(doc_str).unwrap_err();
+            assert_eq!(error.problems().len(), 1, "{error}");
+            error.to_string()
+        };
+
+        assert_eq!(
+            error("session name=\"a\" extends=\"nope\""),
+            "presets.kdl:1:18: there's no template called `nope`"
+        );
+        assert_eq!(
+            error(
+                "template name=\"a\" extends=\"b\"\ntemplate name=\"b\" extends=\"a\"\nsession name=\"s\" extends=\"a\""
+            ),
+            "presets.kdl:2:19: templates extend each other: a -> b -> a"
+        );
+        assert_eq!(
+            error("template name=\"a\"\ntemplate name=\"a\""),
+            "presets.kdl:2:10: there's already a template called `a`"
+        );
+        // Reported once at the template, not again for the session that uses it
+        assert_eq!(
+            error("template name=\"a\" { widnow; }\nsession name=\"s\" extends=\"a\""),
+            "presets.kdl:1:21: unknown session child node `widnow`"
+        );
+    }
+}
@@ -0,0 +1,578 @@
+use std::ffi::OsStr;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::{Error, Origin, PaneInfo, Session, SplitDirection, WindowInfo};
+
+/// Everything muffin needs from a tmux server.
+///
+/// [`CommandBackend`] talks to a real server through the `tmux` binary, while
+/// [`FakeBackend`](crate::fake::FakeBackend) keeps an in-memory model around for tests.
+pub trait TmuxBackend {
+    fn list_sessions(&self) -> Result<Vec<Session>, Error>;
+
+    /// Creates a detached session starting in `cwd`. An empty name lets tmux pick one.
+    ///
+    /// `env` ends up in both the session's environment and the first pane's.
+    fn create_session(
+        &self,
+        name: &str,
+        cwd: Option<&str>,
+        env: &[(&str, &str)],
+    ) -> Result<(), Error>;
+
+    /// Sets `key` in the environment of the `session`, which panes created from then on
+    /// inherit, or removes it if `value` is `None`
+    fn set_environment(&self, session: &str, key: &str, value: Option<&str>) -> Result<(), Error>;
+
+    fn rename_session(&self, target: &str, new_name: &str) -> Result<(), Error>;
+
+    fn kill_session(&self, target: &str) -> Result<(), Error>;
+
+    /// Switches the current client to `target`. Only works from inside tmux.
+    fn switch_session(&self, target: &str) -> Result<(), Error>;
+
+    /// Attaches to `target`. On a real server this replaces the current process and only
+    /// returns on failure.
+    fn attach_session(&self, target: &str) -> Result<(), Error>;
+
+    fn detach_session(&self) -> Result<(), Error>;
+
+    /// Runs the tmux `command` whenever `hook` (like `client-attached`) fires for `session`,
+    /// replacing whatever command the session had for it before
+    fn set_hook(&self, session: &str, hook: &str, command: &str) -> Result<(), Error>;
+
+    /// Sets the `option` of `session`, like a user option such as `@muffin-preset`
+    fn set_option(&self, session: &str, option: &str, value: &str) -> Result<(), Error>;
+
+    fn list_windows(&self, session: &str) -> Result<Vec<WindowInfo>, Error>;
+
+    /// Lists the panes of the `window` target
+    fn list_panes(&self, window: &str) -> Result<Vec<PaneInfo>, Error>;
+
+    /// Creates a window at the end of `session` whose first pane starts in `cwd`, with `env` on
+    /// top of the session's environment. Returns the id of the window, which unlike its name
+    /// is sure to be unique.
+    fn new_window(
+        &self,
+        session: &str,
+        name: &str,
+        cwd: &str,
+        env: &[(&str, &str)],
+    ) -> Result<String, Error>;
+
+    fn rename_window(&self, target: &str, new_name: &str) -> Result<(), Error>;
+
+    /// Kills the `target` window, along with its session if it was the last window in it
+    fn kill_window(&self, target: &str) -> Result<(), Error>;
+
+    /// Kills the `target` pane, along with its window if it was the last pane in it
+    fn kill_pane(&self, target: &str) -> Result<(), Error>;
+
+    /// Moves the `window` target to the end of `session`, without switching to it
+    fn move_window(&self, window: &str, session: &str) -> Result<(), Error>;
+
+    /// Swaps the places of two windows, which may be in different sessions
+    fn swap_window(&self, source: &str, target: &str) -> Result<(), Error>;
+
+    /// Swaps the places of two panes, which may be in different windows
+    fn swap_pane(&self, source: &str, target: &str) -> Result<(), Error>;
+
+    /// Makes the `target` window the current one of its session
+    fn select_window(&self, target: &str) -> Result<(), Error>;
+
+    /// Makes the `target` pane the active one of its window
+    fn select_pane(&self, target: &str) -> Result<(), Error>;
+
+    /// Splits the `target` pane, starting the new pane in `cwd` with `env` on top of the
+    /// session's environment.
+    /// Returns the id of the new pane, e.g. `%4`.
+    fn split_window(
+        &self,
+        target: &str,
+        direction: SplitDirection,
+        cwd: &str,
+        env: &[(&str, &str)],
+    ) -> Result<String, Error>;
+
+    /// Arranges the panes of the `target` window, either with one of tmux's preset layouts
+    /// (like `tiled`) or a full layout string
+    fn select_layout(&self, target: &str, layout: &str) -> Result<(), Error>;
+
+    /// Width and height of the `target` window, in cells
+    fn window_size(&self, target: &str) -> Result<(u16, u16), Error>;
+
+    fn send_keys(&self, target: &str, keys: &[&str]) -> Result<(), Error>;
+
+    /// What the `target` pane shows right now, one line per row, with escape sequences for its
+    /// colors and text attributes
+    fn capture_pane(&self, target: &str) -> Result<String, Error>;
+}
+
+/// Which tmux server to talk to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Server {
+    /// Whatever tmux picks without `-L` or `-S`
+    #[default]
+    Default,
+    /// Socket name inside tmux's socket directory, passed as `-L`
+    Name(String),
+    /// Full socket path, passed as `-S`
+    Path(PathBuf),
+}
+
+impl Server {
+    /// Server that the current process runs inside of, according to `$TMUX`
+    pub fn from_env() -> Option<Self> {
+        Self::from_tmux_var(&std::env::var("TMUX").ok()?)
+    }
+
+    /// Parses `$TMUX`, which looks like `<socket path>,<server pid>,<session id>`
+    pub fn from_tmux_var(value: &str) -> Option<Self> {
+        let (path, _) = value.split_once(',')?;
+        (!path.is_empty()).then(|| Self::Path(path.into()))
+    }
+
+    /// Interprets `socket` as a path if it contains a `/`, and as a socket name otherwise
+    pub fn from_socket(socket: &str) -> Self {
+        if socket.contains('/') {
+            Self::Path(socket.into())
+        } else {
+            Self::Name(socket.to_string())
+        }
+    }
+
+    fn args(&self) -> Vec<&OsStr> {
+        match self {
+            Server::Default => vec![],
+            Server::Name(name) => vec!["-L".as_ref(), name.as_ref()],
+            Server::Path(path) => vec!["-S".as_ref(), path.as_ref()],
+        }
+    }
+}
+
+/// Backend that shells out to the `tmux` binary on `$PATH`.
+#[derive(Debug, Clone, Default)]
+pub struct CommandBackend {
+    server: Server,
+}
+
+impl CommandBackend {
+    pub fn new(server: Server) -> Self {
+        Self { server }
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new("tmux");
+        command.args(self.server.args());
+        command
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String, Error> {
+        let output = self.command().args(args).output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::from_stderr(args[0], &stderr));
+        }
+
+        String::from_utf8(output.stdout)
+            .map_err(|_| Error::Parse(format!("{} printed invalid UTF-8", args[0])))
+    }
+}
+
+// Fields are separated by tabs, which tmux escapes inside session names and muffin inside its
+// options
+const SESSION_FORMAT: &str = "#{session_id}\t#{session_name}\t#{session_windows}\t\
+    #{session_attached}\t#{session_created}\t#{session_last_attached}\t\
+    #{session_activity}\t#{session_group}\t#{@muffin-preset}\t#{@muffin-params}\t\
+    #{session_path}";
+
+const WINDOW_FORMAT: &str = "#{window_id}\t#{window_index}\t#{window_active}\t\
+    #{window_panes}\t#{window_layout}\t#{window_name}";
+
+// Paths come last, since they are the only thing that may contain a tab
+const PANE_FORMAT: &str = "#{pane_id}\t#{pane_index}\t#{pane_active}\t#{pane_width}\t\
+    #{pane_height}\t#{pane_current_command}\t#{pane_current_path}";
+
+impl TmuxBackend for CommandBackend {
+    fn list_sessions(&self) -> Result<Vec<Session>, Error> {
+        let output = self.run(&["list-sessions", "-F", SESSION_FORMAT])?;
+
+        output.lines().map(parse_session).collect()
+    }
+
+    fn create_session(
+        &self,
+        name: &str,
+        cwd: Option<&str>,
+        env: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        let mut args = vec!["new-session", "-d"];
+        if !name.is_empty() {
+            args.extend(["-s", name]);
+        }
+        if let Some(cwd) = cwd {
+            check_dir(cwd)?;
+            args.extend(["-c", cwd]);
+        }
+        let env = env_args(env);
+        args.extend(env.iter().map(String::as_str));
+        self.run(&args).map(|_| ())
+    }
+
+    fn set_environment(&self, session: &str, key: &str, value: Option<&str>) -> Result<(), Error> {
+        match value {
+            Some(value) => self.run(&["set-environment", "-t", session, key, value]),
+            None => self.run(&["set-environment", "-t", session, "-u", key]),
+        }
+        .map(|_| ())
+    }
+
+    fn rename_session(&self, target: &str, new_name: &str) -> Result<(), Error> {
+        self.run(&["rename-session", "-t", target, new_name])
+            .map(|_| ())
+    }
+
+    fn kill_session(&self, target: &str) -> Result<(), Error> {
+        self.run(&["kill-session", "-t", target]).map(|_| ())
+    }
+
+    fn switch_session(&self, target: &str) -> Result<(), Error> {
+        self.run(&["switch-client", "-t", target]).map(|_| ())
+    }
+
+    fn attach_session(&self, target: &str) -> Result<(), Error> {
+        // This replaces the current process
+        let error = self.command().args(["attach-session", "-t", target]).exec();
+
+        // If exec() returns, it means it failed.
+        Err(error.into())
+    }
+
+    fn detach_session(&self) -> Result<(), Error> {
+        self.run(&["detach"]).map(|_| ())
+    }
+
+    fn set_hook(&self, session: &str, hook: &str, command: &str) -> Result<(), Error> {
+        self.run(&["set-hook", "-t", session, hook, command])
+            .map(|_| ())
+    }
+
+    fn set_option(&self, session: &str, option: &str, value: &str) -> Result<(), Error> {
+        self.run(&["set-option", "-t", session, option, value])
+            .map(|_| ())
+    }
+
+    fn list_windows(&self, session: &str) -> Result<Vec<WindowInfo>, Error> {
+        let output = self.run(&["list-windows", "-t", session, "-F", WINDOW_FORMAT])?;
+
+        output.lines().map(parse_window).collect()
+    }
+
+    fn list_panes(&self, window: &str) -> Result<Vec<PaneInfo>, Error> {
+        let output = self.run(&["list-panes", "-t", window, "-F", PANE_FORMAT])?;
+
+        output.lines().map(parse_pane).collect()
+    }
+
+    fn new_window(
+        &self,
+        session: &str,
+        name: &str,
+        cwd: &str,
+        env: &[(&str, &str)],
+    ) -> Result<String, Error> {
+        check_dir(cwd)?;
+        // `=` keeps tmux from settling for a session whose name merely starts with `session`,
+        // and the colon from taking it for a window of the current one
+        let target = format!("={session}:");
+        let mut args = vec![
+            "new-window",
+            "-t",
+            &target,
+            "-n",
+            name,
+            "-c",
+            cwd,
+            "-P",
+            "-F",
+            "#{window_id}",
+        ];
+        let env = env_args(env);
+        args.extend(env.iter().map(String::as_str));
+        let output = self.run(&args)?;
+        Ok(output.trim().to_string())
+    }
+
+    fn rename_window(&self, target: &str, new_name: &str) -> Result<(), Error> {
+        self.run(&["rename-window", "-t", target, new_name])
+            .map(|_| ())
+    }
+
+    fn kill_window(&self, target: &str) -> Result<(), Error> {
+        self.run(&["kill-window", "-t", target]).map(|_| ())
+    }
+
+    fn kill_pane(&self, target: &str) -> Result<(), Error> {
+        self.run(&["kill-pane", "-t", target]).map(|_| ())
+    }
+
+    fn move_window(&self, window: &str, session: &str) -> Result<(), Error> {
+        // Without a window index, tmux picks the next free one
+        let session = format!("{session}:");
+        self.run(&["move-window", "-d", "-s", window, "-t", &session])
+            .map(|_| ())
+    }
+
+    fn swap_window(&self, source: &str, target: &str) -> Result<(), Error> {
+        self.run(&["swap-window", "-d", "-s", source, "-t", target])
+            .map(|_| ())
+    }
+
+    fn swap_pane(&self, source: &str, target: &str) -> Result<(), Error> {
+        self.run(&["swap-pane", "-d", "-s", source, "-t", target])
+            .map(|_| ())
+    }
+
+    fn select_window(&self, target: &str) -> Result<(), Error> {
+        self.run(&["select-window", "-t", target]).map(|_| ())
+    }
+
+    fn select_pane(&self, target: &str) -> Result<(), Error> {
+        self.run(&["select-pane", "-t", target]).map(|_| ())
+    }
+
+    fn split_window(
+        &self,
+        target: &str,
+        direction: SplitDirection,
+        cwd: &str,
+        env: &[(&str, &str)],
+    ) -> Result<String, Error> {
+        check_dir(cwd)?;
+        let direction_flag = match direction {
+            SplitDirection::Horizontal => "-h",
+            SplitDirection::Vertical => "-v",
+        };
+        let mut args = vec![
+            "split-window",
+            "-t",
+            target,
+            direction_flag,
+            "-c",
+            cwd,
+            "-P",
+            "-F",
+            "#{pane_id}",
+        ];
+        let env = env_args(env);
+        args.extend(env.iter().map(String::as_str));
+        let output = self.run(&args)?;
+        Ok(output.trim().to_string())
+    }
+
+    fn select_layout(&self, target: &str, layout: &str) -> Result<(), Error> {
+        self.run(&["select-layout", "-t", target, layout])
+            .map(|_| ())
+    }
+
+    fn window_size(&self, target: &str) -> Result<(u16, u16), Error> {
+        let output = self.run(&[
+            "display-message",
+            "-p",
+            "-t",
+            target,
+            "#{window_width}x#{window_height}",
+        ])?;
+        output
+            .trim()
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+            .ok_or_else(|| Error::Parse(format!("invalid window size `{}`", output.trim())))
+    }
+
+    fn send_keys(&self, target: &str, keys: &[&str]) -> Result<(), Error> {
+        let mut args = vec!["send-keys", "-t", target];
+        args.extend_from_slice(keys);
+        self.run(&args).map(|_| ())
+    }
+
+    fn capture_pane(&self, target: &str) -> Result<String, Error> {
+        self.run(&["capture-pane", "-p", "-e", "-t", target])
+    }
+}
+
+/// tmux quietly starts panes somewhere else when their directory doesn't exist, so check first
+fn check_dir(cwd: &str) -> Result<(), Error> {
+    if Path::new(cwd).is_dir() {
+        Ok(())
+    } else {
+        Err(Error::NoSuchDirectory(cwd.to_string()))
+    }
+}
+
+/// `-e KEY=value` for each variable, as taken by new-session, new-window and split-window
+fn env_args(env: &[(&str, &str)]) -> Vec<String> {
+    env.iter()
+        .flat_map(|(key, value)| ["-e".to_string(), format!("{key}={value}")])
+        .collect()
+}
+
+fn parse_session(line: &str) -> Result<Session, Error> {
+    let fields: Vec<&str> = line.splitn(11, '\t').collect();
+    let [
+        id,
+        name,
+        windows,
+        attached,
+        created,
+        last_attached,
+        activity,
+        group,
+        preset,
+        params,
+        path,
+    ] = fields[..]
+    else {
+        return Err(Error::Parse(format!("unexpected session format: `{line}`")));
+    };
+
+    let number = |field: &str| {
+        field
+            .parse::<u64>()
+            .map_err(|_| Error::Parse(format!("invalid number `{field}` in session `{name}`")))
+    };
+    let optional = |field: &str| (!field.is_empty()).then(|| field.to_string());
+
+    Ok(Session {
+        id: id.to_string(),
+        name: name.to_string(),
+        windows: number(windows)? as usize,
+        attached: number(attached)? as usize,
+        created: number(created)?,
+        last_attached: optional(last_attached).map(|t| number(&t)).transpose()?,
+        activity: number(activity)?,
+        group: optional(group),
+        path: path.to_string(),
+        origin: Origin::from_options(preset, params),
+    })
+}
+
+fn parse_window(line: &str) -> Result<WindowInfo, Error> {
+    let fields: Vec<&str> = line.splitn(6, '\t').collect();
+    let [id, index, active, panes, layout, name] = fields[..] else {
+        return Err(Error::Parse(format!("unexpected window format: `{line}`")));
+    };
+
+    let number = |field: &str| {
+        field
+            .parse::<usize>()
+            .map_err(|_| Error::Parse(format!("invalid number `{field}` in window `{name}`")))
+    };
+
+    Ok(WindowInfo {
+        id: id.to_string(),
+        index: number(index)?,
+        name: name.to_string(),
+        layout: layout.to_string(),
+        active: active == "1",
+        panes: number(panes)?,
+    })
+}
+
+fn parse_pane(line: &str) -> Result<PaneInfo, Error> {
+    let fields: Vec<&str> = line.splitn(7, '\t').collect();
+    let [id, index, active, width, height, command, cwd] = fields[..] else {
+        return Err(Error::Parse(format!("unexpected pane format: `{line}`")));
+    };
+
+    let invalid = |field: &str| Error::Parse(format!("invalid number `{field}` in pane `{id}`"));
+
+    Ok(PaneInfo {
+        id: id.to_string(),
+        index: index.parse().map_err(|_| invalid(index))?,
+        active: active == "1",
+        width: width.parse().map_err(|_| invalid(width))?,
+        height: height.parse().map_err(|_| invalid(height))?,
+        command: command.to_string(),
+        cwd: cwd.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_session() {
+        let session =
+            parse_session("$3\tmy: session\t2\t1\t1700000000\t\t1700000100\t\t\t\t/home/me")
+                .unwrap();
+
+        assert_eq!(session.id, "$3");
+        assert_eq!(session.name, "my: session");
+        assert_eq!(session.windows, 2);
+        assert!(session.is_attached());
+        assert_eq!(session.last_attached, None);
+        assert_eq!(session.activity, 1700000100);
+        assert_eq!(session.group, None);
+        assert_eq!(session.path, "/home/me");
+        assert_eq!(session.origin, None);
+
+        let session =
+            parse_session("$4\tapi-3000\t1\t0\t1\t\t1\t\tapi-{{port}}\tport=3000\t/a\tb").unwrap();
+        assert_eq!(session.origin.unwrap().params["port"], "3000");
+        assert_eq!(session.path, "/a\tb");
+    }
+
+    #[test]
+    fn test_parse_window_and_pane() {
+        let window = parse_window("@4\t1\t1\t2\tb25d,80x24,0,0,0\tmy\twindow").unwrap();
+        assert_eq!(window.id, "@4");
+        assert_eq!(window.index, 1);
+        assert!(window.active);
+        assert_eq!(window.panes, 2);
+        assert_eq!(window.name, "my\twindow");
+
+        let pane = parse_pane("%7\t0\t0\t80\t24\tnvim\t/home/me/src").unwrap();
+        assert_eq!(pane.id, "%7");
+        assert!(!pane.active);
+        assert_eq!((pane.width, pane.height), (80, 24));
+        assert_eq!(pane.command, "nvim");
+        assert_eq!(pane.cwd, "/home/me/src");
+
+        assert!(parse_pane("%7\t0\t0\twide\t24\tnvim\t/").is_err());
+    }
+
+    #[test]
+    fn test_server_selection() {
+        assert_eq!(
+            Server::from_tmux_var("/tmp/tmux-1000/work,4242,3"),
+            Some(Server::Path("/tmp/tmux-1000/work".into()))
+        );
+        assert_eq!(Server::from_tmux_var(""), None);
+
+        assert_eq!(Server::from_socket("work"), Server::Name("work".into()));
+        assert_eq!(
+            Server::from_socket("/run/tmux/personal"),
+            Server::Path("/run/tmux/personal".into())
+        );
+
+        let backend = CommandBackend::new(Server::Name("work".into()));
+        let command = backend.command();
+        let args: Vec<&OsStr> = command.get_args().collect();
+        assert_eq!(args, ["-L", "work"]);
+    }
+
+    #[test]
+    fn test_parse_session_malformed() {
+        assert!(parse_session("main: 1 windows (attached)").is_err());
+        assert!(parse_session("$1\tmain\tmany\t0\t1\t\t1\t\t\t\t/").is_err());
+    }
+}
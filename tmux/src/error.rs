@@ -0,0 +1,176 @@
+use std::fmt;
+
+/// Everything that can go wrong while talking to tmux.
+#[derive(Debug)]
+pub enum Error {
+    /// The `tmux` binary is not on `$PATH`
+    NotInstalled,
+    /// Nothing is listening on the selected server socket
+    NoServer,
+    SessionNotFound(String),
+    DuplicateSession(String),
+    /// tmux exited unsuccessfully for any other reason
+    Command {
+        command: String,
+        stderr: String,
+    },
+    /// tmux printed something we didn't expect
+    Parse(String),
+    /// One of a preset's host-side hooks, like `on_create`, didn't succeed
+    Hook {
+        hook: String,
+        message: String,
+    },
+    /// A preset value couldn't be expanded, like when it uses a variable that isn't set
+    Expand {
+        /// Which value it was, e.g. "the cwd of window `main`"
+        field: String,
+        message: String,
+    },
+    /// A pane was supposed to start in a directory that doesn't exist
+    NoSuchDirectory(String),
+    /// Spawning a preset failed partway through, after which whatever was created of its
+    /// session was killed again
+    Spawn {
+        session: String,
+        step: SpawnStep,
+        source: Box<Error>,
+        /// Whether killing the half-built session worked
+        rolled_back: bool,
+    },
+    /// Running tmux failed for any other reason
+    Io(std::io::Error),
+}
+
+/// The part of a preset that was being spawned when something went wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpawnStep {
+    /// Setting up the session itself, like its environment
+    Session,
+    /// Creating or arranging a window
+    Window(String),
+    /// Creating a pane of a window, or starting its command. Panes count from 1, in the order
+    /// of the preset's layout.
+    Pane { window: String, pane: usize },
+    /// Handing the `on_attach` and `on_detach` hooks over to tmux
+    Hooks,
+}
+
+impl Error {
+    /// Classifies the error message printed by a failed tmux `command`
+    pub(crate) fn from_stderr(command: &str, stderr: &str) -> Self {
+        let stderr = stderr.trim();
+
+        if stderr.starts_with("no server running on")
+            || (stderr.starts_with("error connecting to")
+                && (stderr.ends_with("(No such file or directory)")
+                    || stderr.ends_with("(Connection refused)")))
+        {
+            Error::NoServer
+        } else if let Some(session) = stderr.strip_prefix("can't find session: ") {
+            Error::SessionNotFound(session.to_string())
+        } else if let Some(session) = stderr.strip_prefix("duplicate session: ") {
+            Error::DuplicateSession(session.to_string())
+        } else {
+            Error::Command {
+                command: command.to_string(),
+                stderr: stderr.to_string(),
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotInstalled => write!(f, "tmux is not installed or not on $PATH"),
+            Error::NoServer => write!(f, "no tmux server is running"),
+            Error::SessionNotFound(session) => write!(f, "session `{session}` not found"),
+            Error::DuplicateSession(session) => write!(f, "session `{session}` already exists"),
+            Error::Command { command, stderr } => write!(f, "tmux {command} failed: {stderr}"),
+            Error::Parse(msg) => write!(f, "unexpected output from tmux: {msg}"),
+            Error::Hook { hook, message } => write!(f, "`{hook}` hook failed: {message}"),
+            Error::Expand { field, message } => write!(f, "couldn't expand {field}: {message}"),
+            Error::NoSuchDirectory(path) => write!(f, "`{path}` is not a directory"),
+            Error::Spawn {
+                session,
+                step,
+                source,
+                rolled_back,
+            } => {
+                write!(f, "couldn't spawn `{session}`, {step} failed: {source}")?;
+                if !rolled_back {
+                    write!(f, " (and the half-built session couldn't be killed)")?;
+                }
+                Ok(())
+            }
+            Error::Io(e) => write!(f, "failed to run tmux: {e}"),
+        }
+    }
+}
+
+impl fmt::Display for SpawnStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnStep::Session => write!(f, "setting up the session"),
+            SpawnStep::Window(window) => write!(f, "window `{window}`"),
+            SpawnStep::Pane { window, pane } => write!(f, "pane {pane} of window `{window}`"),
+            SpawnStep::Hooks => write!(f, "setting its hooks"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Spawn { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => Error::NotInstalled,
+            _ => Error::Io(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_stderr() {
+        assert!(matches!(
+            Error::from_stderr(
+                "list-sessions",
+                "no server running on /tmp/tmux-1000/default\n"
+            ),
+            Error::NoServer
+        ));
+        assert!(matches!(
+            Error::from_stderr(
+                "list-sessions",
+                "error connecting to /tmp/tmux-1000/work (No such file or directory)\n"
+            ),
+            Error::NoServer
+        ));
+        assert!(matches!(
+            Error::from_stderr("kill-session", "can't find session: work\n"),
+            Error::SessionNotFound(s) if s == "work"
+        ));
+        assert!(matches!(
+            Error::from_stderr("new-session", "duplicate session: work\n"),
+            Error::DuplicateSession(s) if s == "work"
+        ));
+        assert!(matches!(
+            Error::from_stderr("switch-client", "no current client\n"),
+            Error::Command { command, stderr }
+                if command == "switch-client" && stderr == "no current client"
+        ));
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::layout::{self, Cell, Content};
+use crate::{
+    Env, Error, Hooks, LayoutNode, PaneInfo, Preset, Size, SplitDirection, TmuxBackend, Window,
+};
+
+/// Programs that just mean "nothing is running in this pane"
+const SHELLS: [&str; 10] = [
+    "bash", "zsh", "fish", "sh", "dash", "ksh", "tcsh", "csh", "nu", "elvish",
+];
+
+/// Rebuilds a [`Preset`] from a running session, so that it can be saved and spawned again
+/// later.
+///
+/// Windows whose name was taken already get a number after it, like `bash-2`.
+///
+/// Panes keep their current working directory and, unless it's just a shell, the program
+/// running in the foreground. Only the program's name is known to tmux, so any arguments it
+/// was started with are lost.
+pub fn export_session(tmux: &dyn TmuxBackend, session: &str) -> Result<Preset, Error> {
+    let mut windows = Vec::new();
+    for window in tmux.list_windows(session)? {
+        let panes: HashMap<String, PaneInfo> = tmux
+            .list_panes(&window.id)?
+            .into_iter()
+            .map(|pane| (pane.id.clone(), pane))
+            .collect();
+
+        let cell = layout::parse_layout(&window.layout)?;
+        let layout = to_layout_node(&cell, Size::default(), &panes)?;
+        // Presets can't have two windows with the same name, which tmux doesn't mind at all
+        let mut name = window.name.clone();
+        for n in 2.. {
+            if !windows.iter().any(|w: &Window| w.name == name) {
+                break;
+            }
+            name = format!("{}-{n}", window.name);
+        }
+        windows.push(Window {
+            name,
+            cwd: layout.first_cwd().to_string(),
+            env: Env::new(),
+            layout,
+        });
+    }
+
+    let cwd = windows
+        .first()
+        .map(|w| w.cwd.clone())
+        .unwrap_or_else(|| "~".to_string());
+    Ok(Preset {
+        name: session.to_string(),
+        cwd,
+        // tmux only tells what's in the session's environment, which also holds whatever
+        // update-environment copied from clients, so leave it out rather than guess
+        env: Env::new(),
+        hooks: Hooks::default(),
+        params: vec![],
+        running: true,
+        source: None,
+        windows,
+        origin: None,
+    })
+}
+
+fn to_layout_node(
+    cell: &Cell,
+    size: Size,
+    panes: &HashMap<String, PaneInfo>,
+) -> Result<LayoutNode, Error> {
+    match &cell.content {
+        Content::Pane(id) => {
+            let pane = panes
+                .get(&format!("%{id}"))
+                .ok_or_else(|| Error::Parse(format!("layout refers to unknown pane %{id}")))?;
+            Ok(LayoutNode::Pane {
+                cwd: escape(&contract_home(&pane.cwd)),
+                command: (!is_shell(&pane.command)).then(|| escape(&pane.command)),
+                env: Env::new(),
+                size,
+            })
+        }
+        Content::Split(direction, children) => {
+            let extent = |c: &Cell| match direction {
+                SplitDirection::Horizontal => c.width as u32,
+                SplitDirection::Vertical => c.height as u32,
+            };
+            let total: u32 = children.iter().map(extent).sum::<u32>().max(1);
+
+            let children = children
+                .iter()
+                .map(|child| {
+                    // Weights out of 100 read like the percentages they roughly are
+                    let percent = ((extent(child) * 100 + total / 2) / total).max(1);
+                    to_layout_node(child, Size::Weight(percent), panes)
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(LayoutNode::Split {
+                direction: *direction,
+                children,
+                size,
+            })
+        }
+    }
+}
+
+fn is_shell(command: &str) -> bool {
+    // Login shells show up as e.g. `-bash`
+    let command = command.trim_start_matches('-');
+    let user_shell = std::env::var("SHELL").ok();
+    let user_shell = user_shell
+        .as_deref()
+        .and_then(|shell| Path::new(shell).file_name()?.to_str());
+
+    command.is_empty() || SHELLS.contains(&command) || user_shell == Some(command)
+}
+
+/// Keeps a `$` in `text` from being taken for a variable once the preset is spawned
+fn escape(text: &str) -> String {
+    text.replace('$', "$$")
+}
+
+/// Turns `/home/me/src` back into `~/src`, which keeps presets portable between machines
+fn contract_home(path: &str) -> String {
+    let Ok(home) = std::env::var("HOME") else {
+        return path.to_string();
+    };
+    let home = home.trim_end_matches('/');
+    if home.is_empty() {
+        return path.to_string();
+    }
+
+    match path.strip_prefix(home) {
+        Some("") => "~".to_string(),
+        Some(rest) if rest.starts_with('/') => format!("~{rest}"),
+        _ => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{FakeBackend, pane, preset, split, window};
+    use crate::spawn_preset;
+
+    #[test]
+    fn test_export_session() {
+        let tmux = FakeBackend::new();
+        let preset = preset(
+            "project",
+            vec![
+                window(
+                    "editor",
+                    split(
+                        SplitDirection::Horizontal,
+                        vec![
+                            pane("/tmp", Some("nvim"), 70),
+                            split(
+                                SplitDirection::Vertical,
+                                vec![
+                                    pane("/tmp/git", Some("lazygit"), 50),
+                                    pane("/tmp", Some("zsh"), 50),
+                                ],
+                                30,
+                            ),
+                        ],
+                        100,
+                    ),
+                ),
+                Window {
+                    cwd: "/var/log".to_string(),
+                    ..window("logs", pane("/var/log", Some("tail -f syslog"), 100))
+                },
+            ],
+        );
+        spawn_preset(&tmux, &preset).unwrap();
+
+        let exported = export_session(&tmux, "project").unwrap();
+        assert_eq!(exported.name, "project");
+        assert_eq!(exported.cwd, "/tmp");
+        assert!(exported.running);
+        assert_eq!(exported.windows.len(), 2);
+
+        let LayoutNode::Split {
+            direction: SplitDirection::Horizontal,
+            children,
+            ..
+        } = &exported.windows[0].layout
+        else {
+            panic!("expected a horizontal split");
+        };
+        assert!(matches!(
+            &children[0],
+            LayoutNode::Pane { cwd, command: Some(command), size: Size::Weight(70), .. }
+                if cwd == "/tmp" && command == "nvim"
+        ));
+        let LayoutNode::Split {
+            direction: SplitDirection::Vertical,
+            children: right,
+            size: Size::Weight(30),
+        } = &children[1]
+        else {
+            panic!("expected a vertical split");
+        };
+        // 23 rows can't be split evenly, so this comes back as 12 and 11
+        assert!(matches!(
+            &right[0],
+            LayoutNode::Pane { cwd, command: Some(command), size: Size::Weight(52), .. }
+                if cwd == "/tmp/git" && command == "lazygit"
+        ));
+        // Shells are what a pane runs anyway
+        assert!(matches!(
+            &right[1],
+            LayoutNode::Pane {
+                command: None,
+                size: Size::Weight(48),
+                ..
+            }
+        ));
+
+        // Only the program's name survives
+        assert!(matches!(
+            &exported.windows[1].layout,
+            LayoutNode::Pane { cwd, command: Some(command), size: Size::Weight(1), .. }
+                if cwd == "/var/log" && command == "tail"
+        ));
+        assert_eq!(exported.windows[1].cwd, "/var/log");
+    }
+
+    #[test]
+    fn test_export_repeated_window_names() {
+        let tmux = FakeBackend::new();
+        tmux.create_session("work", Some("/tmp"), &[]).unwrap();
+        tmux.new_window("work", "shell", "/tmp", &[]).unwrap();
+        tmux.new_window("work", "shell", "/tmp", &[]).unwrap();
+
+        let exported = export_session(&tmux, "work").unwrap();
+        let names: Vec<&str> = exported.windows.iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(names, ["shell", "shell-2", "shell-3"]);
+    }
+
+    #[test]
+    fn test_export_missing_session() {
+        let tmux = FakeBackend::new();
+        tmux.create_session("other", None, &[]).unwrap();
+        assert!(matches!(
+            export_session(&tmux, "nope"),
+            Err(Error::SessionNotFound(name)) if name == "nope"
+        ));
+    }
+}
@@ -0,0 +1,713 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::origin::{PARAMS_OPTION, PRESET_OPTION};
+use crate::{
+    Env, Error, Hooks, LayoutNode, Origin, PaneInfo, Preset, Session, Size, SplitDirection,
+    TmuxBackend, Window, WindowInfo, layout,
+};
+
+/// In-memory stand-in for a tmux server, used to test code that drives tmux without
+/// depending on whatever server happens to be running.
+///
+/// Clones share the same server, so a test can keep a handle around to inspect the
+/// result after handing a boxed copy to the code under test.
+#[derive(Debug, Clone, Default)]
+pub struct FakeBackend {
+    server: Rc<RefCell<FakeServer>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FakeSession {
+    pub id: usize,
+    pub name: String,
+    pub attached: usize,
+    /// Environment new panes inherit, on top of the (empty) global one
+    pub env: Env,
+    /// Commands set with `set-hook`, by hook name
+    pub hooks: BTreeMap<String, String>,
+    /// Values set with `set-option`, by option name
+    pub options: BTreeMap<String, String>,
+    pub windows: Vec<FakeWindow>,
+    /// Position of the current window in `windows`
+    pub active: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FakeWindow {
+    pub id: usize,
+    pub index: usize,
+    pub name: String,
+    pub panes: Vec<FakePane>,
+    pub width: u16,
+    pub height: u16,
+    /// Last layout applied with `select-layout`
+    pub layout: Option<String>,
+    /// Position of the active pane in `panes`
+    pub active: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FakePane {
+    pub id: usize,
+    pub cwd: String,
+    /// Environment the pane was started with
+    pub env: Env,
+    /// Every key sent to this pane, in order
+    pub keys: Vec<String>,
+}
+
+impl FakeWindow {
+    /// Last applied custom layout, or the panes stacked on top of each other if there is none
+    fn layout_string(&self) -> String {
+        let mut cell = self
+            .layout
+            .as_deref()
+            .and_then(|layout| layout::parse_layout(layout).ok())
+            .filter(|cell| cell.panes().len() == self.panes.len())
+            .unwrap_or_else(|| {
+                let stack = LayoutNode::Split {
+                    direction: SplitDirection::Vertical,
+                    children: vec![
+                        LayoutNode::Pane {
+                            cwd: String::new(),
+                            command: None,
+                            env: Env::new(),
+                            size: Size::default(),
+                        };
+                        self.panes.len()
+                    ],
+                    size: Size::default(),
+                };
+                let layout = layout::layout_string(&stack, self.width, self.height);
+                layout::parse_layout(&layout).expect("generated layouts are valid")
+            });
+        cell.set_pane_ids(&mut self.panes.iter().map(|p| p.id));
+        cell.to_layout_string()
+    }
+}
+
+impl FakePane {
+    /// Pretends the last command sent to the pane is still running in it
+    fn current_command(&self) -> String {
+        self.keys
+            .windows(2)
+            .rev()
+            .find(|keys| keys[1] == "Enter")
+            .and_then(|keys| keys[0].split_whitespace().next())
+            .unwrap_or("bash")
+            .to_string()
+    }
+
+    /// Pretends every command sent to the pane was echoed after a prompt
+    fn screen(&self) -> String {
+        let mut screen = String::from("$ ");
+        for key in &self.keys {
+            match key.as_str() {
+                "Enter" => screen.push_str("\n$ "),
+                key => screen.push_str(key),
+            }
+        }
+        screen.push('\n');
+        screen
+    }
+}
+
+#[derive(Debug, Default)]
+struct FakeServer {
+    sessions: Vec<FakeSession>,
+    next_id: usize,
+    /// Session the (single) fake client is looking at
+    client: Option<usize>,
+    /// Commands that fail no matter what, with what they print
+    failures: BTreeMap<String, String>,
+}
+
+impl FakeServer {
+    fn next_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn new_window(&mut self, index: usize, name: &str, pane: FakePane) -> FakeWindow {
+        FakeWindow {
+            id: self.next_id(),
+            index,
+            name: name.to_string(),
+            panes: vec![pane],
+            // Same as a detached session without a `default-size`
+            width: 80,
+            height: 24,
+            layout: None,
+            active: 0,
+        }
+    }
+
+    /// Pane started in `cwd`, with `env` on top of whatever it inherits from `session_env`
+    fn new_pane(&mut self, cwd: &str, session_env: &Env, env: &[(&str, &str)]) -> FakePane {
+        let mut pane_env = session_env.clone();
+        pane_env.extend(env.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        FakePane {
+            id: self.next_id(),
+            cwd: cwd.to_string(),
+            env: pane_env,
+            keys: vec![],
+        }
+    }
+
+    fn session(&self, target: &str) -> Result<usize, String> {
+        let found = match target.strip_prefix('$') {
+            Some(id) => self.sessions.iter().position(|s| id.parse() == Ok(s.id)),
+            None => self.sessions.iter().position(|s| s.name == target),
+        };
+        found.ok_or_else(|| format!("can't find session: {target}"))
+    }
+
+    /// Resolves a `session[:window[.pane]]` target, where each part may also be an id
+    /// (`$1`, `@2`, `%3`), to indices into the server's sessions, windows and panes.
+    fn resolve(&self, target: &str) -> Result<(usize, usize, usize), String> {
+        if let Some(id) = target.strip_prefix('%') {
+            return self
+                .find(|_, _, pane| id.parse() == Ok(pane.id))
+                .ok_or_else(|| format!("can't find pane: {id}"));
+        }
+        if let Some(id) = target.strip_prefix('@') {
+            let (s, w, _) = self
+                .find(|_, window, _| id.parse() == Ok(window.id))
+                .ok_or_else(|| format!("can't find window: {id}"))?;
+            return Ok((s, w, self.sessions[s].windows[w].active));
+        }
+
+        let (session, rest) = target.split_once(':').unwrap_or((target, ""));
+        let s = self.session(session)?;
+        if rest.is_empty() {
+            let w = self.sessions[s].active;
+            return Ok((s, w, self.sessions[s].windows[w].active));
+        }
+
+        let (window, pane) = rest.split_once('.').unwrap_or((rest, ""));
+        let windows = &self.sessions[s].windows;
+        let w = windows
+            .iter()
+            .position(|w| w.name == window || window.parse() == Ok(w.index))
+            .ok_or_else(|| format!("can't find window: {window}"))?;
+        if pane.is_empty() {
+            return Ok((s, w, windows[w].active));
+        }
+
+        let p = pane
+            .parse::<usize>()
+            .ok()
+            .filter(|&p| p < windows[w].panes.len())
+            .ok_or_else(|| format!("can't find pane: {pane}"))?;
+        Ok((s, w, p))
+    }
+
+    /// Removes the window at `w` of session `s`, and the session too if that was its last one
+    fn remove_window(&mut self, s: usize, w: usize) -> FakeWindow {
+        let session = &mut self.sessions[s];
+        let window = session.windows.remove(w);
+        if session.windows.is_empty() {
+            self.remove_session(s);
+        } else if session.active >= w {
+            session.active = session.active.saturating_sub(1);
+        }
+        window
+    }
+
+    fn remove_session(&mut self, s: usize) {
+        let session = self.sessions.remove(s);
+        if self.client == Some(session.id) {
+            self.client = None;
+        }
+    }
+
+    fn move_client(&mut self, target: &str) -> Result<(), String> {
+        let s = self.session(target)?;
+        if let Some(old) = self.client
+            && let Some(old) = self.sessions.iter_mut().find(|s| s.id == old)
+        {
+            old.attached -= 1;
+        }
+        self.sessions[s].attached += 1;
+        self.client = Some(self.sessions[s].id);
+        Ok(())
+    }
+
+    fn find(
+        &self,
+        predicate: impl Fn(&FakeSession, &FakeWindow, &FakePane) -> bool,
+    ) -> Option<(usize, usize, usize)> {
+        self.sessions.iter().enumerate().find_map(|(s, session)| {
+            session.windows.iter().enumerate().find_map(|(w, window)| {
+                window
+                    .panes
+                    .iter()
+                    .position(|pane| predicate(session, window, pane))
+                    .map(|p| (s, w, p))
+            })
+        })
+    }
+}
+
+impl FakeBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Snapshot of every session on the fake server
+    pub fn sessions(&self) -> Vec<FakeSession> {
+        self.server.borrow().sessions.clone()
+    }
+
+    pub fn session(&self, name: &str) -> Option<FakeSession> {
+        self.sessions().into_iter().find(|s| s.name == name)
+    }
+
+    /// Name of the session the fake client is currently attached to
+    pub fn client_session(&self) -> Option<String> {
+        let server = self.server.borrow();
+        let id = server.client?;
+        server
+            .sessions
+            .iter()
+            .find(|s| s.id == id)
+            .map(|s| s.name.clone())
+    }
+
+    /// Makes every later call to the tmux `command` fail, printing `stderr`
+    pub fn fail(&self, command: &str, stderr: &str) {
+        self.server
+            .borrow_mut()
+            .failures
+            .insert(command.to_string(), stderr.to_string());
+    }
+
+    /// Runs `f` against the server, turning its tmux-style error message into an [`Error`]
+    /// the same way [`CommandBackend`](crate::CommandBackend) would
+    fn run<T>(
+        &self,
+        command: &str,
+        f: impl FnOnce(&mut FakeServer) -> Result<T, String>,
+    ) -> Result<T, Error> {
+        let mut server = self.server.borrow_mut();
+        let result = match server.failures.get(command) {
+            Some(stderr) => Err(stderr.clone()),
+            None => f(&mut server),
+        };
+        result.map_err(|stderr| Error::from_stderr(command, &stderr))
+    }
+}
+
+impl TmuxBackend for FakeBackend {
+    fn list_sessions(&self) -> Result<Vec<Session>, Error> {
+        self.run("list-sessions", |server| {
+            if server.sessions.is_empty() {
+                // Like the real thing, the server exits along with its last session
+                return Err("no server running on /tmp/fake".to_string());
+            }
+
+            Ok(server
+                .sessions
+                .iter()
+                .map(|s| Session {
+                    id: format!("${}", s.id),
+                    name: s.name.clone(),
+                    windows: s.windows.len(),
+                    attached: s.attached,
+                    created: 0,
+                    last_attached: None,
+                    activity: 0,
+                    group: None,
+                    path: s.windows[0].panes[0].cwd.clone(),
+                    origin: Origin::from_options(
+                        s.options.get(PRESET_OPTION).map_or("", String::as_str),
+                        s.options.get(PARAMS_OPTION).map_or("", String::as_str),
+                    ),
+                })
+                .collect())
+        })
+    }
+
+    fn create_session(
+        &self,
+        name: &str,
+        cwd: Option<&str>,
+        env: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        self.run("new-session", |server| {
+            let name = if name.is_empty() {
+                // tmux numbers unnamed sessions after the next session id
+                server.next_id.to_string()
+            } else {
+                name.replace([':', '.'], "_")
+            };
+            if server.sessions.iter().any(|s| s.name == name) {
+                return Err(format!("duplicate session: {name}"));
+            }
+
+            let id = server.next_id();
+            let env: Env = env
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            let pane = server.new_pane(cwd.unwrap_or("/"), &env, &[]);
+            let window = server.new_window(0, "shell", pane);
+            server.sessions.push(FakeSession {
+                id,
+                name,
+                attached: 0,
+                env,
+                hooks: BTreeMap::new(),
+                options: BTreeMap::new(),
+                windows: vec![window],
+                active: 0,
+            });
+            Ok(())
+        })
+    }
+
+    fn set_environment(&self, session: &str, key: &str, value: Option<&str>) -> Result<(), Error> {
+        self.run("set-environment", |server| {
+            let s = server.session(session)?;
+            let env = &mut server.sessions[s].env;
+            match value {
+                Some(value) => env.insert(key.to_string(), value.to_string()),
+                None => env.remove(key),
+            };
+            Ok(())
+        })
+    }
+
+    fn rename_session(&self, target: &str, new_name: &str) -> Result<(), Error> {
+        self.run("rename-session", |server| {
+            let s = server.session(target)?;
+            let new_name = new_name.replace([':', '.'], "_");
+            let mut others = server.sessions.iter().enumerate().filter(|&(i, _)| i != s);
+            if others.any(|(_, session)| session.name == new_name) {
+                return Err(format!("duplicate session: {new_name}"));
+            }
+            server.sessions[s].name = new_name;
+            Ok(())
+        })
+    }
+
+    fn kill_session(&self, target: &str) -> Result<(), Error> {
+        self.run("kill-session", |server| {
+            let s = server.session(target)?;
+            server.remove_session(s);
+            Ok(())
+        })
+    }
+
+    fn switch_session(&self, target: &str) -> Result<(), Error> {
+        self.run("switch-client", |server| {
+            if server.client.is_none() {
+                return Err("no current client".to_string());
+            }
+            server.move_client(target)
+        })
+    }
+
+    fn attach_session(&self, target: &str) -> Result<(), Error> {
+        self.run("attach-session", |server| server.move_client(target))
+    }
+
+    fn detach_session(&self) -> Result<(), Error> {
+        self.run("detach-client", |server| {
+            let id = server.client.take().ok_or("no current client")?;
+            if let Some(session) = server.sessions.iter_mut().find(|s| s.id == id) {
+                session.attached -= 1;
+            }
+            Ok(())
+        })
+    }
+
+    fn set_hook(&self, session: &str, hook: &str, command: &str) -> Result<(), Error> {
+        self.run("set-hook", |server| {
+            let s = server.session(session)?;
+            server.sessions[s]
+                .hooks
+                .insert(hook.to_string(), command.to_string());
+            Ok(())
+        })
+    }
+
+    fn set_option(&self, session: &str, option: &str, value: &str) -> Result<(), Error> {
+        self.run("set-option", |server| {
+            let s = server.session(session)?;
+            server.sessions[s]
+                .options
+                .insert(option.to_string(), value.to_string());
+            Ok(())
+        })
+    }
+
+    fn list_windows(&self, session: &str) -> Result<Vec<WindowInfo>, Error> {
+        self.run("list-windows", |server| {
+            let s = server.session(session)?;
+            Ok(server.sessions[s]
+                .windows
+                .iter()
+                .enumerate()
+                .map(|(i, w)| WindowInfo {
+                    id: format!("@{}", w.id),
+                    index: w.index,
+                    name: w.name.clone(),
+                    layout: w.layout_string(),
+                    active: i == server.sessions[s].active,
+                    panes: w.panes.len(),
+                })
+                .collect())
+        })
+    }
+
+    fn list_panes(&self, window: &str) -> Result<Vec<PaneInfo>, Error> {
+        self.run("list-panes", |server| {
+            let (s, w, _) = server.resolve(window)?;
+            let window = &server.sessions[s].windows[w];
+            let cell = layout::parse_layout(&window.layout_string()).expect("valid layout");
+
+            Ok(window
+                .panes
+                .iter()
+                .zip(cell.panes())
+                .enumerate()
+                .map(|(i, (pane, cell))| PaneInfo {
+                    id: format!("%{}", pane.id),
+                    index: i,
+                    active: i == window.active,
+                    width: cell.width,
+                    height: cell.height,
+                    command: pane.current_command(),
+                    cwd: pane.cwd.clone(),
+                })
+                .collect())
+        })
+    }
+
+    fn new_window(
+        &self,
+        session: &str,
+        name: &str,
+        cwd: &str,
+        env: &[(&str, &str)],
+    ) -> Result<String, Error> {
+        self.run("new-window", |server| {
+            let s = server.session(session)?;
+            let index = server.sessions[s]
+                .windows
+                .iter()
+                .map(|w| w.index + 1)
+                .max()
+                .unwrap_or(0);
+            let session_env = server.sessions[s].env.clone();
+            let pane = server.new_pane(cwd, &session_env, env);
+            let window = server.new_window(index, name, pane);
+            let id = format!("@{}", window.id);
+            server.sessions[s].windows.push(window);
+            Ok(id)
+        })
+    }
+
+    fn rename_window(&self, target: &str, new_name: &str) -> Result<(), Error> {
+        self.run("rename-window", |server| {
+            let (s, w, _) = server.resolve(target)?;
+            server.sessions[s].windows[w].name = new_name.to_string();
+            Ok(())
+        })
+    }
+
+    fn kill_window(&self, target: &str) -> Result<(), Error> {
+        self.run("kill-window", |server| {
+            let (s, w, _) = server.resolve(target)?;
+            server.remove_window(s, w);
+            Ok(())
+        })
+    }
+
+    fn kill_pane(&self, target: &str) -> Result<(), Error> {
+        self.run("kill-pane", |server| {
+            let (s, w, p) = server.resolve(target)?;
+            let window = &mut server.sessions[s].windows[w];
+            window.panes.remove(p);
+            if window.panes.is_empty() {
+                server.remove_window(s, w);
+            } else if window.active >= p {
+                window.active = window.active.saturating_sub(1);
+            }
+            Ok(())
+        })
+    }
+
+    fn move_window(&self, window: &str, session: &str) -> Result<(), Error> {
+        self.run("move-window", |server| {
+            let (s, w, _) = server.resolve(window)?;
+            let to = server.sessions[server.session(session)?].id;
+            let mut window = server.remove_window(s, w);
+
+            // The source session may be gone by now, so look the target up again
+            let to = server.sessions.iter_mut().find(|s| s.id == to).unwrap();
+            window.index = to.windows.iter().map(|w| w.index + 1).max().unwrap_or(0);
+            to.windows.push(window);
+            Ok(())
+        })
+    }
+
+    fn swap_window(&self, source: &str, target: &str) -> Result<(), Error> {
+        self.run("swap-window", |server| {
+            let (s1, w1, _) = server.resolve(source)?;
+            let (s2, w2, _) = server.resolve(target)?;
+            let mut first = server.sessions[s1].windows[w1].clone();
+            let mut second = server.sessions[s2].windows[w2].clone();
+            // Windows trade places, but each place keeps its index
+            std::mem::swap(&mut first.index, &mut second.index);
+            server.sessions[s1].windows[w1] = second;
+            server.sessions[s2].windows[w2] = first;
+            Ok(())
+        })
+    }
+
+    fn swap_pane(&self, source: &str, target: &str) -> Result<(), Error> {
+        self.run("swap-pane", |server| {
+            let (s1, w1, p1) = server.resolve(source)?;
+            let (s2, w2, p2) = server.resolve(target)?;
+            let first = server.sessions[s1].windows[w1].panes[p1].clone();
+            let second = std::mem::replace(&mut server.sessions[s2].windows[w2].panes[p2], first);
+            server.sessions[s1].windows[w1].panes[p1] = second;
+            Ok(())
+        })
+    }
+
+    fn select_window(&self, target: &str) -> Result<(), Error> {
+        self.run("select-window", |server| {
+            let (s, w, _) = server.resolve(target)?;
+            server.sessions[s].active = w;
+            Ok(())
+        })
+    }
+
+    fn select_pane(&self, target: &str) -> Result<(), Error> {
+        self.run("select-pane", |server| {
+            let (s, w, p) = server.resolve(target)?;
+            server.sessions[s].windows[w].active = p;
+            Ok(())
+        })
+    }
+
+    fn split_window(
+        &self,
+        target: &str,
+        _direction: SplitDirection,
+        cwd: &str,
+        env: &[(&str, &str)],
+    ) -> Result<String, Error> {
+        self.run("split-window", |server| {
+            let (s, w, p) = server.resolve(target)?;
+
+            let session_env = server.sessions[s].env.clone();
+            let pane = server.new_pane(cwd, &session_env, env);
+            let id = pane.id;
+            // New panes are placed right after the one that was split
+            server.sessions[s].windows[w].panes.insert(p + 1, pane);
+            Ok(format!("%{id}"))
+        })
+    }
+
+    fn select_layout(&self, target: &str, layout: &str) -> Result<(), Error> {
+        self.run("select-layout", |server| {
+            let (s, w, _) = server.resolve(target)?;
+            let window = &mut server.sessions[s].windows[w];
+
+            const PRESETS: [&str; 5] = [
+                "even-horizontal",
+                "even-vertical",
+                "main-horizontal",
+                "main-vertical",
+                "tiled",
+            ];
+            if !PRESETS.contains(&layout) {
+                let valid = layout::parse_layout(layout)
+                    .is_ok_and(|cell| cell.panes().len() == window.panes.len());
+                if !valid {
+                    return Err(format!("invalid layout: {layout}"));
+                }
+            }
+
+            window.layout = Some(layout.to_string());
+            Ok(())
+        })
+    }
+
+    fn window_size(&self, target: &str) -> Result<(u16, u16), Error> {
+        self.run("display-message", |server| {
+            let (s, w, _) = server.resolve(target)?;
+            let window = &server.sessions[s].windows[w];
+            Ok((window.width, window.height))
+        })
+    }
+
+    fn send_keys(&self, target: &str, keys: &[&str]) -> Result<(), Error> {
+        self.run("send-keys", |server| {
+            let (s, w, p) = server.resolve(target)?;
+            server.sessions[s].windows[w].panes[p]
+                .keys
+                .extend(keys.iter().map(|k| k.to_string()));
+            Ok(())
+        })
+    }
+
+    fn capture_pane(&self, target: &str) -> Result<String, Error> {
+        self.run("capture-pane", |server| {
+            let (s, w, p) = server.resolve(target)?;
+            Ok(server.sessions[s].windows[w].panes[p].screen())
+        })
+    }
+}
+
+/// Preset called `name` that starts in `/tmp`, with nothing to it but `windows`. Anything else
+/// can be filled in with `Preset { .., ..preset(name, windows) }`.
+pub fn preset(name: &str, windows: Vec<Window>) -> Preset {
+    Preset {
+        name: name.to_string(),
+        cwd: "/tmp".to_string(),
+        env: Env::new(),
+        hooks: Hooks::default(),
+        params: vec![],
+        running: false,
+        source: None,
+        windows,
+        origin: None,
+    }
+}
+
+/// Window called `name` that starts in `/tmp`
+pub fn window(name: &str, layout: LayoutNode) -> Window {
+    Window {
+        name: name.to_string(),
+        cwd: "/tmp".to_string(),
+        env: Env::new(),
+        layout,
+    }
+}
+
+/// Pane in `cwd` running `command`, weighted `weight` against its siblings
+pub fn pane(cwd: &str, command: Option<&str>, weight: u32) -> LayoutNode {
+    LayoutNode::Pane {
+        cwd: cwd.to_string(),
+        command: command.map(str::to_string),
+        env: Env::new(),
+        size: Size::Weight(weight),
+    }
+}
+
+/// Split of `children` in `direction`, weighted `weight` against its siblings
+pub fn split(direction: SplitDirection, children: Vec<LayoutNode>, weight: u32) -> LayoutNode {
+    LayoutNode::Split {
+        direction,
+        children,
+        size: Size::Weight(weight),
+    }
+}
@@ -0,0 +1,209 @@
+use std::process::{Command, Stdio};
+
+use crate::{Error, Preset, TmuxBackend, expand_session};
+
+/// Kills the `session` spawned from `preset`, then runs its `on_kill` hook. The preset has to
+/// be filled in the way it was for that session, as [`Session::preset`](crate::Session::preset)
+/// does.
+pub fn kill_preset(tmux: &dyn TmuxBackend, session: &str, preset: &Preset) -> Result<(), Error> {
+    tmux.kill_session(session)?;
+    let Some(command) = &preset.hooks.on_kill else {
+        return Ok(());
+    };
+
+    // The session is gone either way, so anything that stops the hook is the hook failing
+    let preset = &expand_session(preset).map_err(|e| Error::Hook {
+        hook: "on_kill".to_string(),
+        message: e.to_string(),
+    })?;
+    run_hook("on_kill", command, preset)
+}
+
+/// Runs the `hook` called `name` on the host with `sh`, in the cwd and with the environment
+/// of the already expanded `preset`
+pub(crate) fn run_hook(name: &str, command: &str, preset: &Preset) -> Result<(), Error> {
+    let hook_error = |message: String| Error::Hook {
+        hook: name.to_string(),
+        message,
+    };
+
+    // Whatever the hook prints would end up all over the TUI, so it's only kept for errors
+    let output = Command::new("sh")
+        .args(["-c", command])
+        .current_dir(&preset.cwd)
+        .envs(&preset.env)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| hook_error(e.to_string()))?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(hook_error(match stderr.trim().lines().last() {
+        Some(line) => line.to_string(),
+        None => output.status.to_string(),
+    }))
+}
+
+/// Hands the hooks that tmux has to run over to it with `set-hook`
+pub(crate) fn set_hooks(tmux: &dyn TmuxBackend, preset: &Preset) -> Result<(), Error> {
+    let hooks = &preset.hooks;
+    if hooks.on_attach.is_none() && hooks.on_detach.is_none() {
+        return Ok(());
+    }
+
+    // Sessions can be renamed, so refer to it by id from within the hooks
+    let session = tmux
+        .list_sessions()?
+        .into_iter()
+        .find(|s| s.name == preset.name)
+        .ok_or_else(|| Error::SessionNotFound(preset.name.clone()))?;
+
+    let run_shell = |command: &str| {
+        // run-shell has no -c in older versions of tmux, so change directories in the shell
+        let command = format!("cd {} && {command}", sh_quote(&preset.cwd));
+        format!(
+            "run-shell -b -t {} {}",
+            tmux_quote(&session.id),
+            tmux_quote(&command)
+        )
+    };
+
+    if let Some(command) = &hooks.on_attach {
+        // The hook removes itself, so that it only runs on the first attach
+        let command = format!(
+            "{} ; set-hook -u -t {} client-attached",
+            run_shell(command),
+            tmux_quote(&session.id)
+        );
+        tmux.set_hook(&session.id, "client-attached", &command)?;
+    }
+    if let Some(command) = &hooks.on_detach {
+        tmux.set_hook(&session.id, "client-detached", &run_shell(command))?;
+    }
+    Ok(())
+}
+
+/// Quotes `s` as a single shell word
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Quotes `s` as a single argument of a tmux command, which run-shell then also expands
+/// formats like `#{session_name}` in
+fn tmux_quote(s: &str) -> String {
+    format!("'{}'", s.replace('#', "##").replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{self, FakeBackend, pane, window};
+    use crate::{Hooks, spawn_preset};
+
+    fn preset(hooks: Hooks) -> Preset {
+        Preset {
+            env: [("GREETING".to_string(), "hi".to_string())].into(),
+            hooks,
+            ..fake::preset("project", vec![window("main", pane("/tmp", None, 1))])
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let tmux = FakeBackend::new();
+        let preset = preset(Hooks {
+            on_create: Some("test \"$GREETING\" = hi && test \"$PWD\" = /tmp".to_string()),
+            on_attach: Some("echo '#1' >> log".to_string()),
+            on_detach: Some("true".to_string()),
+            on_kill: Some("echo 'gone' >&2; exit 3".to_string()),
+        });
+        spawn_preset(&tmux, &preset).unwrap();
+
+        let session = tmux.session("project").unwrap();
+        let id = format!("${}", session.id);
+        assert_eq!(
+            session.hooks["client-attached"],
+            format!(
+                "run-shell -b -t '{id}' 'cd '\\''/tmp'\\'' && echo '\\''##1'\\'' >> log' ; \
+                 set-hook -u -t '{id}' client-attached"
+            )
+        );
+        assert_eq!(
+            session.hooks["client-detached"],
+            format!("run-shell -b -t '{id}' 'cd '\\''/tmp'\\'' && true'")
+        );
+
+        // The session is gone either way
+        assert!(matches!(
+            kill_preset(&tmux, "project", &preset),
+            Err(Error::Hook { hook, message }) if hook == "on_kill" && message == "gone"
+        ));
+        assert!(tmux.session("project").is_none());
+    }
+
+    #[test]
+    fn test_failing_on_create() {
+        let tmux = FakeBackend::new();
+        let preset = preset(Hooks {
+            on_create: Some("exit 1".to_string()),
+            ..Hooks::default()
+        });
+
+        assert!(matches!(
+            spawn_preset(&tmux, &preset),
+            Err(Error::Hook { hook, message }) if hook == "on_create" && message.contains('1')
+        ));
+        assert!(tmux.session("project").is_none());
+    }
+
+    #[test]
+    fn test_on_create_existing_session() {
+        let tmux = FakeBackend::new();
+        let marker = std::env::temp_dir().join(format!("muffin-on-create-{}", std::process::id()));
+        let preset = preset(Hooks {
+            on_create: Some(format!("touch {}", sh_quote(&marker.to_string_lossy()))),
+            ..Hooks::default()
+        });
+        tmux.create_session("project", None, &[]).unwrap();
+
+        assert!(matches!(
+            spawn_preset(&tmux, &preset),
+            Err(Error::DuplicateSession(name)) if name == "project"
+        ));
+        assert!(!marker.exists());
+        assert!(tmux.session("project").is_some());
+    }
+
+    #[test]
+    fn test_kill_with_unset_variables() {
+        let tmux = FakeBackend::new();
+        let marker = std::env::temp_dir().join(format!("muffin-on-kill-{}", std::process::id()));
+        let mut preset = preset(Hooks {
+            on_kill: Some(format!("touch {}", sh_quote(&marker.to_string_lossy()))),
+            ..Hooks::default()
+        });
+        tmux.create_session("project", None, &[]).unwrap();
+
+        // Only the session's own cwd and env matter to the hook
+        preset.windows[0].env = [("DATA".to_string(), "$MUFFIN_UNSET/data".to_string())].into();
+        kill_preset(&tmux, "project", &preset).unwrap();
+        assert!(tmux.session("project").is_none());
+        assert!(marker.exists());
+        let _ = std::fs::remove_file(&marker);
+
+        // Those still have to expand, but they don't keep the session alive
+        tmux.create_session("project", None, &[]).unwrap();
+        preset
+            .env
+            .insert("DATA".to_string(), "$MUFFIN_UNSET/data".to_string());
+        assert!(matches!(
+            kill_preset(&tmux, "project", &preset),
+            Err(Error::Hook { hook, message })
+                if hook == "on_kill" && message.contains("`$MUFFIN_UNSET` is not set")
+        ));
+        assert!(tmux.session("project").is_none());
+        assert!(!marker.exists());
+    }
+}
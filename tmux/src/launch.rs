@@ -0,0 +1,161 @@
+use crate::{Error, Preset, TmuxBackend, env_overrides, expand_preset, fill_layout};
+
+/// Spawns `preset` unless a session with its name is running already, so that launching a
+/// preset twice just leads back to the first one. Returns whether it spawned anything.
+pub fn ensure_preset(tmux: &dyn TmuxBackend, preset: &Preset) -> Result<bool, Error> {
+    if is_running(tmux, &preset.name)? {
+        return Ok(false);
+    }
+    crate::spawn_preset(tmux, preset)?;
+    Ok(true)
+}
+
+/// What [`reconcile_preset`] added to a running session
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Reconciled {
+    /// Names of the windows that were missing entirely
+    pub windows: Vec<String>,
+    /// How many panes were missing from windows that did exist
+    pub panes: usize,
+}
+
+/// Brings the running session of `preset` back in line with it, by creating the windows and
+/// panes that are missing from it. Anything that is there already keeps running, although the
+/// panes of a window that got new ones are rearranged to fit the preset's layout. Windows the
+/// preset doesn't know about are left alone.
+///
+/// If the session isn't running at all, this spawns it like [`ensure_preset`] would.
+pub fn reconcile_preset(tmux: &dyn TmuxBackend, preset: &Preset) -> Result<Reconciled, Error> {
+    if ensure_preset(tmux, preset)? {
+        return Ok(Reconciled {
+            windows: preset.windows.iter().map(|w| w.name.clone()).collect(),
+            panes: 0,
+        });
+    }
+
+    let preset = &expand_preset(preset)?;
+    let live = tmux.list_windows(&preset.name)?;
+    let mut reconciled = Reconciled::default();
+    for window in &preset.windows {
+        match live.iter().find(|w| w.name == window.name) {
+            Some(info) => {
+                let existing = tmux
+                    .list_panes(&info.id)?
+                    .into_iter()
+                    .map(|pane| pane.id)
+                    .collect();
+                reconciled.panes += fill_layout(tmux, &info.id, window, existing, &preset.env)
+                    .map_err(|(_, e)| e)?;
+            }
+            None => {
+                let env = window.layout.first_env().unwrap_or(&window.env);
+                let target = tmux.new_window(
+                    &preset.name,
+                    &window.name,
+                    window.layout.first_cwd(),
+                    &env_overrides(env, &preset.env),
+                )?;
+                crate::apply_layout(tmux, &target, window, &preset.env).map_err(|(_, e)| e)?;
+                reconciled.windows.push(window.name.clone());
+            }
+        }
+    }
+    Ok(reconciled)
+}
+
+fn is_running(tmux: &dyn TmuxBackend, name: &str) -> Result<bool, Error> {
+    match tmux.list_sessions() {
+        Ok(sessions) => Ok(sessions.iter().any(|s| s.name == name)),
+        // The server exits along with its last session
+        Err(Error::NoServer) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{self, FakeBackend, split, window};
+    use crate::{LayoutNode, SplitDirection};
+
+    fn pane(command: &str) -> LayoutNode {
+        fake::pane("/tmp", Some(command), 1)
+    }
+
+    fn preset() -> Preset {
+        fake::preset(
+            "project",
+            vec![
+                window(
+                    "editor",
+                    split(
+                        SplitDirection::Horizontal,
+                        vec![pane("nvim"), pane("lazygit"), pane("cargo test")],
+                        1,
+                    ),
+                ),
+                window("logs", pane("tail -f log")),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_ensure_preset() {
+        let tmux = FakeBackend::new();
+        let preset = preset();
+        assert!(ensure_preset(&tmux, &preset).unwrap());
+        tmux.send_keys("project:editor.0", &["q"]).unwrap();
+
+        // The running session is left as it is
+        assert!(!ensure_preset(&tmux, &preset).unwrap());
+        let session = tmux.session("project").unwrap();
+        assert_eq!(session.windows.len(), 2);
+        assert_eq!(session.windows[0].panes[0].keys, ["nvim", "Enter", "q"]);
+    }
+
+    #[test]
+    fn test_reconcile_preset() {
+        let tmux = FakeBackend::new();
+        let preset = preset();
+        spawn_with_drift(&tmux, &preset);
+
+        let reconciled = reconcile_preset(&tmux, &preset).unwrap();
+        assert_eq!(
+            reconciled,
+            Reconciled {
+                windows: vec!["logs".to_string()],
+                panes: 1,
+            }
+        );
+
+        let session = tmux.session("project").unwrap();
+        let names: Vec<&str> = session.windows.iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(names, ["editor", "scratch", "logs"]);
+        let editor = &session.windows[0];
+        assert_eq!(editor.panes.len(), preset.windows[0].layout.panes().len());
+        // Panes that were there already don't get their commands again
+        assert_eq!(editor.panes[0].keys, ["nvim", "Enter"]);
+        assert_eq!(editor.panes[1].keys, ["lazygit", "Enter"]);
+        assert_eq!(editor.panes[2].keys, ["cargo test", "Enter"]);
+        assert_eq!(session.windows[2].panes[0].keys, ["tail -f log", "Enter"]);
+
+        // Once everything is there, there's nothing left to do
+        assert_eq!(
+            reconcile_preset(&tmux, &preset).unwrap(),
+            Reconciled::default()
+        );
+    }
+
+    /// Spawns `preset`, then loses its `logs` window and the last pane of its `editor`, and
+    /// opens a window of its own
+    fn spawn_with_drift(tmux: &FakeBackend, preset: &Preset) {
+        let mut drifted = preset.clone();
+        drifted.windows.truncate(1);
+        let LayoutNode::Split { children, .. } = &mut drifted.windows[0].layout else {
+            unreachable!()
+        };
+        children.pop();
+        crate::spawn_preset(tmux, &drifted).unwrap();
+        tmux.new_window("project", "scratch", "/tmp", &[]).unwrap();
+    }
+}
@@ -0,0 +1,425 @@
+use std::fmt;
+
+use crate::{Error, LayoutNode, Size, SplitDirection};
+
+/// Renders `node` as a tmux layout string for a `width`x`height` window, ready to be handed
+/// to `select-layout`.
+///
+/// tmux hands the window's panes out to the layout's cells in order, so the panes have to
+/// be created in the same order [`LayoutNode::panes`] returns them.
+pub fn layout_string(node: &LayoutNode, width: u16, height: u16) -> String {
+    let cell = Cell::from_node(node, width, height, 0, 0, &mut 0);
+    cell.to_layout_string()
+}
+
+/// One cell of a tmux layout, as found in `#{window_layout}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Cell {
+    pub width: u16,
+    pub height: u16,
+    pub x: u16,
+    pub y: u16,
+    pub content: Content,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Content {
+    /// Pane id, without the leading `%`
+    Pane(usize),
+    Split(SplitDirection, Vec<Cell>),
+}
+
+impl Cell {
+    fn from_node(
+        node: &LayoutNode,
+        width: u16,
+        height: u16,
+        x: u16,
+        y: u16,
+        next_pane: &mut usize,
+    ) -> Cell {
+        let content = match node {
+            // A split with a single child is just that child
+            LayoutNode::Split { children, .. } if children.len() == 1 => {
+                return Cell::from_node(&children[0], width, height, x, y, next_pane);
+            }
+            LayoutNode::Split {
+                direction,
+                children,
+                ..
+            } => {
+                let extent = match direction {
+                    SplitDirection::Horizontal => width,
+                    SplitDirection::Vertical => height,
+                };
+
+                // Neighbouring panes are separated by a one cell wide border
+                let borders = (children.len() as u16).saturating_sub(1);
+                let available = extent.saturating_sub(borders);
+                let sizes: Vec<Size> = children.iter().map(LayoutNode::size).collect();
+                let sizes = resolve_sizes(&sizes, available);
+
+                let mut offset = 0;
+                let cells = children
+                    .iter()
+                    .zip(sizes)
+                    .map(|(child, size)| {
+                        let cell = match direction {
+                            SplitDirection::Horizontal => {
+                                Cell::from_node(child, size, height, x + offset, y, next_pane)
+                            }
+                            SplitDirection::Vertical => {
+                                Cell::from_node(child, width, size, x, y + offset, next_pane)
+                            }
+                        };
+                        offset += size + 1;
+                        cell
+                    })
+                    .collect();
+                Content::Split(*direction, cells)
+            }
+            LayoutNode::Pane { .. } => {
+                *next_pane += 1;
+                Content::Pane(*next_pane - 1)
+            }
+        };
+
+        Cell {
+            width,
+            height,
+            x,
+            y,
+            content,
+        }
+    }
+
+    /// Pane cells, in the order tmux assigns a window's panes to them
+    pub fn panes(&self) -> Vec<&Cell> {
+        match &self.content {
+            Content::Pane(_) => vec![self],
+            Content::Split(_, children) => children.iter().flat_map(Cell::panes).collect(),
+        }
+    }
+
+    /// Renumbers panes in order, e.g. to match the actual ids of a window's panes
+    pub fn set_pane_ids(&mut self, ids: &mut impl Iterator<Item = usize>) {
+        match &mut self.content {
+            Content::Pane(id) => *id = ids.next().unwrap_or(*id),
+            Content::Split(_, children) => {
+                for child in children {
+                    child.set_pane_ids(ids);
+                }
+            }
+        }
+    }
+
+    pub fn to_layout_string(&self) -> String {
+        let body = self.to_string();
+        format!("{:04x},{body}", checksum(&body))
+    }
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{},{},{}", self.width, self.height, self.x, self.y)?;
+        match &self.content {
+            Content::Pane(id) => write!(f, ",{id}"),
+            Content::Split(direction, children) => {
+                let (open, close) = match direction {
+                    SplitDirection::Horizontal => ('{', '}'),
+                    SplitDirection::Vertical => ('[', ']'),
+                };
+                write!(f, "{open}")?;
+                for (i, child) in children.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{child}")?;
+                }
+                write!(f, "{close}")
+            }
+        }
+    }
+}
+
+/// Parses a layout string like the ones in `#{window_layout}`
+pub(crate) fn parse_layout(layout: &str) -> Result<Cell, Error> {
+    let invalid = || Error::Parse(format!("invalid layout `{layout}`"));
+
+    let (checksum_str, body) = layout.trim().split_once(',').ok_or_else(invalid)?;
+    if u16::from_str_radix(checksum_str, 16) != Ok(checksum(body)) {
+        return Err(invalid());
+    }
+
+    let mut parser = LayoutParser {
+        input: body.as_bytes(),
+        pos: 0,
+    };
+    let cell = parser.cell().ok_or_else(invalid)?;
+    if parser.pos != body.len() {
+        return Err(invalid());
+    }
+    Ok(cell)
+}
+
+struct LayoutParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl LayoutParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> Option<()> {
+        (self.peek()? == byte).then(|| self.pos += 1)
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Option<T> {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.input[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    /// `WxH,X,Y` followed by either `,ID`, `{cells}` or `[cells]`
+    fn cell(&mut self) -> Option<Cell> {
+        let width = self.number()?;
+        self.eat(b'x')?;
+        let height = self.number()?;
+        self.eat(b',')?;
+        let x = self.number()?;
+        self.eat(b',')?;
+        let y = self.number()?;
+
+        let content = match self.peek()? {
+            b',' => {
+                self.pos += 1;
+                Content::Pane(self.number()?)
+            }
+            open @ (b'{' | b'[') => {
+                self.pos += 1;
+                let (direction, close) = if open == b'{' {
+                    (SplitDirection::Horizontal, b'}')
+                } else {
+                    (SplitDirection::Vertical, b']')
+                };
+                let mut children = vec![self.cell()?];
+                while self.eat(b',').is_some() {
+                    children.push(self.cell()?);
+                }
+                self.eat(close)?;
+                Content::Split(direction, children)
+            }
+            _ => return None,
+        };
+
+        Some(Cell {
+            width,
+            height,
+            x,
+            y,
+            content,
+        })
+    }
+}
+
+/// Turns the sizes of a split's children into cell counts adding up to `available`.
+///
+/// Fixed sizes are handed out first, then percentages of the whole split, and whatever is left
+/// goes to weighted children. If that doesn't add up, e.g. because percentages leave room but
+/// nobody is weighted, everything is scaled to fit.
+fn resolve_sizes(sizes: &[Size], available: u16) -> Vec<u16> {
+    // Work in thousandths of a cell, so that rounding only happens once, in `distribute`
+    const SCALE: u64 = 1000;
+    let available = available as u64 * SCALE;
+
+    let fixed = |size: &Size| match *size {
+        Size::Cells(cells) => cells as u64 * SCALE,
+        Size::Percent(percent) => available * percent as u64 / 100,
+        Size::Weight(_) => 0,
+    };
+    let taken: u64 = sizes.iter().map(fixed).sum();
+    let left = available.saturating_sub(taken);
+    let total_weight: u64 = sizes
+        .iter()
+        .map(|size| match *size {
+            Size::Weight(weight) => weight as u64,
+            _ => 0,
+        })
+        .sum();
+
+    let targets: Vec<u64> = sizes
+        .iter()
+        .map(|size| match *size {
+            Size::Weight(weight) if total_weight > 0 => left * weight as u64 / total_weight,
+            _ => fixed(size),
+        })
+        .collect();
+
+    // Keep the weights passed on to `distribute` within range
+    let largest = targets.iter().copied().max().unwrap_or(0);
+    let divisor = largest / u32::MAX as u64 + 1;
+    let weights: Vec<u32> = targets.iter().map(|&t| (t / divisor) as u32).collect();
+    distribute(&weights, (available / SCALE) as u16)
+}
+
+/// Splits `total` cells proportionally to `weights`, rounding so that the sizes always
+/// add up to `total` and no cell ends up empty if it can be helped
+fn distribute(weights: &[u32], total: u16) -> Vec<u16> {
+    let weights: Vec<u32> = if weights.iter().all(|&w| w == 0) {
+        vec![1; weights.len()]
+    } else {
+        weights.to_vec()
+    };
+    let sum: u64 = weights.iter().map(|&w| w as u64).sum();
+
+    let mut sizes = Vec::with_capacity(weights.len());
+    let (mut acc, mut prev) = (0u64, 0u64);
+    for weight in weights {
+        acc += weight as u64;
+        let end = (acc * total as u64 + sum / 2) / sum;
+        sizes.push((end - prev) as u16);
+        prev = end;
+    }
+
+    // tmux refuses layouts with zero sized panes, so steal a cell from the largest one
+    while let Some(empty) = sizes.iter().position(|&s| s == 0) {
+        let largest = (0..sizes.len()).max_by_key(|&i| sizes[i]).unwrap();
+        if sizes[largest] <= 1 {
+            break;
+        }
+        sizes[largest] -= 1;
+        sizes[empty] = 1;
+    }
+    sizes
+}
+
+/// Same checksum tmux prefixes its own layout strings with
+fn checksum(layout: &str) -> u16 {
+    layout.bytes().fold(0u16, |csum, b| {
+        let csum = (csum >> 1) | ((csum & 1) << 15);
+        csum.wrapping_add(b as u16)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Straight out of `tmux display -p '#{window_layout}'`
+    const NESTED: &str = "d67e,80x24,0,0{40x24,0,0,0,39x24,41,0[39x12,41,0,1,39x11,41,13,2]}";
+
+    fn pane(size: Size) -> LayoutNode {
+        LayoutNode::Pane {
+            cwd: String::new(),
+            command: None,
+            env: Default::default(),
+            size,
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        let (csum, body) = NESTED.split_once(',').unwrap();
+        assert_eq!(format!("{:04x}", checksum(body)), csum);
+    }
+
+    #[test]
+    fn test_layout_string() {
+        let layout = LayoutNode::Split {
+            direction: SplitDirection::Horizontal,
+            children: vec![
+                pane(Size::Weight(1)),
+                LayoutNode::Split {
+                    direction: SplitDirection::Vertical,
+                    children: vec![pane(Size::Percent(50)), pane(Size::Percent(50))],
+                    size: Size::Weight(1),
+                },
+            ],
+            size: Size::Weight(1),
+        };
+
+        assert_eq!(layout_string(&layout, 80, 24), NESTED);
+    }
+
+    #[test]
+    fn test_layout_string_single_pane() {
+        assert!(layout_string(&pane(Size::default()), 80, 24).ends_with(",80x24,0,0,0"));
+    }
+
+    #[test]
+    fn test_layout_string_empty_split() {
+        // Nothing to arrange, but nothing to trip over either
+        let layout = LayoutNode::Split {
+            direction: SplitDirection::Vertical,
+            children: vec![],
+            size: Size::default(),
+        };
+        assert!(layout_string(&layout, 80, 24).ends_with(",80x24,0,0[]"));
+    }
+
+    #[test]
+    fn test_parse_layout() {
+        let cell = parse_layout(NESTED).unwrap();
+        assert_eq!(cell.panes().len(), 3);
+        assert_eq!(cell.to_layout_string(), NESTED);
+
+        let Content::Split(SplitDirection::Horizontal, children) = &cell.content else {
+            panic!("expected a horizontal split, got {cell:?}");
+        };
+        assert_eq!(children[1].width, 39);
+        assert!(matches!(
+            children[1].content,
+            Content::Split(SplitDirection::Vertical, _)
+        ));
+
+        assert!(parse_layout("b25d,80x24,0,0,0").is_ok());
+        assert!(parse_layout("80x24,0,0,0").is_err());
+        assert!(parse_layout("0000,80x24,0,0,0").is_err());
+        assert!(parse_layout("b25d,80x24,0,0{40x24,0,0,1").is_err());
+    }
+
+    #[test]
+    fn test_resolve_sizes() {
+        use Size::*;
+
+        assert_eq!(
+            resolve_sizes(&[Weight(1), Weight(2), Weight(1)], 78),
+            [20, 39, 19]
+        );
+        assert_eq!(
+            resolve_sizes(&[Cells(10), Weight(1), Weight(1)], 78),
+            [10, 34, 34]
+        );
+        assert_eq!(resolve_sizes(&[Percent(25), Weight(3)], 80), [20, 60]);
+        assert_eq!(
+            resolve_sizes(&[Percent(30), Cells(20), Weight(1)], 100),
+            [30, 20, 50]
+        );
+        // Nothing to fill the rest with, so the percentages are stretched
+        assert_eq!(resolve_sizes(&[Percent(20), Percent(20)], 79), [40, 39]);
+        // Too many fixed cells still leaves every pane visible
+        assert_eq!(
+            resolve_sizes(&[Cells(80), Cells(80), Weight(1)], 40),
+            [20, 19, 1]
+        );
+        assert_eq!(
+            resolve_sizes(&[Weight(u32::MAX), Weight(u32::MAX)], 10),
+            [5, 5]
+        );
+    }
+
+    #[test]
+    fn test_distribute() {
+        assert_eq!(distribute(&[25, 50, 25], 78), [20, 39, 19]);
+        assert_eq!(distribute(&[1, 2, 1], 78), [20, 39, 19]);
+        assert_eq!(distribute(&[0, 0], 9), [5, 4]);
+        assert_eq!(distribute(&[1, 98, 1], 10), [1, 8, 1]);
+    }
+}
@@ -1,280 +1,829 @@
-use regex::Regex;
-use std::process::Command;
-use std::os::unix::process::CommandExt;
-
-#[derive(Debug, Clone)]
+mod backend;
+mod error;
+mod export;
+pub mod fake;
+mod hooks;
+mod launch;
+mod layout;
+mod origin;
+mod params;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub use backend::{CommandBackend, Server, TmuxBackend};
+pub use error::{Error, SpawnStep};
+pub use export::export_session;
+pub use hooks::kill_preset;
+pub use launch::{Reconciled, ensure_preset, reconcile_preset};
+pub use layout::layout_string;
+pub use origin::Origin;
+pub use params::{Param, ParamError, placeholders};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Session {
+    /// Unique session id assigned by tmux, e.g. `$3`
+    pub id: String,
+    pub name: String,
+    pub windows: usize,
+    /// Number of clients attached to this session
+    pub attached: usize,
+    /// Unix timestamps, as reported by tmux
+    pub created: u64,
+    pub last_attached: Option<u64>,
+    pub activity: u64,
+    pub group: Option<String>,
+    pub path: String,
+    /// What muffin spawned the session from, if it did
+    pub origin: Option<Origin>,
+}
+
+impl Session {
+    pub fn is_attached(&self) -> bool {
+        self.attached > 0
+    }
+}
+
+/// A window of a running session
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct WindowInfo {
+    /// Unique window id assigned by tmux, e.g. `@2`
+    pub id: String,
+    pub index: usize,
     pub name: String,
-    pub windows: String,
+    /// Layout string describing how the window's panes are arranged
+    pub layout: String,
     pub active: bool,
+    pub panes: usize,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// A pane of a running window
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PaneInfo {
+    /// Unique pane id assigned by tmux, e.g. `%5`
+    pub id: String,
+    pub index: usize,
+    pub active: bool,
+    pub width: u16,
+    pub height: u16,
+    /// Name of the program running in the foreground, e.g. `nvim` or `bash`
+    pub command: String,
+    pub cwd: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum SplitDirection {
     Horizontal,
     Vertical,
 }
 
-#[derive(Clone, Debug)]
+/// How much of its parent split a pane or split takes up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Size {
+    /// Share of whatever is left after fixed and percentage sizes, relative to its siblings
+    Weight(u32),
+    /// Percentage of the parent split, from 1 to 100
+    Percent(u8),
+    /// Fixed number of terminal cells
+    Cells(u16),
+}
+
+impl Default for Size {
+    fn default() -> Self {
+        Size::Weight(1)
+    }
+}
+
+/// Environment variables by name
+pub type Env = BTreeMap<String, String>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "lowercase"))]
 pub enum LayoutNode {
     Pane {
         cwd: String,
         command: Option<String>,
-        size: u8,
+        /// Everything the pane's environment adds to tmux's global one, including what it
+        /// inherits from its window and session
+        env: Env,
+        size: Size,
     },
     Split {
         direction: SplitDirection,
         children: Vec<LayoutNode>,
-        size: u8,
+        size: Size,
     },
 }
 
 impl LayoutNode {
-    fn size(&self) -> u8 {
+    pub fn size(&self) -> Size {
         match self {
             LayoutNode::Pane { size, .. } => *size,
             LayoutNode::Split { size, .. } => *size,
         }
     }
+
+    /// Every pane in the tree, in the order tmux lays them out
+    pub fn panes(&self) -> Vec<&LayoutNode> {
+        match self {
+            LayoutNode::Pane { .. } => vec![self],
+            LayoutNode::Split { children, .. } => {
+                children.iter().flat_map(LayoutNode::panes).collect()
+            }
+        }
+    }
+
+    /// Working directory of the top-left-most pane, which is the one that ends up
+    /// occupying the pane this node is spawned from
+    fn first_cwd(&self) -> &str {
+        match self {
+            LayoutNode::Pane { cwd, .. } => cwd,
+            LayoutNode::Split { children, .. } => children
+                .first()
+                .map(LayoutNode::first_cwd)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Environment of the top-left-most pane, see [`first_cwd`](Self::first_cwd)
+    fn first_env(&self) -> Option<&Env> {
+        match self {
+            LayoutNode::Pane { env, .. } => Some(env),
+            LayoutNode::Split { children, .. } => children.first().and_then(LayoutNode::first_env),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Window {
     pub name: String,
     pub cwd: String,
+    /// Environment shared by the window's panes, including the session's
+    pub env: Env,
     pub layout: LayoutNode,
 }
 
-#[derive(Debug)]
+/// Shell commands to run when something happens to a session spawned from a preset
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Hooks {
+    /// Run by muffin once the session is created but before its windows are, and the session
+    /// is killed again if the command fails
+    pub on_create: Option<String>,
+    /// Run by tmux the first time a client attaches to the session
+    pub on_attach: Option<String>,
+    /// Run by tmux whenever a client detaches from the session
+    pub on_detach: Option<String>,
+    /// Run by muffin after it kills the session
+    pub on_kill: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Preset {
     pub name: String,
     pub cwd: String,
+    /// Environment shared by every pane in the session
+    pub env: Env,
+    pub hooks: Hooks,
+    /// What the preset asks for when it's launched, see [`Preset::with_params`]
+    pub params: Vec<Param>,
     pub running: bool,
+    /// File the preset was read from, if it came from one
+    pub source: Option<PathBuf>,
     pub windows: Vec<Window>,
+    /// What [`Preset::with_params`] filled the preset in from, which its session remembers
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub origin: Option<Origin>,
+}
+
+impl Preset {
+    /// Name of the first window that has the same name as one before it. Launching a preset
+    /// like that couldn't tell its windows apart from those of the running session.
+    pub fn duplicate_window(&self) -> Option<&str> {
+        self.windows
+            .iter()
+            .enumerate()
+            .find(|(i, window)| self.windows[..*i].iter().any(|w| w.name == window.name))
+            .map(|(_, window)| window.name.as_str())
+    }
 }
 
-pub fn spawn_preset(preset: &Preset) -> Result<(), String> {
-    create_session(&preset.name)?;
+/// Creates a session for `preset`, along with all of its windows and panes.
+///
+/// Once the session exists, any failure kills it again, so that there's never a half-built
+/// session left behind. The error then says which window or pane it was at.
+pub fn spawn_preset(tmux: &dyn TmuxBackend, preset: &Preset) -> Result<(), Error> {
+    let preset = &expand_preset(preset)?;
+
+    // new-session also creates the first pane, so it has to start where that pane wants to be
+    let first_layout = preset.windows.first().map(|w| &w.layout);
+    let first_cwd = first_layout.map_or(preset.cwd.as_str(), LayoutNode::first_cwd);
+    let first_env = first_layout
+        .and_then(LayoutNode::first_env)
+        .unwrap_or(&preset.env);
+    // Nothing was created if this fails, and the session it collides with isn't ours to kill
+    tmux.create_session(
+        &preset.name,
+        Some(first_cwd),
+        &env_overrides(first_env, &Env::new()),
+    )?;
+
+    // Only now that the session is ours, but before anything has started in it
+    if let Some(command) = &preset.hooks.on_create
+        && let Err(e) = hooks::run_hook("on_create", command, preset)
+    {
+        let _ = tmux.kill_session(&preset.name);
+        return Err(e);
+    }
+
+    build_session(tmux, preset, first_env).map_err(|(step, source)| {
+        let rolled_back = tmux.kill_session(&preset.name).is_ok();
+        Error::Spawn {
+            session: preset.name.clone(),
+            step,
+            source: Box::new(source),
+            rolled_back,
+        }
+    })
+}
+
+/// What went wrong while spawning, and where
+type StepResult<T> = Result<T, (SpawnStep, Error)>;
+
+/// Fills the freshly created session of `preset` with its windows, panes and hooks
+fn build_session(tmux: &dyn TmuxBackend, preset: &Preset, first_env: &Env) -> StepResult<()> {
+    // Presets that take no parameters are never filled in, but are just as much an origin
+    let origin = preset.origin.clone().unwrap_or_else(|| Origin {
+        preset: preset.name.clone(),
+        params: BTreeMap::new(),
+    });
+    origin
+        .set(tmux, &preset.name)
+        .map_err(|e| (SpawnStep::Session, e))?;
+
+    // new-session -e puts the first pane's environment into the session's, where every other
+    // pane would inherit it from, so take back whatever was only meant for that pane
+    for (key, value) in first_env {
+        let session_value = preset.env.get(key);
+        if session_value != Some(value) {
+            tmux.set_environment(&preset.name, key, session_value.map(String::as_str))
+                .map_err(|e| (SpawnStep::Session, e))?;
+        }
+    }
 
+    // Windows are targeted by id, since names can repeat and indices depend on `base-index`
     for (i, window_cfg) in preset.windows.iter().enumerate() {
+        let window_step = |e| (SpawnStep::Window(window_cfg.name.clone()), e);
         let window_target = if i == 0 {
             // Use the default window created by new-session
-            run_command(
-                "tmux",
-                &[
-                    "rename-window",
-                    "-t",
-                    &format!("{}:0", preset.name),
-                    &window_cfg.name,
-                ],
-            )?;
-            format!("{}:{}", preset.name, window_cfg.name)
+            let first = tmux
+                .list_windows(&preset.name)
+                .and_then(|windows| {
+                    let first = windows.into_iter().next();
+                    first.ok_or_else(|| Error::SessionNotFound(preset.name.clone()))
+                })
+                .map_err(window_step)?;
+            tmux.rename_window(&first.id, &window_cfg.name)
+                .map_err(window_step)?;
+            first.id
         } else {
-            // Create a new window and get its name/index
-            run_command(
-                "tmux",
-                &[
-                    "new-window",
-                    "-t",
-                    &preset.name,
-                    "-n",
-                    &window_cfg.name,
-                    "-P",
-                ],
-            )?
-            .trim()
-            .to_string();
-            format!("{}:{}", preset.name, window_cfg.name)
+            let env = window_cfg.layout.first_env().unwrap_or(&window_cfg.env);
+            tmux.new_window(
+                &preset.name,
+                &window_cfg.name,
+                window_cfg.layout.first_cwd(),
+                &env_overrides(env, &preset.env),
+            )
+            .map_err(window_step)?
         };
 
-        // Initial pane in a new window is always index 0
-        let initial_pane = format!("{}.0", window_target);
-        apply_layout_recursive(&initial_pane, &window_cfg.layout)?;
+        apply_layout(tmux, &window_target, window_cfg, &preset.env)?;
     }
 
-    Ok(())
+    hooks::set_hooks(tmux, preset).map_err(|e| (SpawnStep::Hooks, e))
 }
 
-fn apply_layout_recursive(pane_target: &str, node: &LayoutNode) -> Result<(), String> {
-    match node {
-        LayoutNode::Pane { cwd, command, .. } => {
-            run_command(
-                "tmux",
-                &[
-                    "send-keys",
-                    "-t",
-                    pane_target,
-                    &format!("cd {}", cwd),
-                    "Enter",
-                ],
-            )?;
-            // run command if provided
-            if let Some(cmd) = command {
-                run_command("tmux", &["send-keys", "-t", pane_target, cmd, "Enter"])?;
-            }
-            Ok(())
-        }
-        LayoutNode::Split {
-            direction,
-            children,
-            ..
-        } => {
-            let mut current_pane_target = pane_target.to_string();
-            let mut remaining_pct: f32 = children.iter().map(|c| c.size() as f32).sum();
-
-            for (i, child) in children.iter().enumerate() {
-                // If it's the last child, we don't split anymore;
-                // it just occupies whatever is left in current_pane_target
-                if i == children.len() - 1 {
-                    apply_layout_recursive(&current_pane_target, child)?;
-                    break;
-                }
-
-                let child_pct = child.size() as f32;
-
-                // Warning: Borrowed from AI slop for math calculations
+/// Creates one pane per leaf of the window's layout, then arranges them in one go
+fn apply_layout(
+    tmux: &dyn TmuxBackend,
+    window_target: &str,
+    window: &Window,
+    session_env: &Env,
+) -> StepResult<()> {
+    // A new window has a single pane, which was started in the first pane's cwd
+    let first_pane = tmux
+        .list_panes(window_target)
+        .and_then(|panes| {
+            let first = panes.into_iter().next();
+            first.ok_or_else(|| Error::Parse(format!("window {window_target} has no panes")))
+        })
+        .map_err(|e| (SpawnStep::Window(window.name.clone()), e))?
+        .id;
+    fill_layout(
+        tmux,
+        window_target,
+        window,
+        vec![first_pane.clone()],
+        session_env,
+    )?;
 
-                // MATH CALCULATION:
-                // Tmux '-p' is the size of the NEW pane relative to the target.
-                // If child needs 20% of the current area, the NEW pane (the rest)
-                // needs to be 80% of the current target.
-                let split_p = (((remaining_pct - child_pct) / remaining_pct) * 100.0).round() as u8;
+    // Nothing runs in it yet, though
+    if let Some(LayoutNode::Pane {
+        command: Some(cmd), ..
+    }) = window.layout.panes().first()
+    {
+        tmux.send_keys(&first_pane, &[cmd, "Enter"])
+            .map_err(|e| (pane_step(window, 0), e))?;
+    }
+    Ok(())
+}
 
-                // Split the window.
-                // The 'old' index stays as the 'child', the 'new' index is the 'rest'.
-                let (sess, win, new_index) =
-                    split_window(&current_pane_target, split_p, direction)?;
+/// Creates the panes of the window's layout that come after its `existing` panes, then
+/// arranges all of them in one go. Only the new panes get their commands. Returns how many
+/// were created.
+fn fill_layout(
+    tmux: &dyn TmuxBackend,
+    window_target: &str,
+    window: &Window,
+    existing: Vec<String>,
+    session_env: &Env,
+) -> StepResult<usize> {
+    let layout = &window.layout;
+    let panes = layout.panes();
+    let existing_count = existing.len();
+    if existing_count >= panes.len() {
+        return Ok(0);
+    }
+    let window_step = |e| (SpawnStep::Window(window.name.clone()), e);
 
-                let next_pane_target = format!("{}:{}.{}", sess, win, new_index);
+    let mut pane_targets = existing;
+    for (i, pane) in panes.iter().enumerate().skip(existing_count) {
+        let LayoutNode::Pane { cwd, env, .. } = pane else {
+            unreachable!()
+        };
+        // Splitting the last pane keeps the window's panes in the same order as the layout's
+        let last = pane_targets.last().unwrap();
+        let new_pane = tmux
+            .split_window(
+                last,
+                SplitDirection::Vertical,
+                cwd,
+                &env_overrides(env, session_env),
+            )
+            .map_err(|e| (pane_step(window, i), e))?;
+        // Spread panes out between splits, so that the next one has room
+        tmux.select_layout(window_target, "tiled")
+            .map_err(window_step)?;
+        pane_targets.push(new_pane);
+    }
 
-                // Recurse into the child we just "carved out"
-                apply_layout_recursive(&current_pane_target, child)?;
+    if panes.len() > 1 {
+        let (width, height) = tmux.window_size(window_target).map_err(window_step)?;
+        tmux.select_layout(window_target, &layout_string(layout, width, height))
+            .map_err(window_step)?;
+    }
 
-                // Move our focus to the newly created pane for the next iteration
-                current_pane_target = next_pane_target;
-                remaining_pct -= child_pct;
-            }
-            Ok(())
+    for (i, (pane, target)) in panes.iter().zip(&pane_targets).enumerate() {
+        if i < existing_count {
+            continue;
+        }
+        // The pane was already started in its cwd, so only run command if provided
+        if let LayoutNode::Pane {
+            command: Some(cmd), ..
+        } = pane
+        {
+            tmux.send_keys(target, &[cmd, "Enter"])
+                .map_err(|e| (pane_step(window, i), e))?;
         }
     }
+    Ok(panes.len() - existing_count)
 }
 
-pub fn split_window(
-    target: &str,
-    size: u8,
-    direction: &SplitDirection,
-) -> Result<(String, String, usize), String> {
-    let direction_flag = match direction {
-        SplitDirection::Horizontal => "-h",
-        SplitDirection::Vertical => "-v",
-    };
-    let output = run_command(
-        "tmux",
-        &[
-            "split-window",
-            "-t",
-            target,
-            direction_flag,
-            "-p",
-            size.to_string().as_str(),
-            "-P",
-        ],
-    )?;
-    let (session_name, rest) = output.trim().split_once(":").ok_or("Unexpected output")?;
-    let (window_name, pane_index) = rest.split_once(".").ok_or("Unexpected output")?;
-    Ok((
-        session_name.into(),
-        window_name.into(),
-        pane_index.parse::<usize>().map_err(|_| "Parsing error")?,
-    ))
+/// The `index`th pane of `window`, counting from 0
+fn pane_step(window: &Window, index: usize) -> SpawnStep {
+    SpawnStep::Pane {
+        window: window.name.clone(),
+        pane: index + 1,
+    }
 }
 
-pub fn list_sessions() -> Result<Vec<Session>, String> {
-    let output = run_command("tmux", &["list-sessions"])?;
-
-    let active_regex = Regex::new(r"\(attached\)$").unwrap();
-    let windows_regex = Regex::new(r"^(.+?): (\d+).*").unwrap();
-
-    let sessions = output
-        .lines()
-        .map(|line| {
-            let captures = windows_regex.captures(line).unwrap();
+/// Variables of `env` that a pane doesn't already inherit from its session's `session_env`
+fn env_overrides<'a>(env: &'a Env, session_env: &Env) -> Vec<(&'a str, &'a str)> {
+    env.iter()
+        .filter(|(key, value)| session_env.get(*key) != Some(*value))
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect()
+}
 
-            Session {
-                name: captures[1].to_string(),
-                windows: captures[2].to_string(),
-                active: active_regex.is_match(line),
+/// `preset` with `~` and environment variables like `$WORKSPACE` expanded in its directories,
+/// commands and variables, which presets keep the way they were written. `$$` stands for a
+/// `$` that isn't expanded.
+///
+/// The parser only lets through values that expand, but parameters are filled in later on, so
+/// a variable that isn't set can still turn up here.
+fn expand_preset(preset: &Preset) -> Result<Preset, Error> {
+    fn expand_layout(node: &mut LayoutNode, window: &str, pane: &mut usize) -> Result<(), Error> {
+        match node {
+            LayoutNode::Pane {
+                cwd, command, env, ..
+            } => {
+                *pane += 1;
+                let owner = format!("pane {pane} of window `{window}`");
+                expand(cwd, || format!("the cwd of {owner}"))?;
+                if let Some(command) = command {
+                    expand(command, || format!("the command of {owner}"))?;
+                }
+                expand_env(env, &owner)
             }
-        })
-        .collect::<Vec<Session>>();
+            LayoutNode::Split { children, .. } => children
+                .iter_mut()
+                .try_for_each(|child| expand_layout(child, window, pane)),
+        }
+    }
 
-    Ok(sessions)
+    let mut preset = expand_session(preset)?;
+    for window in &mut preset.windows {
+        let owner = format!("window `{}`", window.name);
+        expand(&mut window.cwd, || format!("the cwd of {owner}"))?;
+        expand_env(&mut window.env, &owner)?;
+        expand_layout(&mut window.layout, &window.name, &mut 0)?;
+    }
+    Ok(preset)
 }
 
-pub fn switch_session(target: &str) -> Result<(), String> {
-    run_command("tmux", &["switch-client", "-t", target]).map(|_| ())
+/// `preset` with only its own cwd and variables expanded like [`expand_preset`] does, which is
+/// all that its hooks run with
+fn expand_session(preset: &Preset) -> Result<Preset, Error> {
+    let mut preset = preset.clone();
+    expand(&mut preset.cwd, || "the session's cwd".to_string())?;
+    expand_env(&mut preset.env, "the session")?;
+    Ok(preset)
 }
 
-pub fn create_session(new_name: &str) -> Result<(), String> {
-    if new_name.is_empty() {
-        run_command("tmux", &["new-session", "-d"]).map(|_| ())
-    } else {
-        run_command("tmux", &["new-session", "-s", new_name, "-d"]).map(|_| ())
+fn expand_env(env: &mut Env, owner: &str) -> Result<(), Error> {
+    for (key, value) in env {
+        expand(value, || format!("`{key}` of {owner}'s env"))?;
     }
+    Ok(())
 }
 
-pub fn rename_session(target: &str, new_name: &str) -> Result<(), String> {
-    run_command("tmux", &["rename-session", "-t", target, new_name]).map(|_| ())
+/// Expands `text` in place, or says what went wrong with the `field` it came from
+fn expand(text: &mut String, field: impl FnOnce() -> String) -> Result<(), Error> {
+    let expanded = shellexpand::full(text).map_err(|e| Error::Expand {
+        field: field(),
+        message: match e.cause {
+            std::env::VarError::NotPresent => format!("`${}` is not set", e.var_name),
+            std::env::VarError::NotUnicode(_) => format!("`${}` is not valid unicode", e.var_name),
+        },
+    })?;
+    *text = expanded.into_owned();
+    Ok(())
 }
 
-pub fn delete_session(target: &str) -> Result<(), String> {
-    run_command("tmux", &["kill-session", "-t", target]).map(|_| ())
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{FakeBackend, pane, preset, split, window};
 
-pub fn attach_session(target: &str) -> Result<(), String> {
-    let error = Command::new("tmux")
-        .args(&["attach-session", "-t", target])
-        .exec(); // This replaces the current process
+    #[test]
+    fn test_list_session() {
+        let tmux = FakeBackend::new();
+        assert!(matches!(tmux.list_sessions(), Err(Error::NoServer)));
 
-    // If exec() returns, it means it failed.
-    // The error will be an std::io::Error, which we convert to String.
-    Err(format!("Failed to exec tmux attach-session: {}", error))
-}
+        tmux.create_session("first", None, &[]).unwrap();
+        tmux.create_session("", None, &[]).unwrap();
 
-pub fn detach_session() -> Result<(), String> {
-    run_command("tmux", &["detach"]).map(|_| ())
-}
+        let sessions = tmux.list_sessions().unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].name, "first");
+        assert_eq!(sessions[0].windows, 1);
+        assert!(!sessions[0].is_attached());
+    }
 
-fn run_command(command: &str, args: &[&str]) -> Result<String, String> {
-    let output = Command::new(command)
-        .args(args)
-        .output()
-        .map_err(|_| "Error running command")?;
+    #[test]
+    fn test_create_delete_session() {
+        let tmux = FakeBackend::new();
+
+        tmux.create_session("test_session", None, &[]).unwrap();
+        assert!(matches!(
+            tmux.create_session("test_session", None, &[]),
+            Err(Error::DuplicateSession(name)) if name == "test_session"
+        ));
+
+        // Names are checked for duplicates the way tmux stores them
+        tmux.create_session("other", None, &[]).unwrap();
+        assert!(matches!(
+            tmux.rename_session("other", "test:session"),
+            Err(Error::DuplicateSession(name)) if name == "test_session"
+        ));
+        tmux.rename_session("test_session", "test.session").unwrap();
+        assert!(tmux.session("test_session").is_some());
+
+        tmux.kill_session("test_session").unwrap();
+        assert!(tmux.session("test_session").is_none());
+        assert!(matches!(
+            tmux.kill_session("test_session"),
+            Err(Error::SessionNotFound(name)) if name == "test_session"
+        ));
+    }
+
+    #[test]
+    fn test_window_and_pane_actions() {
+        let tmux = FakeBackend::new();
+        tmux.create_session("work", Some("/tmp"), &[]).unwrap();
+        tmux.new_window("work", "logs", "/tmp", &[]).unwrap();
+        tmux.new_window("work", "build", "/tmp", &[]).unwrap();
+        tmux.create_session("play", Some("/tmp"), &[]).unwrap();
+        let names = |session: &str| -> Vec<String> {
+            let windows = tmux.list_windows(session).unwrap();
+            windows
+                .iter()
+                .map(|w| format!("{}:{}", w.index, w.name))
+                .collect()
+        };
 
-    if output.status.code().is_none_or(|code| code != 0) {
-        return Err(String::from_utf8(output.stderr).map_err(|_| "Error decoding output")?);
+        tmux.swap_window("work:logs", "work:build").unwrap();
+        assert_eq!(names("work"), ["0:shell", "1:build", "2:logs"]);
+
+        tmux.select_window("work:build").unwrap();
+        let windows = tmux.list_windows("work").unwrap();
+        assert!(windows[1].active && !windows[0].active);
+
+        let first = tmux.split_window("work:build", SplitDirection::Vertical, "/a", &[]);
+        let second = tmux.split_window("work:build", SplitDirection::Vertical, "/b", &[]);
+        tmux.swap_pane(&first.unwrap(), &second.unwrap()).unwrap();
+        tmux.select_pane("work:build.2").unwrap();
+        let panes = tmux.list_panes("work:build").unwrap();
+        let cwds: Vec<&str> = panes.iter().map(|p| p.cwd.as_str()).collect();
+        assert_eq!(cwds, ["/tmp", "/a", "/b"]);
+        assert!(panes[2].active);
+        // Targets without a pane land on the active one
+        tmux.kill_pane("work:build").unwrap();
+        assert_eq!(tmux.list_panes("work:build").unwrap().len(), 2);
+
+        tmux.move_window("work:logs", "play").unwrap();
+        assert_eq!(names("work"), ["0:shell", "1:build"]);
+        assert_eq!(names("play"), ["0:shell", "1:logs"]);
+
+        // Sessions go away along with their last window
+        tmux.kill_window("play:0").unwrap();
+        tmux.move_window("play:logs", "work").unwrap();
+        assert!(tmux.session("play").is_none());
+        assert_eq!(names("work"), ["0:shell", "1:build", "2:logs"]);
     }
 
-    Ok(String::from_utf8(output.stdout).map_err(|_| "Error decoding output")?)
-}
+    #[test]
+    fn test_spawn_preset() {
+        let tmux = FakeBackend::new();
+        let preset = preset(
+            "project",
+            vec![
+                window(
+                    "editor",
+                    split(
+                        SplitDirection::Horizontal,
+                        vec![
+                            pane("/tmp", Some("nvim"), 50),
+                            split(
+                                SplitDirection::Vertical,
+                                vec![
+                                    pane("/tmp/git", Some("git status"), 50),
+                                    pane("/tmp/my project", None, 50),
+                                ],
+                                50,
+                            ),
+                        ],
+                        100,
+                    ),
+                ),
+                window("logs", pane("/tmp/logs", Some("tail -f log"), 100)),
+            ],
+        );
+
+        spawn_preset(&tmux, &preset).unwrap();
+
+        let session = tmux.session("project").unwrap();
+        let names: Vec<&str> = session.windows.iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(names, ["editor", "logs"]);
+
+        let panes = &session.windows[0].panes;
+        assert_eq!(panes.len(), 3);
+        assert_eq!(panes[0].keys, ["nvim", "Enter"]);
+        assert_eq!(panes[1].keys, ["git status", "Enter"]);
+        assert!(panes[2].keys.is_empty());
+        assert_eq!(session.windows[1].panes[0].keys, ["tail -f log", "Enter"]);
+
+        assert_eq!(
+            session.windows[0].layout,
+            Some(layout_string(&preset.windows[0].layout, 80, 24))
+        );
+        // Single pane windows have nothing to arrange
+        assert_eq!(session.windows[1].layout, None);
+
+        let cwds: Vec<&str> = panes.iter().map(|p| p.cwd.as_str()).collect();
+        assert_eq!(cwds, ["/tmp", "/tmp/git", "/tmp/my project"]);
+        assert_eq!(session.windows[1].panes[0].cwd, "/tmp/logs");
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_spawn_repeated_window_names() {
+        let tmux = FakeBackend::new();
+        let preset = preset(
+            "project",
+            vec![
+                window("shell", pane("/tmp", Some("htop"), 100)),
+                window(
+                    "shell",
+                    split(
+                        SplitDirection::Horizontal,
+                        vec![pane("/tmp", Some("ls"), 50), pane("/tmp", None, 50)],
+                        100,
+                    ),
+                ),
+            ],
+        );
+        // A session whose name starts the same isn't mistaken for it
+        tmux.create_session("project-old", None, &[]).unwrap();
+
+        spawn_preset(&tmux, &preset).unwrap();
+
+        // Each window got its own layout, even though `project:shell` is ambiguous
+        let session = tmux.session("project").unwrap();
+        assert_eq!(session.windows[0].panes.len(), 1);
+        assert_eq!(session.windows[0].panes[0].keys, ["htop", "Enter"]);
+        assert_eq!(session.windows[1].panes.len(), 2);
+        assert_eq!(session.windows[1].panes[0].keys, ["ls", "Enter"]);
+        assert_eq!(tmux.session("project-old").unwrap().windows.len(), 1);
+    }
 
     #[test]
-    fn test_list_session() {
-        let x = list_sessions();
+    fn test_spawn_preset_rollback() {
+        let tmux = FakeBackend::new();
+        tmux.create_session("other", None, &[]).unwrap();
+        let before = tmux.sessions();
+
+        let mut preset = preset(
+            "project",
+            vec![
+                window("shell", pane("/tmp", None, 100)),
+                window(
+                    "editor",
+                    split(
+                        SplitDirection::Horizontal,
+                        vec![pane("/tmp", Some("nvim"), 50), pane("/tmp", None, 50)],
+                        1,
+                    ),
+                ),
+            ],
+        );
+
+        tmux.fail("split-window", "no space for new pane");
+        let error = spawn_preset(&tmux, &preset).unwrap_err();
+        assert!(matches!(
+            &error,
+            Error::Spawn { step: SpawnStep::Pane { window, pane: 2 }, rolled_back: true, .. }
+                if window == "editor"
+        ));
+        assert_eq!(
+            error.to_string(),
+            "couldn't spawn `project`, pane 2 of window `editor` failed: \
+             tmux split-window failed: no space for new pane"
+        );
+        // Nothing of it is left
+        assert_eq!(tmux.sessions(), before);
+
+        // Whatever a name collides with stays where it is
+        preset.name = "other".to_string();
+        assert!(matches!(
+            spawn_preset(&tmux, &preset),
+            Err(Error::DuplicateSession(name)) if name == "other"
+        ));
+        assert_eq!(tmux.sessions(), before);
+    }
 
-        println!("{:#?}", x);
+    #[test]
+    fn test_spawn_preset_env() {
+        let env = |vars: &[(&str, &str)]| -> Env {
+            vars.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let pane = |command: &str, vars: &[(&str, &str)]| LayoutNode::Pane {
+            cwd: "/tmp".to_string(),
+            command: Some(command.to_string()),
+            env: env(vars),
+            size: Size::default(),
+        };
+
+        let session_env = [("RUST_LOG", "info")];
+        let window_env = [("RUST_LOG", "info"), ("KUBECONFIG", "/tmp/kube")];
+        let preset = Preset {
+            env: env(&session_env),
+            ..preset(
+                "project",
+                vec![
+                    Window {
+                        env: env(&window_env),
+                        ..window(
+                            "server",
+                            split(
+                                SplitDirection::Horizontal,
+                                vec![
+                                    pane(
+                                        "cargo run",
+                                        &[("RUST_LOG", "debug"), ("KUBECONFIG", "/tmp/kube")],
+                                    ),
+                                    pane("kubectl get pods", &window_env),
+                                ],
+                                1,
+                            ),
+                        )
+                    },
+                    Window {
+                        env: env(&session_env),
+                        ..window("shell", pane("ls", &session_env))
+                    },
+                ],
+            )
+        };
+
+        let tmux = FakeBackend::new();
+        spawn_preset(&tmux, &preset).unwrap();
+        let session = tmux.session("project").unwrap();
+
+        // Only the session's own variables are left for panes created later on
+        assert_eq!(session.env, env(&session_env));
+        let panes = &session.windows[0].panes;
+        assert_eq!(
+            panes[0].env,
+            env(&[("RUST_LOG", "debug"), ("KUBECONFIG", "/tmp/kube")])
+        );
+        assert_eq!(panes[1].env, env(&window_env));
+        assert_eq!(session.windows[1].panes[0].env, env(&session_env));
     }
 
     #[test]
-    fn test_create_delete_session() {
-        let x = create_session("test_session");
-        println!("{:#?}", x);
+    fn test_spawn_preset_expansion() {
+        let home = std::env::var("HOME").unwrap();
+        // Panes carry what they inherit, like presets from the parser do
+        let env: Env = [("DATA".to_string(), "${HOME}/data".to_string())].into();
+        let layout = LayoutNode::Pane {
+            cwd: "~/src".to_string(),
+            command: Some("echo $$HOME".to_string()),
+            env: env.clone(),
+            size: Size::default(),
+        };
+        let window = Window {
+            cwd: "$HOME/src".to_string(),
+            env: env.clone(),
+            ..window("main", layout)
+        };
+        let preset = Preset {
+            cwd: "~".to_string(),
+            env,
+            ..preset("project", vec![window])
+        };
 
-        let x = delete_session("test_session");
-        println!("{:#?}", x);
+        let tmux = FakeBackend::new();
+        spawn_preset(&tmux, &preset).unwrap();
+        let session = tmux.session("project").unwrap();
+        assert_eq!(session.env["DATA"], format!("{home}/data"));
+        let pane = &session.windows[0].panes[0];
+        assert_eq!(pane.cwd, format!("{home}/src"));
+        assert_eq!(pane.env["DATA"], format!("{home}/data"));
+        assert!(pane.keys.contains(&"echo $HOME".to_string()));
+    }
+
+    #[test]
+    fn test_spawn_preset_unset_variable() {
+        let tmux = FakeBackend::new();
+        let layout = split(
+            SplitDirection::Vertical,
+            vec![
+                pane("/tmp", None, 1),
+                // Like a parameter filled in with `--set`
+                pane("/tmp", Some("serve $MUFFIN_UNSET_VARIABLE"), 1),
+            ],
+            1,
+        );
+        let preset = preset("project", vec![window("main", layout)]);
+
+        let err = spawn_preset(&tmux, &preset).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "couldn't expand the command of pane 2 of window `main`: \
+             `$MUFFIN_UNSET_VARIABLE` is not set"
+        );
+        assert!(tmux.session("project").is_none());
     }
 }
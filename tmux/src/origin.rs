@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use crate::{Error, Preset, Session, TmuxBackend};
+
+/// Session user options that [`spawn_preset`](crate::spawn_preset) records the origin in
+pub(crate) const PRESET_OPTION: &str = "@muffin-preset";
+pub(crate) const PARAMS_OPTION: &str = "@muffin-params";
+
+/// Which preset a session was spawned from, and the values its parameters were given. The
+/// session's name alone doesn't tell, since it may have come from a `{{param}}` or been
+/// renamed since.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Origin {
+    /// Name of the preset as it's written, placeholders and all
+    pub preset: String,
+    /// Value of every parameter, defaults included
+    pub params: BTreeMap<String, String>,
+}
+
+impl Origin {
+    /// Reads an origin back from the values of its session options, which are empty when the
+    /// session has none
+    pub(crate) fn from_options(preset: &str, params: &str) -> Option<Self> {
+        if preset.is_empty() {
+            return None;
+        }
+        let params = params
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (decode(key), decode(value)))
+            .collect();
+        Some(Origin {
+            preset: decode(preset),
+            params,
+        })
+    }
+
+    /// Records the origin on `session`
+    pub(crate) fn set(&self, tmux: &dyn TmuxBackend, session: &str) -> Result<(), Error> {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+            .collect();
+        tmux.set_option(session, PRESET_OPTION, &encode(&self.preset))?;
+        tmux.set_option(session, PARAMS_OPTION, &params.join("&"))
+    }
+}
+
+impl Session {
+    /// Name of the preset the session was spawned from. Sessions that don't say are taken to
+    /// be from the preset with their name.
+    pub fn preset_name(&self) -> &str {
+        match &self.origin {
+            Some(origin) => &origin.preset,
+            None => &self.name,
+        }
+    }
+
+    /// The preset among `presets` that the session was spawned from, with its parameters
+    /// filled in like they were back then. There's none if the preset is gone, or its
+    /// parameters changed so much that those values don't fit it anymore.
+    pub fn preset(&self, presets: &BTreeMap<String, Preset>) -> Option<Preset> {
+        let preset = presets.get(self.preset_name())?;
+        let params = self.origin.as_ref().map(|o| o.params.clone());
+        preset.with_params(&params.unwrap_or_default()).ok()
+    }
+}
+
+/// Percent-encodes whatever would get in the way of the option values being read back, which
+/// are fields of a tab-separated line split up further with `&` and `=`. A trailing `;` would
+/// also end the tmux command.
+fn encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '%' | '&' | '=' | ';' | '\t' | '\n' | '\r' => {
+                out.push_str(&format!("%{:02X}", c as u8))
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn decode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('%') {
+        out.push_str(&rest[..start]);
+        let byte = rest
+            .get(start + 1..start + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match byte {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[start + 3..];
+            }
+            None => {
+                out.push('%');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{FakeBackend, pane, preset, window};
+    use crate::{Param, spawn_preset};
+
+    #[test]
+    fn test_encoding() {
+        let tricky = "a=b&c;\td%20\n100%";
+        assert!(!encode(tricky).contains(['=', '&', ';', '\t', '\n']));
+        assert_eq!(decode(&encode(tricky)), tricky);
+
+        let origin = Origin::from_options("proj-{{dir}}", "dir=%2Fa%3Db&x=").unwrap();
+        assert_eq!(origin.preset, "proj-{{dir}}");
+        assert_eq!(origin.params["dir"], "/a=b");
+        assert_eq!(origin.params["x"], "");
+        assert_eq!(Origin::from_options("", ""), None);
+    }
+
+    #[test]
+    fn test_spawned_session_origin() {
+        let tmux = FakeBackend::new();
+        let raw = crate::Preset {
+            cwd: "{{dir}}".to_string(),
+            params: vec![Param {
+                name: "dir".to_string(),
+                default: None,
+            }],
+            ..preset(
+                "proj-{{dir}}",
+                vec![window("main", pane("{{dir}}", None, 1))],
+            )
+        };
+        let presets = BTreeMap::from([(raw.name.clone(), raw.clone())]);
+        let values = BTreeMap::from([("dir".to_string(), "/tmp".to_string())]);
+        spawn_preset(&tmux, &raw.with_params(&values).unwrap()).unwrap();
+        tmux.create_session("other", None, &[]).unwrap();
+
+        // The record survives renames, which the name wouldn't
+        tmux.rename_session("proj-/tmp", "renamed").unwrap();
+        let sessions = tmux.list_sessions().unwrap();
+        assert_eq!(sessions[0].preset_name(), "proj-{{dir}}");
+        let resolved = sessions[0].preset(&presets).unwrap();
+        assert_eq!(resolved.cwd, "/tmp");
+        assert_eq!(resolved.name, "proj-/tmp");
+
+        assert_eq!(sessions[1].preset_name(), "other");
+        assert_eq!(sessions[1].preset(&presets), None);
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::{LayoutNode, Origin, Preset};
+
+/// Something a preset asks for when it's launched, like `param "dir" default="~/src"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Param {
+    pub name: String,
+    /// Used when no value is given
+    pub default: Option<String>,
+}
+
+/// Why the values given for a preset's parameters don't fit it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamError {
+    /// There's no value for a parameter that has no default
+    Missing(String),
+    /// There's a value for a parameter the preset doesn't have
+    Unknown(String),
+    /// The values give two windows the same name
+    DuplicateWindow(String),
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Missing(name) => write!(f, "no value given for parameter `{name}`"),
+            ParamError::Unknown(name) => write!(f, "the preset has no parameter `{name}`"),
+            ParamError::DuplicateWindow(name) => {
+                write!(f, "two windows would be called `{name}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+impl Preset {
+    /// The preset with every `{{param}}` in its names, directories and commands replaced by
+    /// its value in `values`, or by its default if it has none there
+    pub fn with_params(&self, values: &BTreeMap<String, String>) -> Result<Preset, ParamError> {
+        if let Some(name) = values
+            .keys()
+            .find(|name| !self.params.iter().any(|p| p.name == **name))
+        {
+            return Err(ParamError::Unknown(name.clone()));
+        }
+
+        let mut resolved = BTreeMap::new();
+        for param in &self.params {
+            let value = values
+                .get(&param.name)
+                .or(param.default.as_ref())
+                .ok_or_else(|| ParamError::Missing(param.name.clone()))?;
+            resolved.insert(param.name.as_str(), value.as_str());
+        }
+
+        let mut preset = self.clone();
+        preset.origin = Some(Origin {
+            preset: self.name.clone(),
+            params: resolved
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        });
+        interpolate(&mut preset.name, &resolved);
+        interpolate(&mut preset.cwd, &resolved);
+        for window in &mut preset.windows {
+            interpolate(&mut window.name, &resolved);
+            interpolate(&mut window.cwd, &resolved);
+            interpolate_layout(&mut window.layout, &resolved);
+        }
+        if let Some(name) = preset.duplicate_window() {
+            return Err(ParamError::DuplicateWindow(name.to_string()));
+        }
+        Ok(preset)
+    }
+}
+
+fn interpolate_layout(node: &mut LayoutNode, values: &BTreeMap<&str, &str>) {
+    match node {
+        LayoutNode::Pane { cwd, command, .. } => {
+            interpolate(cwd, values);
+            if let Some(command) = command {
+                interpolate(command, values);
+            }
+        }
+        LayoutNode::Split { children, .. } => {
+            for child in children {
+                interpolate_layout(child, values);
+            }
+        }
+    }
+}
+
+/// The names of the parameters that `text` refers to, in order. `{{}}` refers to nothing and
+/// stays as it is.
+pub fn placeholders(text: &str) -> Vec<&str> {
+    let mut names = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        let name = rest[start + 2..start + end].trim();
+        if !name.is_empty() {
+            names.push(name);
+        }
+        rest = &rest[start + end + 2..];
+    }
+    names
+}
+
+/// Replaces the placeholders in `text` that `values` has a value for
+fn interpolate(text: &mut String, values: &BTreeMap<&str, &str>) {
+    if !text.contains("{{") {
+        return;
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start..start + end + 2];
+        out.push_str(&rest[..start]);
+        match values.get(placeholder[2..placeholder.len() - 2].trim()) {
+            Some(value) => out.push_str(value),
+            None => out.push_str(placeholder),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    *text = out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::{self, window};
+    use crate::{Env, Size, Window};
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(
+            placeholders("{{repo}}/{{ branch }}{{}} {{"),
+            ["repo", "branch"]
+        );
+        assert!(placeholders("echo }} {").is_empty());
+    }
+
+    #[test]
+    fn test_with_params() {
+        let layout = fake::pane(
+            "{{dir}}/{{ branch }}",
+            Some("git switch {{branch}} # {{other}}"),
+            1,
+        );
+        let preset = Preset {
+            cwd: "{{dir}}".to_string(),
+            params: vec![
+                Param {
+                    name: "dir".to_string(),
+                    default: Some("~/src".to_string()),
+                },
+                Param {
+                    name: "branch".to_string(),
+                    default: None,
+                },
+            ],
+            ..fake::preset(
+                "review-{{branch}}",
+                vec![Window {
+                    cwd: "{{dir}}".to_string(),
+                    ..window("{{branch}}", layout)
+                }],
+            )
+        };
+
+        let values = BTreeMap::from([("branch".to_string(), "fix-typo".to_string())]);
+        let resolved = preset.with_params(&values).unwrap();
+        assert_eq!(resolved.name, "review-fix-typo");
+        assert_eq!(resolved.cwd, "~/src");
+        assert_eq!(resolved.windows[0].name, "fix-typo");
+        assert_eq!(
+            resolved.windows[0].layout,
+            LayoutNode::Pane {
+                cwd: "~/src/fix-typo".to_string(),
+                command: Some("git switch fix-typo # {{other}}".to_string()),
+                env: Env::new(),
+                size: Size::default(),
+            }
+        );
+
+        assert_eq!(
+            preset.with_params(&BTreeMap::new()),
+            Err(ParamError::Missing("branch".to_string()))
+        );
+        let typo = BTreeMap::from([("brnach".to_string(), "main".to_string())]);
+        assert_eq!(
+            preset.with_params(&typo),
+            Err(ParamError::Unknown("brnach".to_string()))
+        );
+
+        // Windows only get their names once the values are in
+        let mut preset = preset;
+        preset.windows.push(Window {
+            name: "main".to_string(),
+            ..preset.windows[0].clone()
+        });
+        let main = BTreeMap::from([("branch".to_string(), "main".to_string())]);
+        assert_eq!(
+            preset.with_params(&main),
+            Err(ParamError::DuplicateWindow("main".to_string()))
+        );
+    }
+}